@@ -0,0 +1,143 @@
+/// HL7 code tables (value sets) for coded ID and IS values.
+///
+/// `tables.json` embeds the HL7-defined tables the dictionary references most
+/// (event type 0003, acknowledgment code 0008, observation result status
+/// 0085, …) together with the suggested values of the common user-defined
+/// tables (administrative sex 0001, patient class 0004, admission type 0007,
+/// abnormal flags 0078). Tables not embedded are not checked.
+///
+/// Sites add or replace tables with JSON files in the same format, listed
+/// under `[validation] user_tables`. A site table replaces the embedded table
+/// with the same number outright; later files win over earlier ones.
+use anyhow::Context;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CodeTable {
+    pub desc: String,
+    /// Code → description
+    pub values: HashMap<String, String>,
+}
+
+impl CodeTable {
+    pub fn contains(&self, code: &str) -> bool {
+        self.values.contains_key(code)
+    }
+}
+
+#[derive(Deserialize)]
+struct TableFile {
+    tables: HashMap<String, CodeTable>,
+}
+
+static TABLES: OnceLock<HashMap<String, CodeTable>> = OnceLock::new();
+
+fn embedded() -> HashMap<String, CodeTable> {
+    serde_json::from_str::<TableFile>(include_str!("assets/hl7/tables.json"))
+        .expect("Failed to parse embedded code tables")
+        .tables
+}
+
+fn tables() -> &'static HashMap<String, CodeTable> {
+    TABLES.get_or_init(embedded)
+}
+
+/// Code table by number (e.g. `"0001"`), site tables first
+pub fn get(table: &str) -> Option<&'static CodeTable> {
+    tables().get(table)
+}
+
+/// Read site-defined tables from JSON files, later files replacing earlier ones
+pub fn load_user_tables(paths: &[PathBuf]) -> anyhow::Result<HashMap<String, CodeTable>> {
+    let mut tables = HashMap::new();
+    for path in paths {
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("Cannot read {}", path.display()))?;
+        let file: TableFile = serde_json::from_str(&json)
+            .with_context(|| format!("Invalid code table file {}", path.display()))?;
+        tables.extend(file.tables);
+    }
+    Ok(tables)
+}
+
+/// Embedded tables with `user` tables replacing or adding to them
+fn merged(user: HashMap<String, CodeTable>) -> HashMap<String, CodeTable> {
+    let mut tables = embedded();
+    tables.extend(user);
+    tables
+}
+
+/// Load site-defined tables and make them the ones validation uses. Must run
+/// before the first lookup; returns the number of tables loaded.
+pub fn install_user_tables(paths: &[PathBuf]) -> anyhow::Result<usize> {
+    let user = load_user_tables(paths)?;
+    let count = user.len();
+    if count > 0 {
+        TABLES
+            .set(merged(user))
+            .map_err(|_| anyhow::anyhow!("Code tables are already in use"))?;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_embedded_tables() {
+        let sex = get("0001").unwrap();
+        assert_eq!(sex.desc, "Administrative Sex");
+        assert!(sex.contains("F"));
+        assert!(!sex.contains("X"));
+        assert!(get("0003").unwrap().contains("A01"));
+        assert!(get("0008").unwrap().contains("CA"));
+        assert!(get("0085").unwrap().contains("F"));
+        assert!(get("0104").unwrap().contains("2.5.1"));
+        assert!(get("9999").is_none());
+    }
+
+    #[test]
+    fn test_user_tables_replace_embedded() {
+        let dir = std::env::temp_dir().join(format!("hl7-forge-tables-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let first = dir.join("site.json");
+        let second = dir.join("lab.json");
+        std::fs::write(
+            &first,
+            r#"{"tables": {
+                "0001": {"desc": "Sex (site)", "values": {"M": "Male", "F": "Female", "X": "Non-binary"}},
+                "0099": {"desc": "VIP Indicator", "values": {"V": "VIP"}}
+            }}"#,
+        )
+        .unwrap();
+        std::fs::write(
+            &second,
+            r#"{"tables": {"0099": {"desc": "VIP Indicator", "values": {"Y": "Yes"}}}}"#,
+        )
+        .unwrap();
+
+        let user = load_user_tables(&[first, second]).unwrap();
+        assert_eq!(user.len(), 2);
+        let tables = merged(user);
+        let sex = &tables["0001"];
+        assert_eq!(sex.desc, "Sex (site)");
+        assert!(sex.contains("X"));
+        // Replaced outright, not merged value by value
+        assert!(!sex.contains("U"));
+        assert!(tables["0099"].contains("Y"));
+        assert!(!tables["0099"].contains("V"));
+        assert!(tables["0003"].contains("A01"));
+
+        let bad = dir.join("bad.json");
+        std::fs::write(&bad, "{").unwrap();
+        let err = load_user_tables(&[bad]).unwrap_err();
+        assert!(err.to_string().contains("bad.json"));
+        let err = load_user_tables(&[dir.join("missing.json")]).unwrap_err();
+        assert!(err.to_string().contains("missing.json"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -13,6 +13,9 @@ pub struct Config {
     pub logging: LoggingConfig,
     pub store: StoreConfig,
     pub mllp: MllpConfig,
+    pub outbound: OutboundConfig,
+    pub listeners: Vec<ListenerConfig>,
+    pub validation: ValidationConfig,
 }
 
 #[derive(Debug, Deserialize)]
@@ -36,7 +39,59 @@ pub struct LoggingConfig {
 #[serde(default)]
 pub struct StoreConfig {
     pub max_messages: usize,
+    /// Memory budget of the in-memory backend; the SQLite backend is bounded
+    /// by `max_messages` only
     pub max_memory_mb: usize,
+    pub backend: StoreBackend,
+    /// Database file used when `backend = "sqlite"`
+    pub sqlite_path: PathBuf,
+    pub retention: RetentionConfig,
+    pub parse_mode: ParseMode,
+    /// With `parse_mode = "lazy"`: how many parsed messages to keep for
+    /// repeated detail views (0 = parse on every request)
+    pub detail_cache_size: usize,
+    /// Flag repeats of a message seen from the same host within this many
+    /// seconds (0 = off)
+    pub duplicate_window_secs: u64,
+}
+
+/// Retention rules enforced by the background sweeper (`[store.retention]`).
+///
+/// Bookmarked messages are never removed. With no age or per-type limit set,
+/// the sweeper does not run.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RetentionConfig {
+    /// Delete messages received more than this many days ago
+    pub max_age_days: Option<u64>,
+    /// Keep at most this many messages per message type (newest win)
+    pub max_per_type: Option<usize>,
+    /// Never delete messages that carry at least one tag
+    pub keep_tagged: bool,
+    pub sweep_interval_secs: u64,
+}
+
+/// Where the message store keeps its data.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StoreBackend {
+    /// In-process ring buffer — fast, lost on restart
+    #[default]
+    Memory,
+    /// SQLite database file — survives restarts
+    Sqlite,
+}
+
+/// When segment and field detail is built for stored messages.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ParseMode {
+    /// Keep the full parsed tree for every message
+    #[default]
+    Eager,
+    /// Keep only the raw text and summary fields; parse the detail when a
+    /// message is opened or a field-path search needs it
+    Lazy,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -46,6 +101,134 @@ pub struct MllpConfig {
     pub read_timeout_secs: u64,
     pub write_timeout_secs: u64,
     pub max_connections: usize,
+    /// Charset for messages without a usable MSH-18, as an HL7 table 0211
+    /// value (`"8859/1"`) or encoding label (`"windows-1252"`); UTF-8 if unset
+    pub charset: Option<String>,
+    pub ack: AckConfig,
+}
+
+/// A single MLLP listener (`[[listeners]]`).
+///
+/// Unset limits fall back to the `[mllp]` section. When no listeners are
+/// configured, one listener on `server.mllp_port` is started.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ListenerConfig {
+    pub port: u16,
+    pub bind: String,
+    /// Display name recorded on every message received by this listener
+    pub label: Option<String>,
+    pub max_message_size_mb: Option<usize>,
+    pub read_timeout_secs: Option<u64>,
+    pub write_timeout_secs: Option<u64>,
+    pub max_connections: Option<usize>,
+    pub charset: Option<String>,
+    /// Serve MLLP over TLS (MLLP/S) when set
+    pub tls: Option<TlsConfig>,
+}
+
+/// TLS settings for a listener (`[listeners.tls]`).
+#[derive(Debug, Clone, Deserialize)]
+pub struct TlsConfig {
+    /// PEM certificate chain presented to clients
+    pub cert: PathBuf,
+    /// PEM private key for `cert`
+    pub key: PathBuf,
+    /// PEM CA bundle; when set, clients must present a certificate signed by it
+    pub client_ca: Option<PathBuf>,
+}
+
+/// Outbound MLLP client settings (`[outbound]`), used for message replay.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct OutboundConfig {
+    pub connect_timeout_secs: u64,
+    pub ack_timeout_secs: u64,
+    pub targets: Vec<OutboundTarget>,
+}
+
+/// A named replay destination (`[[outbound.targets]]`).
+#[derive(Debug, Clone, Deserialize)]
+pub struct OutboundTarget {
+    pub name: String,
+    pub host: String,
+    pub port: u16,
+}
+
+/// Validation settings (`[validation]`).
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ValidationConfig {
+    /// JSON files with site-defined code tables, in the same format as the
+    /// embedded `tables.json`; a table here replaces the embedded one with
+    /// the same number
+    pub user_tables: Vec<PathBuf>,
+}
+
+/// ACK behaviour profile for the MLLP listener (`[mllp.ack]`).
+///
+/// Rules are evaluated top to bottom; the first matching rule decides the
+/// response. Messages that match no rule are answered with `AA` immediately.
+/// Messages requesting enhanced mode (MSH-15/MSH-16) get the commit-level
+/// equivalent (`CA`/`CE`/`CR`) of the chosen code instead.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct AckConfig {
+    pub rules: Vec<AckRule>,
+    /// ACK code for messages flagged as duplicates; takes precedence over
+    /// the rules. Unset means duplicates are acknowledged like any other.
+    pub duplicate_code: Option<AckCode>,
+    /// Name of an `[[outbound.targets]]` entry that enhanced-mode application
+    /// ACKs are sent to. Unset means no application ACKs are sent.
+    pub application_ack_target: Option<String>,
+}
+
+/// A single `[[mllp.ack.rules]]` entry.
+///
+/// All filters are optional — a rule without filters matches every message.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AckRule {
+    /// Message type to match, e.g. `"ADT^A01"`; a trailing `*` matches a prefix (`"ADT^*"`)
+    pub message_type: Option<String>,
+    /// Exact MSH-4 Sending Facility to match
+    pub sending_facility: Option<String>,
+    /// Chance (0–100) that a matching message is handled by this rule; each
+    /// matching rule rolls separately, in order, until one applies
+    #[serde(deserialize_with = "deserialize_percentage")]
+    pub percentage: f64,
+    /// Acknowledgment code to send, or `"none"` to send no ACK at all
+    pub code: AckCode,
+    /// Delay before the response is written
+    pub delay_ms: u64,
+}
+
+/// Reject ACK rule percentages outside 0–100 when the config is loaded.
+fn deserialize_percentage<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<f64, D::Error> {
+    let value = f64::deserialize(deserializer)?;
+    if (0.0..=100.0).contains(&value) {
+        Ok(value)
+    } else {
+        Err(serde::de::Error::custom(format!(
+            "percentage must be between 0 and 100, got {}",
+            value
+        )))
+    }
+}
+
+/// Acknowledgment codes an [`AckRule`] can answer with (HL7 table 0008).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum AckCode {
+    AA,
+    AE,
+    AR,
+    CA,
+    CE,
+    CR,
+    #[serde(rename = "none", alias = "NONE")]
+    None,
 }
 
 // --- Defaults ---
@@ -76,6 +259,23 @@ impl Default for StoreConfig {
         Self {
             max_messages: 10_000,
             max_memory_mb: 512,
+            backend: StoreBackend::Memory,
+            sqlite_path: PathBuf::from("hl7-forge.db"),
+            retention: RetentionConfig::default(),
+            parse_mode: ParseMode::Eager,
+            detail_cache_size: 256,
+            duplicate_window_secs: 300,
+        }
+    }
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            max_age_days: None,
+            max_per_type: None,
+            keep_tagged: false,
+            sweep_interval_secs: 60,
         }
     }
 }
@@ -87,6 +287,46 @@ impl Default for MllpConfig {
             read_timeout_secs: 60,
             write_timeout_secs: 30,
             max_connections: 100,
+            charset: None,
+            ack: AckConfig::default(),
+        }
+    }
+}
+
+impl Default for ListenerConfig {
+    fn default() -> Self {
+        Self {
+            port: 2575,
+            bind: "0.0.0.0".to_string(),
+            label: None,
+            max_message_size_mb: None,
+            read_timeout_secs: None,
+            write_timeout_secs: None,
+            max_connections: None,
+            charset: None,
+            tls: None,
+        }
+    }
+}
+
+impl Default for OutboundConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout_secs: 10,
+            ack_timeout_secs: 30,
+            targets: Vec::new(),
+        }
+    }
+}
+
+impl Default for AckRule {
+    fn default() -> Self {
+        Self {
+            message_type: None,
+            sending_facility: None,
+            percentage: 100.0,
+            code: AckCode::AA,
+            delay_ms: 0,
         }
     }
 }
@@ -97,6 +337,27 @@ impl StoreConfig {
     pub fn max_memory_bytes(&self) -> usize {
         self.max_memory_mb * 1024 * 1024
     }
+
+    /// Duplicate detection window, or `None` when detection is off
+    pub fn duplicate_window(&self) -> Option<Duration> {
+        (self.duplicate_window_secs > 0).then(|| Duration::from_secs(self.duplicate_window_secs))
+    }
+}
+
+impl RetentionConfig {
+    /// True when at least one retention rule is configured
+    pub fn is_enabled(&self) -> bool {
+        self.max_age_days.is_some() || self.max_per_type.is_some()
+    }
+
+    pub fn max_age(&self) -> Option<Duration> {
+        self.max_age_days
+            .map(|days| Duration::from_secs(days * 24 * 60 * 60))
+    }
+
+    pub fn sweep_interval(&self) -> Duration {
+        Duration::from_secs(self.sweep_interval_secs.max(1))
+    }
 }
 
 impl MllpConfig {
@@ -113,9 +374,143 @@ impl MllpConfig {
     }
 }
 
+impl ListenerConfig {
+    pub fn bind_addr(&self) -> String {
+        format!("{}:{}", self.bind, self.port)
+    }
+
+    /// Label shown in the UI; defaults to the port number.
+    pub fn label(&self) -> String {
+        self.label
+            .clone()
+            .unwrap_or_else(|| format!("port {}", self.port))
+    }
+
+    /// Effective MLLP settings for this listener, falling back to `defaults`.
+    pub fn mllp_config(&self, defaults: &MllpConfig) -> MllpConfig {
+        MllpConfig {
+            max_message_size_mb: self
+                .max_message_size_mb
+                .unwrap_or(defaults.max_message_size_mb),
+            read_timeout_secs: self.read_timeout_secs.unwrap_or(defaults.read_timeout_secs),
+            write_timeout_secs: self
+                .write_timeout_secs
+                .unwrap_or(defaults.write_timeout_secs),
+            max_connections: self.max_connections.unwrap_or(defaults.max_connections),
+            charset: self.charset.clone().or_else(|| defaults.charset.clone()),
+            ack: defaults.ack.clone(),
+        }
+    }
+}
+
+impl OutboundConfig {
+    pub fn connect_timeout(&self) -> Duration {
+        Duration::from_secs(self.connect_timeout_secs)
+    }
+
+    pub fn ack_timeout(&self) -> Duration {
+        Duration::from_secs(self.ack_timeout_secs)
+    }
+
+    /// Look up a configured target by name.
+    pub fn target(&self, name: &str) -> Option<&OutboundTarget> {
+        self.targets.iter().find(|t| t.name == name)
+    }
+}
+
+impl OutboundTarget {
+    pub fn addr(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+}
+
+impl AckRule {
+    /// True when the message type and sending facility filters match.
+    /// The percentage is applied separately by the caller.
+    pub fn matches(&self, message_type: &str, sending_facility: &str) -> bool {
+        let type_ok = match self.message_type.as_deref() {
+            None => true,
+            Some(pattern) => match pattern.strip_suffix('*') {
+                Some(prefix) => message_type.starts_with(prefix),
+                None => message_type == pattern,
+            },
+        };
+        let facility_ok = self
+            .sending_facility
+            .as_deref()
+            .is_none_or(|f| f == sending_facility);
+        type_ok && facility_ok
+    }
+
+    pub fn delay(&self) -> Duration {
+        Duration::from_millis(self.delay_ms)
+    }
+}
+
+impl AckCode {
+    /// The MSA-1 code, or `None` when no ACK should be sent.
+    pub fn as_str(&self) -> Option<&'static str> {
+        match self {
+            AckCode::AA => Some("AA"),
+            AckCode::AE => Some("AE"),
+            AckCode::AR => Some("AR"),
+            AckCode::CA => Some("CA"),
+            AckCode::CE => Some("CE"),
+            AckCode::CR => Some("CR"),
+            AckCode::None => None,
+        }
+    }
+
+    /// The accept-level code for an enhanced-mode commit ACK
+    pub fn commit(self) -> AckCode {
+        match self {
+            AckCode::AA => AckCode::CA,
+            AckCode::AE => AckCode::CE,
+            AckCode::AR => AckCode::CR,
+            other => other,
+        }
+    }
+
+    /// The application-level code for an enhanced-mode application ACK
+    pub fn application(self) -> AckCode {
+        match self {
+            AckCode::CA => AckCode::AA,
+            AckCode::CE => AckCode::AE,
+            AckCode::CR => AckCode::AR,
+            other => other,
+        }
+    }
+}
+
 // --- Loading ---
 
 impl Config {
+    /// Listeners to start: the `[[listeners]]` entries, or a single default
+    /// listener on `server.mllp_port` when none are configured.
+    pub fn effective_listeners(&self) -> Vec<ListenerConfig> {
+        if self.listeners.is_empty() {
+            vec![ListenerConfig {
+                port: self.server.mllp_port,
+                ..Default::default()
+            }]
+        } else {
+            self.listeners.clone()
+        }
+    }
+
+    /// Apply the `MLLP_PORT` override. With `[[listeners]]` configured it
+    /// replaces the port of the first entry; the others keep theirs.
+    fn override_mllp_port(&mut self, port: u16) {
+        self.server.mllp_port = port;
+        if let Some(first) = self.listeners.first_mut() {
+            eprintln!(
+                "NOTE: MLLP_PORT={} replaces port {} of the first [[listeners]] entry",
+                port, first.port
+            );
+            first.port = port;
+        }
+    }
+
     /// Load configuration with priority: file → env vars → defaults.
     pub fn load() -> Self {
         let mut config = match find_config_path() {
@@ -149,7 +544,7 @@ impl Config {
         // Env-var overrides (highest priority)
         if let Ok(val) = std::env::var("MLLP_PORT") {
             if let Ok(port) = val.parse::<u16>() {
-                config.server.mllp_port = port;
+                config.override_mllp_port(port);
             }
         }
         if let Ok(val) = std::env::var("WEB_PORT") {
@@ -192,7 +587,28 @@ fn find_config_path() -> Option<PathBuf> {
 
 impl std::fmt::Display for Config {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        writeln!(f, "  MLLP port:          {}", self.server.mllp_port)?;
+        for listener in self.effective_listeners() {
+            let mllp = listener.mllp_config(&self.mllp);
+            let tls = match &listener.tls {
+                Some(tls) if tls.client_ca.is_some() => ", TLS + client cert",
+                Some(_) => ", TLS",
+                None => "",
+            };
+            let charset = mllp
+                .charset
+                .as_deref()
+                .map_or(String::new(), |c| format!(", default charset {}", c));
+            writeln!(
+                f,
+                "  MLLP listener:      {} on {} (max {} connections, {} MB{}{})",
+                listener.label(),
+                listener.bind_addr(),
+                mllp.max_connections,
+                mllp.max_message_size_mb,
+                tls,
+                charset
+            )?;
+        }
         writeln!(f, "  Web port:           {}", self.server.web_port)?;
         writeln!(
             f,
@@ -210,7 +626,47 @@ impl std::fmt::Display for Config {
             writeln!(f, "  File log:           disabled")?;
         }
         writeln!(f, "  Max messages:       {}", self.store.max_messages)?;
-        writeln!(f, "  Max memory:         {} MB", self.store.max_memory_mb)?;
+        match self.store.backend {
+            StoreBackend::Memory => {
+                writeln!(f, "  Max memory:         {} MB", self.store.max_memory_mb)?;
+                writeln!(f, "  Store backend:      memory")?
+            }
+            StoreBackend::Sqlite => writeln!(
+                f,
+                "  Store backend:      sqlite ({}, no memory cap)",
+                self.store.sqlite_path.display()
+            )?,
+        }
+        if self.store.parse_mode == ParseMode::Lazy {
+            writeln!(
+                f,
+                "  Parse mode:         lazy (detail cache {} messages)",
+                self.store.detail_cache_size
+            )?;
+        }
+        let retention = &self.store.retention;
+        if retention.is_enabled() {
+            let age = retention
+                .max_age_days
+                .map_or("none".to_string(), |d| format!("{} days", d));
+            let per_type = retention
+                .max_per_type
+                .map_or("none".to_string(), |n| n.to_string());
+            writeln!(
+                f,
+                "  Retention:          max age {}, max per type {}{} (every {}s)",
+                age,
+                per_type,
+                if retention.keep_tagged {
+                    ", keep tagged"
+                } else {
+                    ""
+                },
+                retention.sweep_interval_secs
+            )?;
+        } else {
+            writeln!(f, "  Retention:          disabled")?;
+        }
         writeln!(
             f,
             "  Max message size:   {} MB",
@@ -218,13 +674,26 @@ impl std::fmt::Display for Config {
         )?;
         writeln!(f, "  Read timeout:       {}s", self.mllp.read_timeout_secs)?;
         writeln!(f, "  Write timeout:      {}s", self.mllp.write_timeout_secs)?;
-        write!(f, "  Max connections:    {}", self.mllp.max_connections)
+        writeln!(f, "  Max connections:    {}", self.mllp.max_connections)?;
+        writeln!(f, "  ACK rules:          {}", self.mllp.ack.rules.len())?;
+        if let Some(target) = &self.mllp.ack.application_ack_target {
+            writeln!(f, "  Application ACKs:   {}", target)?;
+        }
+        if !self.validation.user_tables.is_empty() {
+            writeln!(
+                f,
+                "  User code tables:   {} file(s)",
+                self.validation.user_tables.len()
+            )?;
+        }
+        write!(f, "  Replay targets:     {}", self.outbound.targets.len())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::path::Path;
 
     #[test]
     fn test_defaults() {
@@ -238,10 +707,26 @@ mod tests {
         assert_eq!(config.logging.max_files, 5);
         assert_eq!(config.store.max_messages, 10_000);
         assert_eq!(config.store.max_memory_mb, 512);
+        assert_eq!(config.store.backend, StoreBackend::Memory);
+        assert_eq!(config.store.sqlite_path, PathBuf::from("hl7-forge.db"));
+        assert_eq!(config.store.parse_mode, ParseMode::Eager);
+        assert_eq!(
+            config.store.duplicate_window(),
+            Some(Duration::from_secs(300))
+        );
+        assert!(!config.store.retention.is_enabled());
+        assert!(!config.store.retention.keep_tagged);
+        assert_eq!(config.store.retention.sweep_interval_secs, 60);
         assert_eq!(config.mllp.max_message_size_mb, 10);
         assert_eq!(config.mllp.read_timeout_secs, 60);
         assert_eq!(config.mllp.write_timeout_secs, 30);
         assert_eq!(config.mllp.max_connections, 100);
+        assert!(config.mllp.ack.rules.is_empty());
+        assert_eq!(config.outbound.connect_timeout_secs, 10);
+        assert_eq!(config.outbound.ack_timeout_secs, 30);
+        assert!(config.outbound.targets.is_empty());
+        assert!(config.listeners.is_empty());
+        assert!(config.validation.user_tables.is_empty());
     }
 
     #[test]
@@ -272,6 +757,17 @@ max_files = 10
 [store]
 max_messages = 5000
 max_memory_mb = 256
+backend = "sqlite"
+sqlite_path = "data/messages.db"
+parse_mode = "lazy"
+detail_cache_size = 64
+duplicate_window_secs = 0
+
+[store.retention]
+max_age_days = 7
+max_per_type = 200
+keep_tagged = true
+sweep_interval_secs = 30
 
 [mllp]
 max_message_size_mb = 20
@@ -289,6 +785,22 @@ max_connections = 50
         assert_eq!(config.logging.max_files, 10);
         assert_eq!(config.store.max_messages, 5000);
         assert_eq!(config.store.max_memory_mb, 256);
+        assert_eq!(config.store.backend, StoreBackend::Sqlite);
+        assert_eq!(config.store.sqlite_path, PathBuf::from("data/messages.db"));
+        assert_eq!(config.store.parse_mode, ParseMode::Lazy);
+        assert_eq!(config.store.detail_cache_size, 64);
+        assert_eq!(config.store.duplicate_window(), None);
+        assert!(config.store.retention.is_enabled());
+        assert_eq!(
+            config.store.retention.max_age(),
+            Some(Duration::from_secs(7 * 86_400))
+        );
+        assert_eq!(config.store.retention.max_per_type, Some(200));
+        assert!(config.store.retention.keep_tagged);
+        assert_eq!(
+            config.store.retention.sweep_interval(),
+            Duration::from_secs(30)
+        );
         assert_eq!(config.mllp.max_message_size_mb, 20);
         assert_eq!(config.mllp.read_timeout_secs, 120);
         assert_eq!(config.mllp.write_timeout_secs, 60);
@@ -318,6 +830,180 @@ mllp_port = 4000
         assert_eq!(config.server.web_port, 8080);
     }
 
+    #[test]
+    fn test_parse_ack_rules() {
+        let toml_str = r#"
+[mllp.ack]
+duplicate_code = "CE"
+application_ack_target = "engine-test"
+
+[[mllp.ack.rules]]
+message_type = "ORU^*"
+sending_facility = "LAB"
+code = "AE"
+delay_ms = 1500
+
+[[mllp.ack.rules]]
+percentage = 10
+code = "none"
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        let rules = &config.mllp.ack.rules;
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].code, AckCode::AE);
+        assert_eq!(rules[0].delay(), Duration::from_millis(1500));
+        assert_eq!(rules[0].percentage, 100.0);
+        assert_eq!(rules[1].code, AckCode::None);
+        assert_eq!(rules[1].code.as_str(), None);
+        assert_eq!(rules[1].percentage, 10.0);
+        assert_eq!(config.mllp.ack.duplicate_code, Some(AckCode::CE));
+        assert_eq!(
+            config.mllp.ack.application_ack_target.as_deref(),
+            Some("engine-test")
+        );
+        assert_eq!(AckCode::AE.commit(), AckCode::CE);
+        assert_eq!(AckCode::CR.application(), AckCode::AR);
+        assert_eq!(AckCode::None.commit(), AckCode::None);
+    }
+
+    #[test]
+    fn test_ack_rule_percentage_out_of_range() {
+        for percentage in ["-1", "100.5", "150"] {
+            let toml_str = format!(
+                "[[mllp.ack.rules]]\npercentage = {}\ncode = \"AE\"\n",
+                percentage
+            );
+            let err = toml::from_str::<Config>(&toml_str).unwrap_err();
+            assert!(err.to_string().contains("between 0 and 100"), "{err}");
+        }
+    }
+
+    #[test]
+    fn test_default_listener_uses_mllp_port() {
+        let mut config = Config::default();
+        config.server.mllp_port = 3001;
+        let listeners = config.effective_listeners();
+        assert_eq!(listeners.len(), 1);
+        assert_eq!(listeners[0].bind_addr(), "0.0.0.0:3001");
+        assert_eq!(listeners[0].label(), "port 3001");
+    }
+
+    #[test]
+    fn test_mllp_port_override_applies_to_first_listener() {
+        let mut config = Config::default();
+        config.override_mllp_port(3001);
+        assert_eq!(config.effective_listeners()[0].port, 3001);
+
+        let mut config: Config =
+            toml::from_str("[[listeners]]\nport = 2575\n\n[[listeners]]\nport = 2576\n").unwrap();
+        config.override_mllp_port(3001);
+        let ports: Vec<u16> = config
+            .effective_listeners()
+            .iter()
+            .map(|l| l.port)
+            .collect();
+        assert_eq!(ports, [3001, 2576]);
+    }
+
+    #[test]
+    fn test_parse_listeners() {
+        let toml_str = r#"
+[mllp]
+read_timeout_secs = 90
+charset = "UNICODE UTF-8"
+
+[[listeners]]
+port = 2575
+label = "ADT channel"
+charset = "8859/1"
+
+[[listeners]]
+port = 2576
+bind = "127.0.0.1"
+max_connections = 5
+max_message_size_mb = 1
+
+[listeners.tls]
+cert = "certs/server.pem"
+key = "certs/server.key"
+client_ca = "certs/ca.pem"
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        let listeners = config.effective_listeners();
+        assert_eq!(listeners.len(), 2);
+        assert_eq!(listeners[0].label(), "ADT channel");
+        assert_eq!(listeners[1].bind_addr(), "127.0.0.1:2576");
+        assert!(listeners[0].tls.is_none());
+        let tls = listeners[1].tls.as_ref().unwrap();
+        assert_eq!(tls.cert, PathBuf::from("certs/server.pem"));
+        assert_eq!(tls.client_ca.as_deref(), Some(Path::new("certs/ca.pem")));
+
+        let first = listeners[0].mllp_config(&config.mllp);
+        assert_eq!(first.read_timeout_secs, 90);
+        assert_eq!(first.max_connections, 100);
+        assert_eq!(first.charset.as_deref(), Some("8859/1"));
+        let second = listeners[1].mllp_config(&config.mllp);
+        assert_eq!(second.max_connections, 5);
+        assert_eq!(second.max_message_size(), 1024 * 1024);
+        assert_eq!(second.read_timeout_secs, 90);
+        assert_eq!(second.charset.as_deref(), Some("UNICODE UTF-8"));
+    }
+
+    #[test]
+    fn test_parse_outbound_targets() {
+        let toml_str = r#"
+[outbound]
+ack_timeout_secs = 5
+
+[[outbound.targets]]
+name = "engine-test"
+host = "10.0.0.5"
+port = 6661
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.outbound.ack_timeout(), Duration::from_secs(5));
+        assert_eq!(config.outbound.connect_timeout(), Duration::from_secs(10));
+        let target = config.outbound.target("engine-test").unwrap();
+        assert_eq!(target.addr(), "10.0.0.5:6661");
+        assert!(config.outbound.target("missing").is_none());
+    }
+
+    #[test]
+    fn test_parse_validation() {
+        let toml_str = r#"
+[validation]
+user_tables = ["tables/site.json", "/etc/hl7-forge/lab.json"]
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(
+            config.validation.user_tables,
+            vec![
+                PathBuf::from("tables/site.json"),
+                PathBuf::from("/etc/hl7-forge/lab.json")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ack_rule_matching() {
+        let rule = AckRule {
+            message_type: Some("ADT^*".into()),
+            sending_facility: Some("WARD".into()),
+            ..Default::default()
+        };
+        assert!(rule.matches("ADT^A01", "WARD"));
+        assert!(!rule.matches("ADT^A01", "LAB"));
+        assert!(!rule.matches("ORU^R01", "WARD"));
+
+        let exact = AckRule {
+            message_type: Some("ADT^A01".into()),
+            ..Default::default()
+        };
+        assert!(exact.matches("ADT^A01", "ANY"));
+        assert!(!exact.matches("ADT^A011", "ANY"));
+        assert!(AckRule::default().matches("ZZZ^Z01", ""));
+    }
+
     #[test]
     fn test_unknown_fields_ignored() {
         let toml_str = r#"
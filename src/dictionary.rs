@@ -1,71 +1,318 @@
-#![allow(dead_code)]
-#![allow(unused_variables)]
-
+/// Embedded HL7 v2.x segment and field dictionaries, one per version.
+///
+/// `v2.5.1.json` is the full base dictionary. Every other version is an
+/// overlay on a neighbouring version (2.5 → 2.4 → 2.3.1 → 2.3 going back,
+/// 2.6 → 2.7 → 2.8 going forward) listing what differs: datatype renames
+/// (TS → DTM and CE → CWE in 2.7), fields added, renamed or not yet defined,
+/// and segments introduced or not yet defined.
+///
+/// Fields carry the metadata from the standard's segment tables: usage
+/// (R/O/C/B), maximum repetitions, maximum length and the code table. 2.5.1
+/// has it for every segment; fields without it (overlay fields that don't
+/// spell it out) are optional, unbounded and unchecked. Overlay fields
+/// replace the base field outright, metadata included.
+///
+/// Data type definitions (`datatypes.json`) name the components of composite
+/// types such as XPN, CX and XAD. They follow 2.5.1 and are shared by every
+/// version; an overlay's datatype renames also apply to component types.
+///
+/// The dictionary for a message is picked from MSH-12 with this fallback
+/// chain:
+/// 1. the exact version (`2.3.1`);
+/// 2. the newest known version older than it (`2.7.1` → `2.7`, `2.9` → `2.8`);
+/// 3. the oldest known version for anything older (`2.2` → `2.3`);
+/// 4. `2.5.1` when MSH-12 is empty or not a version number.
+use crate::hl7::types::{ComponentDescription, Hl7Component, Hl7Field, Hl7Segment};
 use serde::Deserialize;
 use std::collections::HashMap;
 use std::sync::OnceLock;
 
-#[derive(Debug, Deserialize)]
+/// Version used when MSH-12 is missing or unreadable
+pub const DEFAULT_VERSION: &str = "2.5.1";
+
+/// Known versions, oldest first
+pub const VERSIONS: &[&str] = &["2.3", "2.3.1", "2.4", "2.5", "2.5.1", "2.6", "2.7", "2.8"];
+
+/// Overlays in build order; each one's base is built before it
+const OVERLAYS: &[&str] = &[
+    include_str!("assets/hl7/v2.5.json"),
+    include_str!("assets/hl7/v2.4.json"),
+    include_str!("assets/hl7/v2.3.1.json"),
+    include_str!("assets/hl7/v2.3.json"),
+    include_str!("assets/hl7/v2.6.json"),
+    include_str!("assets/hl7/v2.7.json"),
+    include_str!("assets/hl7/v2.8.json"),
+];
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct FieldDef {
     pub seq: usize,
     pub desc: String,
     pub datatype: String,
+    #[serde(default)]
+    pub usage: Usage,
+    /// Maximum number of repetitions; `None` when unbounded or unknown
+    #[serde(default)]
+    pub max_cardinality: Option<usize>,
+    /// Maximum length of one repetition
+    #[serde(default)]
+    pub max_length: Option<usize>,
+    /// HL7 or user-defined code table (e.g. "0001")
+    #[serde(default)]
+    pub table: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+/// Field usage (optionality) from the segment definition
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum Usage {
+    #[serde(rename = "R")]
+    Required,
+    #[default]
+    #[serde(rename = "O")]
+    Optional,
+    #[serde(rename = "C")]
+    Conditional,
+    /// Kept for backward compatibility only
+    #[serde(rename = "B")]
+    Backward,
+}
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct SegmentDef {
     pub desc: String,
     pub fields: Vec<FieldDef>,
 }
 
-#[derive(Debug, Deserialize)]
+/// A data type; primitive types (ST, NM, …) have no components. The asset
+/// also names each type (`desc`), which is only there for readers.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DatatypeDef {
+    pub components: Vec<FieldDef>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct VersionDef {
     pub version: String,
     pub segments: HashMap<String, SegmentDef>,
+    #[serde(default)]
+    pub datatypes: HashMap<String, DatatypeDef>,
 }
 
-static DICTIONARY_V251: OnceLock<VersionDef> = OnceLock::new();
+#[derive(Debug, Deserialize)]
+struct Datatypes {
+    datatypes: HashMap<String, DatatypeDef>,
+}
 
-pub fn get_v251() -> &'static VersionDef {
-    DICTIONARY_V251.get_or_init(|| {
-        let json_str = include_str!("assets/hl7/v2.5.1.json");
-        serde_json::from_str(json_str).expect("Failed to parse embedded v2.5.1 dictionary")
-    })
+/// Differences between a version and its base
+#[derive(Debug, Deserialize)]
+struct Overlay {
+    version: String,
+    base: String,
+    /// Datatype renames applied to every inherited field
+    #[serde(default)]
+    datatypes: HashMap<String, String>,
+    #[serde(default)]
+    removed_segments: Vec<String>,
+    #[serde(default)]
+    segments: HashMap<String, SegmentOverlay>,
 }
 
-pub fn get_field_description(version: &str, segment: &str, field_seq: usize) -> Option<String> {
-    // Currently fallback to v2.5.1 for all versions, could be extended later
-    let dict = get_v251();
+#[derive(Debug, Deserialize)]
+struct SegmentOverlay {
+    /// Required for segments the base does not have
+    desc: Option<String>,
+    /// Drop inherited fields after this sequence number
+    field_count: Option<usize>,
+    /// Fields to add or replace
+    #[serde(default)]
+    fields: Vec<FieldDef>,
+}
+
+impl VersionDef {
+    fn segment(&self, name: &str) -> Option<&SegmentDef> {
+        self.segments.get(name)
+    }
 
-    if let Some(seg_def) = dict.segments.get(segment) {
-        // Find field definition by sequence number (1-based)
-        if let Some(field_def) = seg_def.fields.iter().find(|f| f.seq == field_seq) {
-            return Some(field_def.desc.clone());
+    pub fn field(&self, segment: &str, seq: usize) -> Option<&FieldDef> {
+        self.segment(segment)?.fields.iter().find(|f| f.seq == seq)
+    }
+
+    /// Components of a composite type; empty for primitive or unknown types
+    pub fn components(&self, datatype: &str) -> &[FieldDef] {
+        self.datatypes
+            .get(datatype)
+            .map(|d| d.components.as_slice())
+            .unwrap_or_default()
+    }
+
+    fn apply(&self, overlay: Overlay) -> VersionDef {
+        let mut segments = self.segments.clone();
+        for name in &overlay.removed_segments {
+            segments.remove(name);
+        }
+        let mut datatypes = self.datatypes.clone();
+        let fields = segments.values_mut().flat_map(|s| s.fields.iter_mut());
+        let components = datatypes.values_mut().flat_map(|d| d.components.iter_mut());
+        for field in fields.chain(components) {
+            if let Some(renamed) = overlay.datatypes.get(&field.datatype) {
+                field.datatype = renamed.clone();
+            }
+        }
+        for (name, changes) in overlay.segments {
+            let segment = segments.entry(name.clone()).or_insert_with(|| SegmentDef {
+                desc: String::new(),
+                fields: Vec::new(),
+            });
+            if let Some(desc) = changes.desc {
+                segment.desc = desc;
+            }
+            if let Some(count) = changes.field_count {
+                segment.fields.retain(|f| f.seq <= count);
+            }
+            for field in changes.fields {
+                segment.fields.retain(|f| f.seq != field.seq);
+                segment.fields.push(field);
+            }
+            segment.fields.sort_by_key(|f| f.seq);
+            assert!(
+                !segment.desc.is_empty(),
+                "v{} dictionary adds segment {} without a description",
+                overlay.version,
+                name
+            );
         }
+        VersionDef {
+            version: overlay.version,
+            segments,
+            datatypes,
+        }
+    }
+}
+
+static DICTIONARIES: OnceLock<HashMap<String, VersionDef>> = OnceLock::new();
+
+fn dictionaries() -> &'static HashMap<String, VersionDef> {
+    DICTIONARIES.get_or_init(|| {
+        let mut base: VersionDef = serde_json::from_str(include_str!("assets/hl7/v2.5.1.json"))
+            .expect("Failed to parse embedded v2.5.1 dictionary");
+        let datatypes: Datatypes = serde_json::from_str(include_str!("assets/hl7/datatypes.json"))
+            .expect("Failed to parse embedded data type definitions");
+        base.datatypes = datatypes.datatypes;
+        let mut all = HashMap::from([(base.version.clone(), base)]);
+        for json in OVERLAYS {
+            let overlay: Overlay =
+                serde_json::from_str(json).expect("Failed to parse embedded dictionary overlay");
+            let version = all
+                .get(&overlay.base)
+                .unwrap_or_else(|| panic!("Dictionary base v{} is not built yet", overlay.base))
+                .apply(overlay);
+            all.insert(version.version.clone(), version);
+        }
+        all
+    })
+}
+
+/// The known version whose dictionary applies to an MSH-12 value
+pub fn resolve_version(version: &str) -> &'static str {
+    let version = version.split('^').next().unwrap_or_default().trim();
+    if let Some(known) = VERSIONS.iter().find(|v| **v == version) {
+        return known;
     }
-    None
+    let Some(requested) = version_key(version) else {
+        return DEFAULT_VERSION;
+    };
+    VERSIONS
+        .iter()
+        .rev()
+        .find(|v| version_key(v).is_some_and(|known| known <= requested))
+        .unwrap_or(&VERSIONS[0])
+}
+
+fn version_key(version: &str) -> Option<Vec<u32>> {
+    version.split('.').map(|part| part.parse().ok()).collect()
+}
+
+/// Dictionary for an MSH-12 value, following the fallback chain
+pub fn get(version: &str) -> &'static VersionDef {
+    &dictionaries()[resolve_version(version)]
+}
+
+#[allow(dead_code)]
+pub fn get_field_description(version: &str, segment: &str, field_seq: usize) -> Option<String> {
+    get(version)
+        .field(segment, field_seq)
+        .map(|f| f.desc.clone())
 }
 
 /// Return the description for a segment (e.g. "MSH" → "Message Header").
-pub fn get_segment_description(name: &str) -> Option<String> {
-    get_v251().segments.get(name).map(|s| s.desc.clone())
+pub fn get_segment_description(version: &str, name: &str) -> Option<String> {
+    get(version).segment(name).map(|s| s.desc.clone())
 }
 
-pub fn inject_descriptions(segments: &mut [crate::hl7::types::Hl7Segment], version: &str) {
-    let dict = get_v251();
+pub fn inject_descriptions(segments: &mut [Hl7Segment], version: &str) {
+    let dict = get(version);
     for segment in segments.iter_mut() {
-        let seg_name = segment.name.clone();
-        if let Some(seg_def) = dict.segments.get(&seg_name) {
+        if let Some(seg_def) = dict.segment(&segment.name) {
             segment.description = Some(seg_def.desc.clone());
             for field in segment.fields.iter_mut() {
                 if let Some(field_def) = seg_def.fields.iter().find(|f| f.seq == field.index) {
                     field.description = Some(field_def.desc.clone());
+                    field.datatype = Some(field_def.datatype.clone());
+                    field.table = field_def.table.clone();
+                    field.component_descriptions = describe_components(dict, field_def, field);
                 }
             }
         }
     }
 }
 
+/// Component names for the components present in a composite field. Fields
+/// holding a single plain value get none; the field description covers them.
+fn describe_components(
+    dict: &VersionDef,
+    field_def: &FieldDef,
+    field: &Hl7Field,
+) -> Vec<ComponentDescription> {
+    let present = |c: &Hl7Component| c.subcomponents.len();
+    let component_count = field
+        .repetitions
+        .iter()
+        .map(|r| r.components.len())
+        .max()
+        .unwrap_or(0);
+    let nested = component_count > 1
+        || field
+            .repetitions
+            .iter()
+            .any(|r| r.components.iter().any(|c| present(c) > 1));
+    if !nested {
+        return Vec::new();
+    }
+    dict.components(&field_def.datatype)
+        .iter()
+        .take(component_count)
+        .enumerate()
+        .map(|(i, component)| {
+            let split = field
+                .repetitions
+                .iter()
+                .any(|r| r.components.get(i).is_some_and(|c| present(c) > 1));
+            ComponentDescription {
+                description: component.desc.clone(),
+                datatype: component.datatype.clone(),
+                subcomponents: if split {
+                    dict.components(&component.datatype)
+                        .iter()
+                        .map(|s| s.desc.clone())
+                        .collect()
+                } else {
+                    Vec::new()
+                },
+            }
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -87,4 +334,125 @@ mod tests {
         let desc = get_field_description("2.5.1", "ZZZ", 1);
         assert_eq!(desc, None);
     }
+
+    #[test]
+    fn test_every_version_is_embedded() {
+        for version in VERSIONS {
+            assert_eq!(get(version).version, *version);
+            assert!(get(version).segment("MSH").is_some(), "v{version}");
+        }
+    }
+
+    #[test]
+    fn test_resolve_version_fallback_chain() {
+        assert_eq!(resolve_version("2.3.1"), "2.3.1");
+        assert_eq!(resolve_version("2.7.1"), "2.7");
+        assert_eq!(resolve_version("2.5.2"), "2.5.1");
+        assert_eq!(resolve_version("2.9"), "2.8");
+        assert_eq!(resolve_version("2.1"), "2.3");
+        assert_eq!(resolve_version("2.4^DEU&&HL70399"), "2.4");
+        assert_eq!(resolve_version(""), DEFAULT_VERSION);
+        assert_eq!(resolve_version("v2"), DEFAULT_VERSION);
+    }
+
+    #[test]
+    fn test_versions_differ() {
+        let datatype = |v: &str, seg: &str, seq| get(v).field(seg, seq).map(|f| f.datatype.clone());
+        // TS → DTM and CE → CWE from 2.7
+        assert_eq!(datatype("2.5.1", "PID", 7).as_deref(), Some("TS"));
+        assert_eq!(datatype("2.7", "PID", 7).as_deref(), Some("DTM"));
+        assert_eq!(datatype("2.6", "PID", 10).as_deref(), Some("CE"));
+        assert_eq!(datatype("2.8", "PID", 10).as_deref(), Some("CWE"));
+        assert_eq!(datatype("2.4", "MSH", 9).as_deref(), Some("CM_MSG"));
+
+        assert_eq!(
+            get_field_description("2.3", "PID", 8).as_deref(),
+            Some("Sex")
+        );
+        assert_eq!(
+            get_field_description("2.4", "PID", 8).as_deref(),
+            Some("Administrative Sex")
+        );
+        assert!(get_field_description("2.4", "PID", 39).is_none());
+        assert!(get_field_description("2.3", "OBX", 18).is_none());
+        assert!(get_field_description("2.8", "PID", 40).is_some());
+        assert!(get_segment_description("2.4", "SPM").is_none());
+        assert!(get_segment_description("2.5", "SPM").is_some());
+        assert!(get_segment_description("2.6", "PRT").is_none());
+        assert_eq!(
+            get_segment_description("2.7", "PRT").as_deref(),
+            Some("Participation Information")
+        );
+    }
+
+    #[test]
+    fn test_datatype_components() {
+        let cx = get("2.5.1").components("CX");
+        assert_eq!(cx[3].desc, "Assigning Authority");
+        assert_eq!(cx[3].datatype, "HD");
+        assert!(get("2.5.1").components("ST").is_empty());
+        assert!(get("2.5.1").components("ZZZ").is_empty());
+
+        // Datatype renames reach component types too
+        assert_eq!(get("2.6").components("XPN")[8].datatype, "CE");
+        assert_eq!(get("2.7").components("XPN")[8].datatype, "CWE");
+
+        // 2.3 and 2.4 type MSH-9 as CM_MSG, with the same components as MSG
+        assert_eq!(get("2.3").components("CM_MSG")[1].desc, "Trigger Event");
+    }
+
+    #[test]
+    fn test_component_types_are_defined() {
+        let dict = get("2.5.1");
+        let undefined: Vec<_> = dict
+            .datatypes
+            .values()
+            .flat_map(|d| &d.components)
+            .filter(|c| !dict.datatypes.contains_key(&c.datatype))
+            .map(|c| c.datatype.clone())
+            .collect();
+        assert!(undefined.is_empty(), "{undefined:?}");
+    }
+
+    #[test]
+    fn test_field_metadata() {
+        let pid3 = get("2.5.1").field("PID", 3).unwrap();
+        assert_eq!(pid3.usage, Usage::Required);
+        assert_eq!(pid3.max_cardinality, None);
+        assert_eq!(pid3.max_length, Some(250));
+
+        let pid8 = get("2.5.1").field("PID", 8).unwrap();
+        assert_eq!(pid8.usage, Usage::Optional);
+        assert_eq!(pid8.max_cardinality, Some(1));
+        assert_eq!(pid8.table.as_deref(), Some("0001"));
+        assert_eq!(get("2.5.1").field("PID", 2).unwrap().usage, Usage::Backward);
+
+        // Overlay fields bring their own metadata
+        let sex = get("2.3").field("PID", 8).unwrap();
+        assert_eq!(
+            (sex.desc.as_str(), sex.table.as_deref()),
+            ("Sex", Some("0001"))
+        );
+        assert_eq!(get("2.3").field("MSH", 12).unwrap().usage, Usage::Required);
+    }
+
+    #[test]
+    fn test_every_segment_has_metadata() {
+        let dict = get("2.5.1");
+        for (name, seg) in &dict.segments {
+            for f in &seg.fields {
+                assert!(f.max_length.is_some(), "{name}-{} has no length", f.seq);
+            }
+        }
+
+        let in1 = dict.field("IN1", 2).unwrap();
+        assert_eq!(in1.usage, Usage::Required);
+        assert_eq!(in1.table.as_deref(), Some("0072"));
+        assert_eq!(
+            dict.field("PV2", 22).unwrap().table.as_deref(),
+            Some("0136")
+        );
+        assert_eq!(dict.field("SPM", 4).unwrap().usage, Usage::Required);
+        assert_eq!(dict.field("RXA", 5).unwrap().max_cardinality, Some(1));
+    }
 }
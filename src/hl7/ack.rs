@@ -0,0 +1,495 @@
+/// Acknowledgment construction and the acknowledgment mode requested by the
+/// sender in MSH-15 (accept acknowledgment type) and MSH-16 (application
+/// acknowledgment type).
+///
+/// When both fields are empty the message uses original mode: one AA/AE/AR
+/// ACK on the receiving connection. When either is set it uses enhanced mode:
+/// a commit ACK (CA/CE/CR) on the connection and an application ACK
+/// (AA/AE/AR) sent back separately, each governed by its condition.
+///
+/// ACKs mirror the original header (MSH-3/4 ↔ MSH-5/6), echo its version and
+/// processing ID, and report problems in ERR segments: ERR-2 location, ERR-3
+/// HL7 error code (table 0357), ERR-4 severity and ERR-8 user message. Those
+/// fields arrived in v2.5; for older originals ERR-1 (error code and location)
+/// carries the location and code instead.
+use super::parser::parse_message;
+use super::path::FieldPath;
+use super::types::{Delimiters, Hl7Message};
+use crate::dictionary::resolve_version;
+use crate::validation::ValidationWarning;
+
+/// Sending application/facility used when the original names no receiver
+const FORGE_NAME: &str = "HL7Forge";
+/// Version used when the original's version is unknown
+const DEFAULT_VERSION: &str = "2.5";
+
+/// Conditions under which an acknowledgment is sent (HL7 table 0155)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AckCondition {
+    /// Always
+    AL,
+    /// Never
+    NE,
+    /// Error/reject conditions only
+    ER,
+    /// Successful completion only
+    SU,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AckMode {
+    Original,
+    Enhanced {
+        accept: AckCondition,
+        application: AckCondition,
+    },
+}
+
+impl AckCondition {
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_uppercase().as_str() {
+            "AL" => Some(AckCondition::AL),
+            "NE" => Some(AckCondition::NE),
+            "ER" => Some(AckCondition::ER),
+            "SU" => Some(AckCondition::SU),
+            _ => None,
+        }
+    }
+
+    /// Whether an acknowledgment with this outcome should be sent
+    pub fn applies(self, success: bool) -> bool {
+        match self {
+            AckCondition::AL => true,
+            AckCondition::NE => false,
+            AckCondition::ER => !success,
+            AckCondition::SU => success,
+        }
+    }
+}
+
+impl Hl7Message {
+    /// Acknowledgment mode from MSH-15/MSH-16. In enhanced mode an empty or
+    /// unrecognised condition counts as `AL`.
+    pub fn ack_mode(&self) -> AckMode {
+        let condition = |field: usize| {
+            self.get_path(&FieldPath {
+                segment: "MSH".into(),
+                occurrence: None,
+                field,
+                repetition: None,
+                component: None,
+                subcomponent: None,
+            })
+            .filter(|v| !v.trim().is_empty())
+        };
+        let (accept, application) = (condition(15), condition(16));
+        if accept.is_none() && application.is_none() {
+            return AckMode::Original;
+        }
+        let parse = |v: Option<String>| {
+            v.as_deref()
+                .and_then(AckCondition::parse)
+                .unwrap_or(AckCondition::AL)
+        };
+        AckMode::Enhanced {
+            accept: parse(accept),
+            application: parse(application),
+        }
+    }
+}
+
+/// HL7 error codes used in ERR-3 (table 0357)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    SegmentSequence,
+    RequiredFieldMissing,
+    DataType,
+    TableValueNotFound,
+    InternalError,
+}
+
+impl ErrorCode {
+    pub fn code(self) -> &'static str {
+        match self {
+            ErrorCode::SegmentSequence => "100",
+            ErrorCode::RequiredFieldMissing => "101",
+            ErrorCode::DataType => "102",
+            ErrorCode::TableValueNotFound => "103",
+            ErrorCode::InternalError => "207",
+        }
+    }
+
+    pub fn text(self) -> &'static str {
+        match self {
+            ErrorCode::SegmentSequence => "Segment sequence error",
+            ErrorCode::RequiredFieldMissing => "Required field missing",
+            ErrorCode::DataType => "Data type error",
+            ErrorCode::TableValueNotFound => "Table value not found",
+            ErrorCode::InternalError => "Application internal error",
+        }
+    }
+}
+
+/// ERR-4 severity (HL7 table 0516)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    fn code(self) -> &'static str {
+        match self {
+            Severity::Error => "E",
+            Severity::Warning => "W",
+        }
+    }
+}
+
+/// One problem reported in an ERR segment
+#[derive(Debug, Clone, PartialEq)]
+pub struct AckError {
+    /// ERR-2 segment ID; the first occurrence is assumed
+    pub segment: String,
+    /// ERR-2 field position, if the problem is in a single field
+    pub field: Option<usize>,
+    pub code: ErrorCode,
+    pub severity: Severity,
+    /// ERR-8 user message
+    pub message: String,
+}
+
+impl AckError {
+    /// A validation finding, reported with `severity`
+    pub fn from_warning(warning: &ValidationWarning, severity: Severity) -> Self {
+        let code = match warning.code.as_str() {
+            "MISSING_SEGMENT" => ErrorCode::SegmentSequence,
+            "MISSING_FIELD" => ErrorCode::RequiredFieldMissing,
+            "INVALID_DATATYPE" | "FIELD_TOO_LONG" | "TOO_MANY_REPETITIONS" => ErrorCode::DataType,
+            "INVALID_CODE" => ErrorCode::TableValueNotFound,
+            _ => ErrorCode::InternalError,
+        };
+        Self {
+            segment: warning.segment.clone(),
+            field: warning.field,
+            code,
+            severity,
+            message: warning.message.clone(),
+        }
+    }
+
+    fn to_segment(&self, delims: &Delimiters, legacy: bool) -> String {
+        if legacy {
+            // ERR-1 (ELD): segment^sequence^field^code&text&table
+            return format!(
+                "ERR|{}^1^{}^{}&{}&HL70357",
+                delims.escape(&self.segment),
+                self.field.map(|f| f.to_string()).unwrap_or_default(),
+                self.code.code(),
+                self.code.text(),
+            );
+        }
+        let location = match self.field {
+            Some(field) => format!("{}^1^{}", delims.escape(&self.segment), field),
+            None => format!("{}^1", delims.escape(&self.segment)),
+        };
+        format!(
+            "ERR||{}|{}^{}^HL70357|{}||||{}",
+            location,
+            self.code.code(),
+            self.code.text(),
+            self.severity.code(),
+            delims.escape(&self.message),
+        )
+    }
+}
+
+/// True for the error and reject codes AE/AR/CE/CR
+fn is_error_code(ack_code: &str) -> bool {
+    matches!(ack_code, "AE" | "AR" | "CE" | "CR")
+}
+
+/// Build an ACK for a received message. Validation warnings on the message
+/// are reported in ERR segments: as errors when `ack_code` rejects the
+/// message, otherwise as warnings, since the message was still accepted.
+pub fn build_ack(original: &Hl7Message, ack_code: &str) -> String {
+    let severity = if is_error_code(ack_code) {
+        Severity::Error
+    } else {
+        Severity::Warning
+    };
+    let errors: Vec<AckError> = original
+        .validation_warnings
+        .iter()
+        .map(|w| AckError::from_warning(w, severity))
+        .collect();
+    build_ack_with_errors(original, ack_code, &errors)
+}
+
+/// Header of a message that could not be parsed, recovered from the first
+/// MSH segment in `raw` when possible; otherwise an empty message.
+pub fn recover_header(raw: &str) -> Hl7Message {
+    raw.find("MSH")
+        .and_then(|start| raw[start..].split(['\r', '\n']).next())
+        .and_then(|msh| parse_message(msh, "").ok())
+        .unwrap_or_else(|| Hl7Message::new_empty(String::new(), String::new()))
+}
+
+/// Build a NAK (`AE` in original mode, `CE` in enhanced mode) for a message
+/// that could not be parsed, mirroring its recovered `header`. MSA-2 is empty
+/// when no header could be recovered.
+pub fn build_parse_error_nack(header: &Hl7Message, ack_code: &str, error: &str) -> String {
+    let error = AckError {
+        segment: "MSH".into(),
+        field: None,
+        code: ErrorCode::SegmentSequence,
+        severity: Severity::Error,
+        message: format!("Message parse error: {error}"),
+    };
+    build_ack_with_errors(header, ack_code, &[error])
+}
+
+fn build_ack_with_errors(original: &Hl7Message, ack_code: &str, errors: &[AckError]) -> String {
+    let now = chrono::Utc::now().format("%Y%m%d%H%M%S").to_string();
+    // Decoded values echoed from the original (control ID, processing ID,
+    // trigger) are re-escaped; MSH-3 to MSH-6 and MSH-12 are stored raw and
+    // echoed as received so their components survive
+    let delims = Delimiters::default();
+    let or_forge = |value: &str| {
+        if value.is_empty() {
+            FORGE_NAME.to_string()
+        } else {
+            value.to_string()
+        }
+    };
+    let processing_id = original
+        .get_path(&FieldPath {
+            segment: "MSH".into(),
+            occurrence: None,
+            field: 11,
+            repetition: None,
+            component: Some(1),
+            subcomponent: None,
+        })
+        .filter(|v| !v.is_empty())
+        .unwrap_or_else(|| "P".into());
+    let version = if original.version.is_empty() {
+        DEFAULT_VERSION
+    } else {
+        original.version.as_str()
+    };
+    let message_type = match original.trigger_event.as_str() {
+        "" => "ACK".to_string(),
+        trigger => format!("ACK^{}", delims.escape(trigger)),
+    };
+    let msh = format!(
+        "MSH|^~\\&|{}|{}|{}|{}|{}||{}|{}|{}|{}",
+        or_forge(&original.receiving_application),
+        or_forge(&original.receiving_facility),
+        original.sending_application,
+        original.sending_facility,
+        now,
+        message_type,
+        &uuid::Uuid::new_v4().to_string().replace('-', "")[..20],
+        delims.escape(&processing_id),
+        version,
+    );
+    let mut msa = format!(
+        "MSA|{}|{}",
+        ack_code,
+        delims.escape(&original.message_control_id),
+    );
+    // MSA-3 is deprecated in favour of ERR but still read by older engines
+    if is_error_code(ack_code) {
+        if let Some(error) = errors.iter().find(|e| e.severity == Severity::Error) {
+            msa.push('|');
+            msa.push_str(&delims.escape(&error.message));
+        }
+    }
+    let legacy = matches!(resolve_version(version), "2.3" | "2.3.1" | "2.4");
+    let mut ack = format!("{}\r{}", msh, msa);
+    for error in errors {
+        ack.push('\r');
+        ack.push_str(&error.to_segment(&delims, legacy));
+    }
+    ack
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hl7::parser::parse_message;
+
+    fn mode(msh_15_16: &str) -> AckMode {
+        let raw = format!("MSH|^~\\&|APP|FAC|R|R|20240101||ADT^A01|1|P|2.5|||{msh_15_16}");
+        parse_message(&raw, "127.0.0.1:5000").unwrap().ack_mode()
+    }
+
+    #[test]
+    fn test_ack_mode_from_msh() {
+        assert_eq!(mode(""), AckMode::Original);
+        assert_eq!(mode("|"), AckMode::Original);
+        assert_eq!(
+            mode("AL|ER"),
+            AckMode::Enhanced {
+                accept: AckCondition::AL,
+                application: AckCondition::ER,
+            }
+        );
+        // A missing or unknown condition in enhanced mode means "always"
+        assert_eq!(
+            mode("|SU"),
+            AckMode::Enhanced {
+                accept: AckCondition::AL,
+                application: AckCondition::SU,
+            }
+        );
+        assert_eq!(
+            mode("NE|XX"),
+            AckMode::Enhanced {
+                accept: AckCondition::NE,
+                application: AckCondition::AL,
+            }
+        );
+    }
+
+    const SAMPLE_ADT: &str = "MSH|^~\\&|SENDING_APP|SENDING_FAC|REC_APP|REC_FAC|20240101120000||ADT^A01^ADT_A01|MSG00001|T|2.3\rEVN|A01\rPID|||12345^^^HOSP||Smith^John\rPV1||I";
+
+    fn segment<'a>(ack: &'a str, name: &str) -> Vec<&'a str> {
+        ack.split('\r').filter(|s| s.starts_with(name)).collect()
+    }
+
+    #[test]
+    fn test_build_ack() {
+        let msg = parse_message(SAMPLE_ADT, "127.0.0.1:9999").unwrap();
+        let ack = build_ack(&msg, "AA");
+        let msh: Vec<&str> = segment(&ack, "MSH")[0].split('|').collect();
+        // Sender and receiver swapped, version and processing ID echoed
+        assert_eq!(
+            &msh[2..6],
+            ["REC_APP", "REC_FAC", "SENDING_APP", "SENDING_FAC"]
+        );
+        assert_eq!(msh[8], "ACK^A01");
+        assert_eq!(msh[10], "T");
+        assert_eq!(msh[11], "2.3");
+        assert!(ack.contains("MSA|AA|MSG00001"));
+    }
+
+    #[test]
+    fn test_build_ack_escapes_echoed_values() {
+        let raw = "MSH|^~\\&|APP|A\\F\\B|R|R|20240101||ADT^A01|X\\S\\1|P|2.5";
+        let msg = parse_message(raw, "127.0.0.1:9999").unwrap();
+        assert_eq!(msg.sending_facility, "A\\F\\B");
+        let ack = build_ack(&msg, "AA");
+        assert!(ack.contains("|A\\F\\B|"));
+        assert_eq!(segment(&ack, "MSA")[0], "MSA|AA|X\\S\\1");
+    }
+
+    #[test]
+    fn test_build_ack_echoes_composite_header_fields() {
+        let raw = "MSH|^~\\&|APP^1.2.3^ISO|FAC|R|R|20240101||ADT^A01|C1|P|2.5^DEU&&HL70399";
+        let msg = parse_message(raw, "127.0.0.1:9999").unwrap();
+        let ack = build_ack(&msg, "AA");
+        let msh: Vec<&str> = segment(&ack, "MSH")[0].split('|').collect();
+        assert_eq!(&msh[2..6], ["R", "R", "APP^1.2.3^ISO", "FAC"]);
+        assert_eq!(msh[11], "2.5^DEU&&HL70399");
+    }
+
+    #[test]
+    fn test_ack_without_receiver_names_forge() {
+        let msg = parse_message("MSH|^~\\&|APP|FAC|||20240101||ORU^R01|C1", "").unwrap();
+        let ack = build_ack(&msg, "AA");
+        assert!(
+            ack.starts_with("MSH|^~\\&|HL7Forge|HL7Forge|APP|FAC|"),
+            "{ack}"
+        );
+        let msh: Vec<&str> = segment(&ack, "MSH")[0].split('|').collect();
+        assert_eq!((msh[10], msh[11]), ("P", "2.5"));
+    }
+
+    #[test]
+    fn test_validation_warnings_become_err_segments() {
+        // ADT^A01 without PV1 is missing a required segment
+        let msg = parse_message(
+            "MSH|^~\\&|APP|FAC|R|R|20240101||ADT^A01|C1|P|2.5\rEVN|A01\rPID|||1^^^H||Doe^Jane",
+            "",
+        )
+        .unwrap();
+        assert!(!msg.validation_warnings.is_empty());
+
+        let nak = build_ack(&msg, "AE");
+        let errs = segment(&nak, "ERR");
+        assert_eq!(errs.len(), msg.validation_warnings.len());
+        let pv1 = errs.iter().find(|e| e.contains("PV1^1")).unwrap();
+        let fields: Vec<&str> = pv1.split('|').collect();
+        assert_eq!(fields[3], "100^Segment sequence error^HL70357");
+        assert_eq!(fields[4], "E");
+        assert!(segment(&nak, "MSA")[0].starts_with("MSA|AE|C1|"));
+
+        // Accepted messages report the same findings as warnings
+        let ack = build_ack(&msg, "AA");
+        assert!(segment(&ack, "ERR")
+            .iter()
+            .all(|e| e.split('|').nth(4) == Some("W")));
+        assert_eq!(segment(&ack, "MSA")[0], "MSA|AA|C1");
+    }
+
+    #[test]
+    fn test_pre_25_errors_use_err_1() {
+        let msg = parse_message(
+            "MSH|^~\\&|APP|FAC|R|R|20240101||ADT^A01|C1|P|2.3\rEVN|A01\rPID|||1^^^H||Doe^Jane\rPV1||I",
+            "",
+        )
+        .unwrap();
+        let errors = [AckError {
+            segment: "PID".into(),
+            field: Some(8),
+            code: ErrorCode::RequiredFieldMissing,
+            severity: Severity::Error,
+            message: "PID-8 (Sex) is required".into(),
+        }];
+        let nak = build_ack_with_errors(&msg, "AE", &errors);
+        assert_eq!(
+            segment(&nak, "ERR"),
+            ["ERR|PID^1^8^101&Required field missing&HL70357"]
+        );
+        assert!(segment(&nak, "MSA")[0].starts_with("MSA|AE|C1|PID-8"));
+
+        // 2.5 and later keep the ERR-2..8 layout
+        let msg = parse_message(
+            "MSH|^~\\&|APP|FAC|R|R|20240101||ADT^A01|C1|P|2.5\rEVN|A01\rPID|||1^^^H||Doe^Jane\rPV1||I",
+            "",
+        )
+        .unwrap();
+        let nak = build_ack_with_errors(&msg, "AE", &errors);
+        assert!(segment(&nak, "ERR")[0].starts_with("ERR||PID^1^8|101^"));
+    }
+
+    #[test]
+    fn test_parse_error_nack_recovers_header() {
+        let header =
+            recover_header("\u{feff}MSH|^~\\&|APP|FAC|R|RF|20240101||ADT^A01|CTRL9|P|2.4\rPID|||1");
+        let nak = build_parse_error_nack(&header, "AE", "Message does not start with MSH");
+        assert!(nak.starts_with("MSH|^~\\&|R|RF|APP|FAC|"), "{nak}");
+        assert!(nak.contains("|2.4\rMSA|AE|CTRL9|Message parse error"));
+        // v2.4 reports the error in ERR-1
+        assert_eq!(
+            segment(&nak, "ERR")[0],
+            "ERR|MSH^1^^100&Segment sequence error&HL70357"
+        );
+
+        let header = recover_header("garbage");
+        assert_eq!(header.ack_mode(), AckMode::Original);
+        let nak = build_parse_error_nack(&header, "AE", "Message does not start with MSH");
+        assert!(nak.contains("||ACK|"), "{nak}");
+        assert!(nak.contains("\rMSA|AE||Message parse error"), "{nak}");
+    }
+
+    #[test]
+    fn test_condition_applies() {
+        assert!(AckCondition::AL.applies(true) && AckCondition::AL.applies(false));
+        assert!(!AckCondition::NE.applies(true) && !AckCondition::NE.applies(false));
+        assert!(!AckCondition::ER.applies(true) && AckCondition::ER.applies(false));
+        assert!(AckCondition::SU.applies(true) && !AckCondition::SU.applies(false));
+    }
+}
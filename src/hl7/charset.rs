@@ -0,0 +1,189 @@
+/// Character set handling for inbound frames (MSH-18).
+///
+/// Frames arrive as bytes. The charset named in MSH-18 (HL7 table 0211) picks
+/// the decoder; when MSH-18 is empty or unrecognised the listener's default
+/// charset applies, and UTF-8 when none is configured. Only ASCII-compatible
+/// charsets are supported, since MSH itself must be readable to find MSH-18.
+use crate::validation::ValidationWarning;
+use encoding_rs::Encoding;
+use std::borrow::Cow;
+
+/// The result of decoding one frame
+#[derive(Debug)]
+pub struct Decoded {
+    pub text: String,
+    /// The encoding actually applied
+    pub encoding: &'static Encoding,
+    /// Some bytes were invalid in `encoding` and were replaced with U+FFFD
+    pub lossy: bool,
+    /// MSH-18 value that names no supported charset
+    pub unknown_charset: Option<String>,
+}
+
+impl Decoded {
+    /// Validation warnings for a lossy decode or an unrecognised MSH-18
+    pub fn warnings(&self) -> Vec<ValidationWarning> {
+        let mut warnings = Vec::new();
+        if let Some(charset) = &self.unknown_charset {
+            warnings.push(ValidationWarning {
+                code: "UNKNOWN_CHARSET".into(),
+                message: format!(
+                    "MSH-18 character set '{}' is not supported; decoded as {}",
+                    charset,
+                    self.encoding.name()
+                ),
+                segment: "MSH".into(),
+                field: Some(18),
+            });
+        }
+        if self.lossy {
+            warnings.push(ValidationWarning {
+                code: "LOSSY_DECODING".into(),
+                message: format!(
+                    "Message is not valid {}; invalid bytes were replaced with U+FFFD",
+                    self.encoding.name()
+                ),
+                segment: "MSH".into(),
+                field: Some(18),
+            });
+        }
+        warnings
+    }
+}
+
+/// Encoding for an HL7 table 0211 value such as `8859/1` or `UNICODE UTF-8`,
+/// or for a WHATWG label such as `windows-1252`
+pub fn encoding_for(charset: &str) -> Option<&'static Encoding> {
+    let charset = charset.trim();
+    let encoding = match charset.to_ascii_uppercase().as_str() {
+        // windows-1252 is a superset of both
+        "ASCII" | "8859/1" => encoding_rs::WINDOWS_1252,
+        "8859/2" => encoding_rs::ISO_8859_2,
+        "8859/3" => encoding_rs::ISO_8859_3,
+        "8859/4" => encoding_rs::ISO_8859_4,
+        "8859/5" => encoding_rs::ISO_8859_5,
+        "8859/6" => encoding_rs::ISO_8859_6,
+        "8859/7" => encoding_rs::ISO_8859_7,
+        "8859/8" => encoding_rs::ISO_8859_8,
+        "8859/9" => encoding_rs::WINDOWS_1254,
+        "8859/15" => encoding_rs::ISO_8859_15,
+        "GB 18030-2000" => encoding_rs::GB18030,
+        "KS X 1001" => encoding_rs::EUC_KR,
+        "BIG-5" => encoding_rs::BIG5,
+        "UNICODE" | "UNICODE UTF-8" => encoding_rs::UTF_8,
+        _ => Encoding::for_label(charset.as_bytes())?,
+    };
+    // UTF-16 frames cannot be framed or parsed as ASCII-compatible text
+    (encoding.output_encoding() == encoding).then_some(encoding)
+}
+
+/// Decode a frame using its MSH-18 charset, then `default`, then UTF-8
+pub fn decode(bytes: &[u8], default: Option<&'static Encoding>) -> Decoded {
+    let declared = msh18(bytes);
+    let from_msh = declared.as_deref().and_then(encoding_for);
+    let encoding = from_msh.or(default).unwrap_or(encoding_rs::UTF_8);
+    let (text, encoding, lossy) = encoding.decode(bytes);
+    Decoded {
+        text: text.into_owned(),
+        encoding,
+        lossy,
+        unknown_charset: declared.filter(|_| from_msh.is_none()),
+    }
+}
+
+/// Encode `text` for the wire in the named encoding (UTF-8 when unset or
+/// unknown), so replays and ACKs use the charset the message arrived in
+pub fn encode<'a>(text: &'a str, encoding: Option<&str>) -> Cow<'a, [u8]> {
+    let encoding = encoding
+        .and_then(|name| Encoding::for_label(name.as_bytes()))
+        .unwrap_or(encoding_rs::UTF_8);
+    encoding.encode(text).0
+}
+
+/// First repetition of MSH-18, read straight from the bytes
+fn msh18(bytes: &[u8]) -> Option<String> {
+    let start = bytes.windows(3).position(|w| w == b"MSH")?;
+    let msh = &bytes[start..];
+    let end = msh
+        .iter()
+        .position(|&b| b == b'\r' || b == b'\n')
+        .unwrap_or(msh.len());
+    let msh = &msh[..end];
+    let field_sep = *msh.get(3)?;
+    let repetition_sep = msh.get(5).copied().unwrap_or(b'~');
+    // parts[0] is "MSH" and MSH-1 is the separator itself, so MSH-n is parts[n - 1]
+    let field = msh.split(|&b| b == field_sep).nth(17)?;
+    let first = field.split(|&b| b == repetition_sep).next()?;
+    let value = String::from_utf8_lossy(first).trim().to_string();
+    (!value.is_empty()).then_some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(msh18: &str, body: &[u8]) -> Vec<u8> {
+        let mut bytes =
+            format!("MSH|^~\\&|APP|FAC|R|R|20240101||ADT^A01|1|P|2.5||||||{msh18}\rPID|||1||")
+                .into_bytes();
+        bytes.extend_from_slice(body);
+        bytes
+    }
+
+    #[test]
+    fn test_msh18_selects_decoder() {
+        // "Müller" in ISO-8859-1
+        let decoded = decode(&frame("8859/1", b"M\xfcller^J"), None);
+        assert!(decoded.text.ends_with("Müller^J"), "{}", decoded.text);
+        assert_eq!(decoded.encoding, encoding_rs::WINDOWS_1252);
+        assert!(!decoded.lossy);
+        assert!(decoded.warnings().is_empty());
+
+        let decoded = decode(&frame("UNICODE UTF-8~8859/1", "Müller".as_bytes()), None);
+        assert!(decoded.text.ends_with("Müller"));
+        assert_eq!(decoded.encoding, encoding_rs::UTF_8);
+    }
+
+    #[test]
+    fn test_default_charset_and_lossy_warning() {
+        // No MSH-18: the listener default applies
+        let latin1 = frame("", b"Gr\xf6\xdfe");
+        let decoded = decode(&latin1, Some(encoding_rs::WINDOWS_1252));
+        assert!(decoded.text.ends_with("Größe"));
+
+        // ...and without a default, UTF-8 is lossy
+        let decoded = decode(&latin1, None);
+        assert!(decoded.lossy);
+        assert!(decoded.text.contains('\u{FFFD}'));
+        let codes: Vec<_> = decoded.warnings().into_iter().map(|w| w.code).collect();
+        assert_eq!(codes, vec!["LOSSY_DECODING"]);
+    }
+
+    #[test]
+    fn test_unknown_charset_falls_back() {
+        let decoded = decode(&frame("EBCDIC", b"Smith"), Some(encoding_rs::WINDOWS_1252));
+        assert_eq!(decoded.encoding, encoding_rs::WINDOWS_1252);
+        assert_eq!(decoded.unknown_charset.as_deref(), Some("EBCDIC"));
+        assert_eq!(decoded.warnings()[0].code, "UNKNOWN_CHARSET");
+    }
+
+    #[test]
+    fn test_encoding_for() {
+        assert_eq!(encoding_for("8859/2"), Some(encoding_rs::ISO_8859_2));
+        assert_eq!(
+            encoding_for("windows-1252"),
+            Some(encoding_rs::WINDOWS_1252)
+        );
+        assert_eq!(encoding_for("iso-8859-15"), Some(encoding_rs::ISO_8859_15));
+        assert_eq!(encoding_for("UNICODE UTF-16"), None);
+        assert_eq!(encoding_for("utf-16le"), None);
+        assert_eq!(encoding_for("nonsense"), None);
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        let bytes = encode("Müller", Some("windows-1252"));
+        assert_eq!(&*bytes, b"M\xfcller");
+        assert_eq!(&*encode("Müller", None), "Müller".as_bytes());
+    }
+}
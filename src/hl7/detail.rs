@@ -0,0 +1,116 @@
+/// Deferred message detail for `parse_mode = "lazy"`.
+///
+/// In lazy mode the store keeps only the raw text and the summary fields of
+/// each message. The parsed segment tree, with its dictionary descriptions,
+/// and the typical-segment lists are rebuilt from `raw` when a message is
+/// opened or a field-path search needs them.
+use super::parser::parse_message;
+use super::types::{Hl7Message, Hl7Segment};
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+/// The parts of a message that lazy mode drops after ingest
+#[derive(Debug, Clone, Default)]
+pub struct MessageDetail {
+    pub segments: Vec<Hl7Segment>,
+    pub typical_segments: Vec<String>,
+    pub typical_segment_descriptions: HashMap<String, String>,
+}
+
+impl Hl7Message {
+    /// Drop the parsed detail, keeping raw text and summary fields.
+    /// Messages that failed to parse have no detail and are left as they are.
+    pub fn defer_detail(&mut self) {
+        if self.detail_deferred || self.parse_error.is_some() {
+            return;
+        }
+        self.segments = Vec::new();
+        self.typical_segments = Vec::new();
+        self.typical_segment_descriptions = HashMap::new();
+        self.detail_deferred = true;
+    }
+
+    /// Re-parse the detail dropped by [`defer_detail`](Self::defer_detail)
+    pub fn parse_detail(&self) -> MessageDetail {
+        match parse_message(&self.raw, &self.source_addr) {
+            Ok(parsed) => MessageDetail {
+                segments: parsed.segments,
+                typical_segments: parsed.typical_segments,
+                typical_segment_descriptions: parsed.typical_segment_descriptions,
+            },
+            Err(_) => MessageDetail::default(),
+        }
+    }
+
+    pub fn restore_detail(&mut self, detail: MessageDetail) {
+        self.segments = detail.segments;
+        self.typical_segments = detail.typical_segments;
+        self.typical_segment_descriptions = detail.typical_segment_descriptions;
+        self.detail_deferred = false;
+    }
+
+    /// This message with its full detail, re-parsing a deferred one
+    pub fn with_detail(&self) -> Cow<'_, Hl7Message> {
+        if !self.detail_deferred {
+            return Cow::Borrowed(self);
+        }
+        let mut full = self.clone();
+        full.restore_detail(self.parse_detail());
+        Cow::Owned(full)
+    }
+
+    /// Number of segments, counted from `raw` when the detail is deferred
+    pub fn segment_count(&self) -> usize {
+        if self.detail_deferred {
+            self.raw
+                .split(['\r', '\n'])
+                .filter(|s| !s.trim().is_empty())
+                .count()
+        } else {
+            self.segments.len()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADT: &str = "MSH|^~\\&|APP|FAC|R|R|20240101||ADT^A01|1|P|2.5\r\
+                       EVN|A01|20240101\r\
+                       PID|||123^^^HOSP||Doe^Jane\r\
+                       PV1||I|WARD1";
+
+    #[test]
+    fn test_defer_and_restore_round_trip() {
+        let full = parse_message(ADT, "127.0.0.1:9999").unwrap();
+        let mut lazy = full.clone();
+        lazy.defer_detail();
+
+        assert!(lazy.segments.is_empty());
+        assert!(lazy.typical_segment_descriptions.is_empty());
+        assert!(lazy.memory_estimate().total() < full.memory_estimate().total() / 2);
+        // Summary fields survive
+        assert_eq!(lazy.patient_id.as_deref(), Some("123"));
+        assert_eq!(lazy.segment_count(), 4);
+
+        let restored = lazy.with_detail();
+        assert!(!restored.detail_deferred);
+        assert_eq!(restored.segments.len(), full.segments.len());
+        assert_eq!(
+            restored.segments[2].fields[2].description,
+            full.segments[2].fields[2].description
+        );
+        assert_eq!(restored.typical_segments, full.typical_segments);
+    }
+
+    #[test]
+    fn test_unparsed_message_is_not_deferred() {
+        let mut msg = Hl7Message::new_empty("garbage".into(), "127.0.0.1:9999".into());
+        msg.parse_error = Some("Message does not start with MSH".into());
+        msg.defer_detail();
+        assert!(!msg.detail_deferred);
+        assert_eq!(msg.segment_count(), 0);
+        assert!(matches!(msg.with_detail(), Cow::Borrowed(_)));
+    }
+}
@@ -0,0 +1,178 @@
+/// HL7 v2 escape sequences (HL7 v2.5.1 chapter 2.7).
+///
+/// Delimiter characters inside a value are written as `\F\`, `\S\`, `\T\`,
+/// `\R\` and `\E\` (using the message's own escape character). Hex data
+/// (`\Xhh…\`) and the line-break formatting command `\.br\` are decoded as
+/// well; the `\H\`/`\N\` highlighting toggles have no plain-text equivalent
+/// and are dropped. Any other sequence is kept verbatim.
+use super::types::Delimiters;
+use std::borrow::Cow;
+
+impl Delimiters {
+    /// Decode all escape sequences in a single component or subcomponent.
+    ///
+    /// Decode after splitting on delimiters: a decoded `\S\` is a literal
+    /// component separator and must not be split again.
+    pub fn unescape<'a>(&self, value: &'a str) -> Cow<'a, str> {
+        let esc = self.escape;
+        if !value.contains(esc) {
+            return Cow::Borrowed(value);
+        }
+
+        let mut out = String::with_capacity(value.len());
+        let mut rest = value;
+        while let Some(start) = rest.find(esc) {
+            out.push_str(&rest[..start]);
+            let after = &rest[start + esc.len_utf8()..];
+            let Some(end) = after.find(esc) else {
+                // Unterminated sequence: keep the remainder as-is
+                out.push_str(&rest[start..]);
+                return Cow::Owned(out);
+            };
+            let sequence = &after[..end];
+            match self.decode_sequence(sequence) {
+                Some(decoded) => out.push_str(&decoded),
+                None => {
+                    out.push(esc);
+                    out.push_str(sequence);
+                    out.push(esc);
+                }
+            }
+            rest = &after[end + esc.len_utf8()..];
+        }
+        out.push_str(rest);
+        Cow::Owned(out)
+    }
+
+    /// Encode a plain value so it can be placed into a single component or
+    /// subcomponent. Line breaks become `\.br\` and carriage returns (the
+    /// segment terminator) become `\X0D\`.
+    pub fn escape(&self, value: &str) -> String {
+        let mut out = String::with_capacity(value.len());
+        for c in value.chars() {
+            let sequence = if c == self.escape {
+                "E"
+            } else if c == self.field {
+                "F"
+            } else if c == self.component {
+                "S"
+            } else if c == self.subcomponent {
+                "T"
+            } else if c == self.repetition {
+                "R"
+            } else if c == '\n' {
+                ".br"
+            } else if c == '\r' {
+                "X0D"
+            } else {
+                out.push(c);
+                continue;
+            };
+            out.push(self.escape);
+            out.push_str(sequence);
+            out.push(self.escape);
+        }
+        out
+    }
+
+    /// Decode the text between two escape characters, or `None` if it is
+    /// not a sequence we understand.
+    fn decode_sequence(&self, sequence: &str) -> Option<Cow<'static, str>> {
+        let decoded = match sequence {
+            "F" => self.field,
+            "S" => self.component,
+            "T" => self.subcomponent,
+            "R" => self.repetition,
+            "E" => self.escape,
+            ".br" => '\n',
+            "H" | "N" => return Some(Cow::Borrowed("")),
+            _ => return decode_hex(sequence.strip_prefix('X')?).map(Cow::Owned),
+        };
+        Some(Cow::Owned(decoded.to_string()))
+    }
+}
+
+/// Decode `\Xhh…\` data. Bytes are read as UTF-8, falling back to Latin-1
+/// when they are not valid UTF-8.
+fn decode_hex(hex: &str) -> Option<String> {
+    if hex.is_empty() || !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    Some(match String::from_utf8(bytes) {
+        Ok(s) => s,
+        Err(e) => e.into_bytes().into_iter().map(char::from).collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn delims() -> Delimiters {
+        Delimiters::default()
+    }
+
+    #[test]
+    fn test_unescape_delimiters() {
+        let d = delims();
+        assert_eq!(d.unescape("A\\F\\B"), "A|B");
+        assert_eq!(d.unescape("Smith\\S\\Jones"), "Smith^Jones");
+        assert_eq!(d.unescape("R\\T\\D"), "R&D");
+        assert_eq!(d.unescape("a\\R\\b"), "a~b");
+        assert_eq!(d.unescape("C:\\E\\temp"), "C:\\temp");
+    }
+
+    #[test]
+    fn test_unescape_borrows_plain_values() {
+        assert!(matches!(delims().unescape("plain"), Cow::Borrowed("plain")));
+    }
+
+    #[test]
+    fn test_unescape_hex_formatting_and_highlight() {
+        let d = delims();
+        assert_eq!(d.unescape("M\\XC3BC\\ller"), "Müller");
+        assert_eq!(d.unescape("\\XE9\\"), "é"); // not UTF-8 → Latin-1
+        assert_eq!(d.unescape("line1\\.br\\line2"), "line1\nline2");
+        assert_eq!(d.unescape("\\H\\IMPORTANT\\N\\ note"), "IMPORTANT note");
+    }
+
+    #[test]
+    fn test_unescape_keeps_unknown_and_unterminated() {
+        let d = delims();
+        assert_eq!(d.unescape("\\.sp2\\x"), "\\.sp2\\x");
+        assert_eq!(d.unescape("\\XZZ\\"), "\\XZZ\\");
+        assert_eq!(d.unescape("trailing \\F"), "trailing \\F");
+    }
+
+    #[test]
+    fn test_custom_delimiters() {
+        let d = Delimiters {
+            field: '#',
+            component: '@',
+            repetition: '*',
+            escape: '!',
+            subcomponent: '%',
+        };
+        assert_eq!(d.unescape("a!F!b!S!c"), "a#b@c");
+        assert_eq!(d.escape("a#b@c!"), "a!F!b!S!c!E!");
+    }
+
+    #[test]
+    fn test_escape_round_trip() {
+        let d = delims();
+        let plain = "O'Brien & Sons | A^B~C\\D\nnext\rend";
+        let escaped = d.escape(plain);
+        assert_eq!(
+            escaped,
+            "O'Brien \\T\\ Sons \\F\\ A\\S\\B\\R\\C\\E\\D\\.br\\next\\X0D\\end"
+        );
+        for delim in ['|', '^', '~', '&', '\r', '\n'] {
+            assert!(!escaped.contains(delim));
+        }
+        assert_eq!(d.unescape(&escaped), plain);
+    }
+}
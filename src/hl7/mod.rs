@@ -1,3 +1,9 @@
+pub mod ack;
+pub mod charset;
+pub mod detail;
+pub mod escape;
 pub mod message_types;
 pub mod parser;
+pub mod path;
+pub mod size;
 pub mod types;
@@ -3,6 +3,17 @@ use super::types::*;
 /// Parse a raw HL7 v2.x message string into a structured Hl7Message.
 /// Handles standard and custom delimiters from MSH segment.
 pub fn parse_message(raw: &str, source_addr: &str) -> Result<Hl7Message, String> {
+    parse(raw, source_addr, true)
+}
+
+/// Parse without the dictionary descriptions on segments and fields, for
+/// messages whose segment detail is dropped after ingest (`parse_mode =
+/// "lazy"`); summary fields and validation are the same as [`parse_message`].
+pub fn parse_message_unannotated(raw: &str, source_addr: &str) -> Result<Hl7Message, String> {
+    parse(raw, source_addr, false)
+}
+
+fn parse(raw: &str, source_addr: &str, annotate: bool) -> Result<Hl7Message, String> {
     let raw = raw.trim();
     if raw.is_empty() {
         return Err("Empty message".into());
@@ -34,6 +45,7 @@ pub fn parse_message(raw: &str, source_addr: &str) -> Result<Hl7Message, String>
     // Extract key fields from MSH
     if let Some(msh) = msg.segments.first() {
         // Use HL7-standard field numbers (1-based): MSH-1=separator, MSH-2=encoding chars, etc.
+        // MSH-3 to MSH-6 stay raw so that `A^B` and `A\S\B` remain distinct
         msg.sending_application = get_field_value(msh, 3);
         msg.sending_facility = get_field_value(msh, 4);
         msg.receiving_application = get_field_value(msh, 5);
@@ -53,46 +65,32 @@ pub fn parse_message(raw: &str, source_addr: &str) -> Result<Hl7Message, String>
             }
         }
 
-        msg.message_control_id = get_field_value(msh, 10);
+        msg.message_control_id = get_field_text(msh, 10);
         msg.version = get_field_value(msh, 12);
     }
 
     // Extract patient info from PID segment
     if let Some(pid) = msg.segments.iter().find(|s| s.name == "PID") {
-        // PID-3: Patient ID
-        let pid3 = get_field_value(pid, 3);
-        if !pid3.is_empty() {
-            // Take first component (ID itself, before ^^^authority)
-            msg.patient_id = Some(
-                pid3.split(delimiters.component)
-                    .next()
-                    .unwrap_or(&pid3)
-                    .to_string(),
-            );
+        // PID-3: Patient ID — first component (ID itself, before ^^^authority)
+        if let Some(id) = get_component(pid, 3, 1).filter(|id| !id.is_empty()) {
+            msg.patient_id = Some(id.to_string());
         }
 
         // PID-5: Patient Name (Family^Given^Middle^Suffix^Prefix)
-        let pid5 = get_field_value(pid, 5);
-        if !pid5.is_empty() {
-            let name_parts: Vec<&str> = pid5.split(delimiters.component).collect();
-            let name = match name_parts.len() {
-                0 => String::new(),
-                1 => name_parts[0].to_string(),
-                _ => format!("{}, {}", name_parts[0], name_parts[1]),
-            };
-            if !name.is_empty() {
-                msg.patient_name = Some(name);
-            }
+        let family = get_component(pid, 5, 1).unwrap_or_default();
+        let name = match get_component(pid, 5, 2) {
+            Some(given) => format!("{}, {}", family, given),
+            None => family.to_string(),
+        };
+        if !name.is_empty() {
+            msg.patient_name = Some(name);
         }
     }
 
-    // Second pass: inject field descriptions from the embedded dictionary
-    let version = if msg.version.is_empty() {
-        "2.5.1"
-    } else {
-        &msg.version
-    };
-    crate::dictionary::inject_descriptions(&mut msg.segments, version);
+    // Second pass: inject field descriptions from the dictionary for MSH-12
+    if annotate {
+        crate::dictionary::inject_descriptions(&mut msg.segments, &msg.version);
+    }
 
     // Third pass: look up message type description and typical segments
     if let Some(info) = super::message_types::get_message_type_info(&msg.message_type) {
@@ -106,7 +104,8 @@ pub fn parse_message(raw: &str, source_addr: &str) -> Result<Hl7Message, String>
             .typical_segments
             .iter()
             .filter_map(|s| {
-                crate::dictionary::get_segment_description(s).map(|d| (s.to_string(), d))
+                crate::dictionary::get_segment_description(&msg.version, s)
+                    .map(|d| (s.to_string(), d))
             })
             .collect();
     }
@@ -157,16 +156,34 @@ fn parse_segment(raw: &str, delimiters: Delimiters) -> Hl7Segment {
 
     // For MSH, field indexing is special: MSH-1 is the separator itself
     for (i, &part) in parts.iter().enumerate().skip(1) {
+        // MSH-2 holds the encoding characters themselves and is never split or unescaped
+        if name == "MSH" && i == 1 {
+            fields.push(literal_field(i, part));
+            continue;
+        }
+
+        // `components` describes the first repetition only
         let components: Vec<String> = part
+            .split(delimiters.repetition)
+            .next()
+            .unwrap_or_default()
             .split(delimiters.component)
-            .map(|c| c.to_string())
+            .map(|c| delimiters.unescape(c).into_owned())
             .collect();
 
         fields.push(Hl7Field {
             index: i,
             value: part.to_string(),
+            unescaped: match delimiters.unescape(part) {
+                std::borrow::Cow::Owned(decoded) => Some(decoded),
+                std::borrow::Cow::Borrowed(_) => None,
+            },
             components,
+            repetitions: parse_repetitions(part, delimiters),
             description: None,
+            datatype: None,
+            table: None,
+            component_descriptions: Vec::new(),
         });
     }
 
@@ -177,15 +194,7 @@ fn parse_segment(raw: &str, delimiters: Delimiters) -> Hl7Segment {
         for field in fields.iter_mut() {
             field.index += 1;
         }
-        fields.insert(
-            0,
-            Hl7Field {
-                index: 1,
-                value: sep.to_string(),
-                components: vec![sep.to_string()],
-                description: None,
-            },
-        );
+        fields.insert(0, literal_field(1, &sep.to_string()));
     }
 
     Hl7Segment {
@@ -196,6 +205,46 @@ fn parse_segment(raw: &str, delimiters: Delimiters) -> Hl7Segment {
     }
 }
 
+/// Split a raw field value into repetitions, components and subcomponents.
+fn parse_repetitions(value: &str, delimiters: Delimiters) -> Vec<Hl7Repetition> {
+    if value.is_empty() {
+        return Vec::new();
+    }
+    value
+        .split(delimiters.repetition)
+        .map(|rep| Hl7Repetition {
+            components: rep
+                .split(delimiters.component)
+                .map(|comp| Hl7Component {
+                    subcomponents: comp
+                        .split(delimiters.subcomponent)
+                        .map(|sub| delimiters.unescape(sub).into_owned())
+                        .collect(),
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+/// A field taken verbatim, without delimiter splitting (MSH-1 and MSH-2).
+fn literal_field(index: usize, value: &str) -> Hl7Field {
+    Hl7Field {
+        index,
+        value: value.to_string(),
+        unescaped: None,
+        components: vec![value.to_string()],
+        repetitions: vec![Hl7Repetition {
+            components: vec![Hl7Component {
+                subcomponents: vec![value.to_string()],
+            }],
+        }],
+        description: None,
+        datatype: None,
+        table: None,
+        component_descriptions: Vec::new(),
+    }
+}
+
 /// Get field value by HL7 field number (1-based standard numbering).
 /// For MSH: index 1 = field separator, 2 = encoding chars, 3 = sending app, etc.
 fn get_field_value(segment: &Hl7Segment, index: usize) -> String {
@@ -207,20 +256,33 @@ fn get_field_value(segment: &Hl7Segment, index: usize) -> String {
         .unwrap_or_default()
 }
 
-/// Build an ACK message for a received HL7 message
-pub fn build_ack(original: &Hl7Message, ack_code: &str) -> String {
-    let now = chrono::Utc::now().format("%Y%m%d%H%M%S").to_string();
-    let msh = format!(
-        "MSH|^~\\&|HL7Forge|HL7Forge|{}|{}|{}||ACK^{}|{}|P|{}",
-        original.sending_application,
-        original.sending_facility,
-        now,
-        original.trigger_event,
-        &uuid::Uuid::new_v4().to_string().replace('-', "")[..20],
-        original.version,
-    );
-    let msa = format!("MSA|{}|{}", ack_code, original.message_control_id,);
-    format!("{}\r{}", msh, msa)
+/// Get a field's value with escape sequences decoded.
+fn get_field_text(segment: &Hl7Segment, index: usize) -> String {
+    segment
+        .fields
+        .iter()
+        .find(|f| f.index == index)
+        .map(|f| f.unescaped.clone().unwrap_or_else(|| f.value.clone()))
+        .unwrap_or_default()
+}
+
+/// Get the first subcomponent of a component in the first repetition of a
+/// field, decoded (HL7 field and component numbers are 1-based).
+fn get_component(segment: &Hl7Segment, field: usize, component: usize) -> Option<&str> {
+    segment
+        .fields
+        .iter()
+        .find(|f| f.index == field)?
+        .repetition(1)?
+        .component(component)?
+        .subcomponent(1)
+}
+
+/// Extract the acknowledgment code (MSA-1) from a raw ACK message.
+pub fn extract_ack_code(raw: &str) -> Option<String> {
+    let msg = parse_message(raw, "").ok()?;
+    let msa = msg.segments.iter().find(|s| s.name == "MSA")?;
+    Some(get_field_value(msa, 1)).filter(|v| !v.is_empty())
 }
 
 #[cfg(test)]
@@ -244,11 +306,11 @@ mod tests {
     }
 
     #[test]
-    fn test_build_ack() {
-        let msg = parse_message(SAMPLE_ADT, "127.0.0.1:9999").unwrap();
-        let ack = build_ack(&msg, "AA");
-        assert!(ack.starts_with("MSH|^~\\&|HL7Forge"));
-        assert!(ack.contains("MSA|AA|MSG00001"));
+    fn test_extract_ack_code() {
+        let ack = "MSH|^~\\&|R|R|S|S|20240101||ACK^A01|ACK001|P|2.5\rMSA|AE|MSG00001";
+        assert_eq!(extract_ack_code(ack), Some("AE".into()));
+        assert_eq!(extract_ack_code("MSH|^~\\&|R|R"), None);
+        assert_eq!(extract_ack_code("garbage"), None);
     }
 
     #[test]
@@ -262,4 +324,145 @@ mod tests {
         assert_eq!(get_field_value(msh, 4), "SENDING_FAC");
         assert_eq!(get_field_value(msh, 9), "ADT^A01^ADT_A01");
     }
+
+    #[test]
+    fn test_header_fields_keep_separators_and_escapes() {
+        let composite = parse_message(
+            "MSH|^~\\&|APP^1.2.3^ISO|FAC|R|R|20240101||ADT^A01|1|P|2.5",
+            "t",
+        )
+        .unwrap();
+        let escaped = parse_message(
+            "MSH|^~\\&|APP\\S\\1.2.3|FAC|R|R|20240101||ADT^A01|1|P|2.5",
+            "t",
+        )
+        .unwrap();
+        assert_eq!(composite.sending_application, "APP^1.2.3^ISO");
+        assert_eq!(escaped.sending_application, "APP\\S\\1.2.3");
+    }
+
+    #[test]
+    fn test_unannotated_parse_skips_descriptions() {
+        let full = parse_message(SAMPLE_ADT, "127.0.0.1:9999").unwrap();
+        let bare = parse_message_unannotated(SAMPLE_ADT, "127.0.0.1:9999").unwrap();
+        assert!(full.segments[0].description.is_some());
+        assert!(bare
+            .segments
+            .iter()
+            .all(|s| s.description.is_none() && s.fields.iter().all(|f| f.description.is_none())));
+        assert_eq!(bare.message_type, full.message_type);
+        assert_eq!(bare.patient_id, full.patient_id);
+        assert_eq!(
+            bare.validation_warnings.len(),
+            full.validation_warnings.len()
+        );
+    }
+
+    #[test]
+    fn test_escape_sequences_are_decoded() {
+        let raw = "MSH|^~\\&|LAB\\T\\CO|FAC|R|R|20240101||ORU^R01|C\\F\\1|P|2.5\r\
+                   PID|||12345||O\\S\\Brien^Ann\\.br\\Marie\r\
+                   NTE|1||Result \\H\\high\\N\\ \\XC3BC\\";
+        let msg = parse_message(raw, "127.0.0.1:9999").unwrap();
+        assert_eq!(msg.sending_application, "LAB\\T\\CO");
+        assert_eq!(msg.message_control_id, "C|1");
+        assert_eq!(msg.patient_name, Some("O^Brien, Ann\nMarie".into()));
+
+        let msh = &msg.segments[0];
+        assert_eq!(get_field_value(msh, 2), "^~\\&");
+        assert!(msh.fields[1].unescaped.is_none());
+
+        // The escaped separator is not split into an extra component
+        let pid5 = msg.segments[1]
+            .fields
+            .iter()
+            .find(|f| f.index == 5)
+            .unwrap();
+        assert_eq!(pid5.value, "O\\S\\Brien^Ann\\.br\\Marie");
+        assert_eq!(pid5.components, vec!["O^Brien", "Ann\nMarie"]);
+        assert_eq!(pid5.unescaped.as_deref(), Some("O^Brien^Ann\nMarie"));
+
+        let nte3 = msg.segments[2]
+            .fields
+            .iter()
+            .find(|f| f.index == 3)
+            .unwrap();
+        assert_eq!(nte3.unescaped.as_deref(), Some("Result high ü"));
+        let pid3 = msg.segments[1]
+            .fields
+            .iter()
+            .find(|f| f.index == 3)
+            .unwrap();
+        assert!(pid3.unescaped.is_none());
+    }
+
+    #[test]
+    fn test_repetitions_and_subcomponents() {
+        let raw = "MSH|^~\\&|APP|FAC|R|R|20240101||ADT^A01|1|P|2.5\r\
+                   PID|||123^^^HOSP&1.2.3&ISO^MR~456^^^LAB^PI||Doe^J\\T\\D";
+        let msg = parse_message(raw, "127.0.0.1:9999").unwrap();
+        let pid = &msg.segments[1];
+        let pid3 = pid.fields.iter().find(|f| f.index == 3).unwrap();
+
+        assert_eq!(pid3.repetitions.len(), 2);
+        assert_eq!(pid3.components, vec!["123", "", "", "HOSP&1.2.3&ISO", "MR"]);
+        let authority = pid3.repetition(1).unwrap().component(4).unwrap();
+        assert_eq!(authority.subcomponents, vec!["HOSP", "1.2.3", "ISO"]);
+        assert_eq!(authority.subcomponent(2), Some("1.2.3"));
+        let second = pid3.repetition(2).unwrap();
+        assert_eq!(second.component(1).unwrap().subcomponents, vec!["456"]);
+        assert_eq!(second.component(5).unwrap().subcomponent(1), Some("PI"));
+        assert!(pid3.repetition(3).is_none());
+        assert_eq!(msg.patient_id, Some("123".into()));
+
+        // An escaped subcomponent separator stays inside its subcomponent
+        let pid5 = pid.fields.iter().find(|f| f.index == 5).unwrap();
+        let given = pid5.repetition(1).unwrap().component(2).unwrap();
+        assert_eq!(given.subcomponents, vec!["J&D"]);
+
+        // Empty fields have no repetitions; MSH-2 is kept as a single literal value
+        let pid2 = pid.fields.iter().find(|f| f.index == 2).unwrap();
+        assert!(pid2.repetitions.is_empty());
+        let msh2 = &msg.segments[0].fields[1];
+        assert_eq!(
+            msh2.repetitions[0].components[0].subcomponents,
+            vec!["^~\\&"]
+        );
+
+        // Serialized as nested arrays
+        let json = serde_json::to_value(pid3).unwrap();
+        assert_eq!(
+            json["repetitions"][0][3],
+            serde_json::json!(["HOSP", "1.2.3", "ISO"])
+        );
+        assert_eq!(json["repetitions"][1][0], serde_json::json!(["456"]));
+    }
+
+    #[test]
+    fn test_component_descriptions() {
+        let raw = "MSH|^~\\&|APP|FAC|R|R|20240101||ADT^A01|1|P|2.5\r\
+                   PID|||123^^^HOSP&1.2.3&ISO^MR||Doe^John||19800101|M";
+        let msg = parse_message(raw, "127.0.0.1:9999").unwrap();
+        let pid = &msg.segments[1];
+
+        let pid5 = pid.fields.iter().find(|f| f.index == 5).unwrap();
+        assert_eq!(pid5.datatype.as_deref(), Some("XPN"));
+        assert_eq!(pid5.component_descriptions.len(), 2);
+        assert_eq!(pid5.component_descriptions[1].description, "Given Name");
+        assert_eq!(pid5.component_descriptions[0].datatype, "FN");
+
+        let pid3 = pid.fields.iter().find(|f| f.index == 3).unwrap();
+        let authority = &pid3.component_descriptions[3];
+        assert_eq!(authority.description, "Assigning Authority");
+        assert_eq!(
+            authority.subcomponents,
+            vec!["Namespace ID", "Universal ID", "Universal ID Type"]
+        );
+        assert!(pid3.component_descriptions[4].subcomponents.is_empty());
+
+        // A single plain value is described by the field alone
+        let pid8 = pid.fields.iter().find(|f| f.index == 8).unwrap();
+        assert_eq!(pid8.datatype.as_deref(), Some("IS"));
+        assert!(pid8.component_descriptions.is_empty());
+    }
 }
@@ -0,0 +1,303 @@
+/// Terser-style field paths such as `PID-3`, `OBX[3]-5`, `PID-3[2].4.1`.
+///
+/// Grammar: `SEG[occurrence]-field[repetition].component.subcomponent`, where
+/// every number is 1-based and everything after the field number is optional.
+/// Values are returned with escape sequences decoded.
+use super::types::{Delimiters, Hl7Field, Hl7Message, Hl7Repetition};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldPath {
+    pub segment: String,
+    /// Which occurrence of the segment (1 = first); `None` when not given
+    pub occurrence: Option<usize>,
+    pub field: usize,
+    pub repetition: Option<usize>,
+    pub component: Option<usize>,
+    pub subcomponent: Option<usize>,
+}
+
+impl FromStr for FieldPath {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (segment_part, rest) = s
+            .split_once('-')
+            .ok_or_else(|| format!("Invalid path '{}': expected e.g. PID-3", s))?;
+
+        let (segment, occurrence) = split_index(segment_part, "segment occurrence")?;
+        if segment.len() != 3 || !segment.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(format!("Invalid segment name '{}'", segment));
+        }
+
+        let mut parts = rest.split('.');
+        let (field, repetition) = split_index(parts.next().unwrap_or_default(), "repetition")?;
+        let field = parse_number(field, "field")?;
+        let component = parts
+            .next()
+            .map(|c| parse_number(c, "component"))
+            .transpose()?;
+        let subcomponent = parts
+            .next()
+            .map(|c| parse_number(c, "subcomponent"))
+            .transpose()?;
+        if parts.next().is_some() {
+            return Err(format!(
+                "Invalid path '{}': at most field.component.subcomponent",
+                s
+            ));
+        }
+
+        Ok(Self {
+            segment: segment.to_ascii_uppercase(),
+            occurrence,
+            field,
+            repetition,
+            component,
+            subcomponent,
+        })
+    }
+}
+
+impl fmt::Display for FieldPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.segment)?;
+        if let Some(occurrence) = self.occurrence {
+            write!(f, "[{}]", occurrence)?;
+        }
+        write!(f, "-{}", self.field)?;
+        if let Some(rep) = self.repetition {
+            write!(f, "[{}]", rep)?;
+        }
+        if let Some(comp) = self.component {
+            write!(f, ".{}", comp)?;
+        }
+        if let Some(sub) = self.subcomponent {
+            write!(f, ".{}", sub)?;
+        }
+        Ok(())
+    }
+}
+
+/// Split `NAME[n]` into `("NAME", Some(n))`; plain `NAME` gives `None`.
+fn split_index<'a>(s: &'a str, what: &str) -> Result<(&'a str, Option<usize>), String> {
+    match s.strip_suffix(']').and_then(|s| s.split_once('[')) {
+        Some((name, index)) => Ok((name, Some(parse_number(index, what)?))),
+        None => Ok((s, None)),
+    }
+}
+
+fn parse_number(s: &str, what: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(n) if n >= 1 => Ok(n),
+        _ => Err(format!(
+            "Invalid {} number '{}' (must be 1 or greater)",
+            what, s
+        )),
+    }
+}
+
+impl Hl7Message {
+    /// Value at `path`, escape sequences decoded; `None` when the message has
+    /// no such segment, field or part. An unspecified segment occurrence or
+    /// repetition means the first one.
+    ///
+    /// Without a repetition or component the whole field is returned; a
+    /// repetition or component without further indexes is returned with its
+    /// parts joined by the message's delimiters.
+    pub fn get_path(&self, path: &FieldPath) -> Option<String> {
+        let segment = self
+            .segments
+            .iter()
+            .filter(|s| s.name == path.segment)
+            .nth(path.occurrence.unwrap_or(1) - 1)?;
+        let field = segment.fields.iter().find(|f| f.index == path.field)?;
+
+        if path.repetition.is_none() && path.component.is_none() {
+            return Some(field_text(field));
+        }
+        let repetition = field.repetition(path.repetition.unwrap_or(1))?;
+        repetition_value(repetition, path, &self.delimiters())
+    }
+
+    /// Every value matching `path`. Unlike [`get_path`](Self::get_path), an
+    /// unspecified segment occurrence or repetition matches all of them, so
+    /// `OBX-5` yields the value of every OBX segment.
+    pub fn get_path_all(&self, path: &FieldPath) -> Vec<String> {
+        let delimiters = self.delimiters();
+        let segments = self
+            .segments
+            .iter()
+            .filter(|s| s.name == path.segment)
+            .enumerate()
+            .filter(|(i, _)| path.occurrence.is_none_or(|n| n == i + 1))
+            .map(|(_, s)| s);
+
+        let mut values = Vec::new();
+        for segment in segments {
+            let Some(field) = segment.fields.iter().find(|f| f.index == path.field) else {
+                continue;
+            };
+            if path.repetition.is_none() && path.component.is_none() {
+                values.push(field_text(field));
+                continue;
+            }
+            let repetitions = field
+                .repetitions
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| path.repetition.is_none_or(|n| n == i + 1));
+            for (_, repetition) in repetitions {
+                values.extend(repetition_value(repetition, path, &delimiters));
+            }
+        }
+        values
+    }
+
+    /// Delimiters declared in MSH-1/MSH-2, or the defaults.
+    fn delimiters(&self) -> Delimiters {
+        let mut delimiters = Delimiters::default();
+        let Some(msh) = self.segments.first().filter(|s| s.name == "MSH") else {
+            return delimiters;
+        };
+        if let Some(sep) = msh.fields.first().and_then(|f| f.value.chars().next()) {
+            delimiters.field = sep;
+        }
+        if let Some(enc) = msh.fields.get(1) {
+            let mut chars = enc.value.chars();
+            if let Some(c) = chars.next() {
+                delimiters.component = c;
+            }
+            if let Some(c) = chars.next() {
+                delimiters.repetition = c;
+            }
+            if let Some(c) = chars.next() {
+                delimiters.escape = c;
+            }
+            if let Some(c) = chars.next() {
+                delimiters.subcomponent = c;
+            }
+        }
+        delimiters
+    }
+}
+
+/// Whole field value with escape sequences decoded
+fn field_text(field: &Hl7Field) -> String {
+    field
+        .unescaped
+        .clone()
+        .unwrap_or_else(|| field.value.clone())
+}
+
+/// The component/subcomponent of `path` within one repetition, or the whole
+/// repetition when no component is given.
+fn repetition_value(
+    repetition: &Hl7Repetition,
+    path: &FieldPath,
+    delimiters: &Delimiters,
+) -> Option<String> {
+    let sub = delimiters.subcomponent.to_string();
+    let Some(component) = path.component else {
+        return Some(
+            repetition
+                .components
+                .iter()
+                .map(|c| c.subcomponents.join(&sub))
+                .collect::<Vec<_>>()
+                .join(&delimiters.component.to_string()),
+        );
+    };
+    let component = repetition.component(component)?;
+    match path.subcomponent {
+        Some(n) => component.subcomponent(n).map(str::to_string),
+        None => Some(component.subcomponents.join(&sub)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hl7::parser::parse_message;
+
+    const MSG: &str = "MSH|^~\\&|APP|FAC|R|R|20240101||ORU^R01|CTRL1|P|2.5\r\
+                       PID|||123^^^HOSP&1.2.3&ISO^MR~456^^^LAB^PI||Doe^Jane\r\
+                       OBX|1|NM|GLU||5.4|mmol/L\r\
+                       OBX|2|NM|HB||14\r\
+                       OBX|3|ST|NOTE||A\\T\\B";
+
+    #[test]
+    fn test_parse_path() {
+        let path: FieldPath = "pid[2]-3[4].5.6".parse().unwrap();
+        assert_eq!(
+            path,
+            FieldPath {
+                segment: "PID".into(),
+                occurrence: Some(2),
+                field: 3,
+                repetition: Some(4),
+                component: Some(5),
+                subcomponent: Some(6),
+            }
+        );
+        assert_eq!(path.to_string(), "PID[2]-3[4].5.6");
+        assert_eq!("OBX-5".parse::<FieldPath>().unwrap().to_string(), "OBX-5");
+    }
+
+    #[test]
+    fn test_parse_path_errors() {
+        for bad in [
+            "PID",
+            "PID-",
+            "PID-0",
+            "PID-3.x",
+            "PI-3",
+            "PID[0]-3",
+            "PID-3.1.1.1",
+        ] {
+            assert!(bad.parse::<FieldPath>().is_err(), "{bad} should fail");
+        }
+    }
+
+    #[test]
+    fn test_query_values() {
+        let msg = parse_message(MSG, "127.0.0.1:9999").unwrap();
+        let q = |p: &str| msg.get_path(&p.parse().unwrap());
+
+        assert_eq!(q("MSH-10").as_deref(), Some("CTRL1"));
+        assert_eq!(q("MSH-9.2").as_deref(), Some("R01"));
+        assert_eq!(
+            q("PID-3").as_deref(),
+            Some("123^^^HOSP&1.2.3&ISO^MR~456^^^LAB^PI")
+        );
+        assert_eq!(q("PID-3[2]").as_deref(), Some("456^^^LAB^PI"));
+        assert_eq!(q("PID-3.4").as_deref(), Some("HOSP&1.2.3&ISO"));
+        assert_eq!(q("PID-3[1].4.2").as_deref(), Some("1.2.3"));
+        assert_eq!(q("PID-3[2].4.1").as_deref(), Some("LAB"));
+        assert_eq!(q("PID-5.2").as_deref(), Some("Jane"));
+        assert_eq!(q("OBX-5").as_deref(), Some("5.4"));
+        assert_eq!(q("OBX[2]-5").as_deref(), Some("14"));
+        assert_eq!(q("OBX[3]-5").as_deref(), Some("A&B"));
+
+        // Valid paths that point at nothing
+        assert_eq!(q("OBX[4]-5"), None);
+        assert_eq!(q("PID-3[3]"), None);
+        assert_eq!(q("PID-99"), None);
+        assert_eq!(q("PV1-2"), None);
+    }
+
+    #[test]
+    fn test_get_path_all() {
+        let msg = parse_message(MSG, "127.0.0.1:9999").unwrap();
+        let all = |p: &str| msg.get_path_all(&p.parse().unwrap());
+
+        assert_eq!(all("OBX-5"), vec!["5.4", "14", "A&B"]);
+        assert_eq!(all("OBX[2]-5"), vec!["14"]);
+        assert_eq!(all("PID-3.1"), vec!["123", "456"]);
+        assert_eq!(all("PID-3[2].1"), vec!["456"]);
+        assert_eq!(all("PID-3.4.1"), vec!["HOSP", "LAB"]);
+        assert!(all("NTE-3").is_empty());
+    }
+}
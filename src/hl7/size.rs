@@ -0,0 +1,245 @@
+/// Approximate memory footprint of a stored message, used for the store's
+/// `max_memory_mb` budget.
+///
+/// Counts the allocated capacity of every string and vector in the parsed
+/// tree plus the inline size of each element; hash maps are estimated from
+/// their capacity. Allocator overhead is not included, so real usage is a
+/// little higher. The store adds its own per-message bookkeeping (map keys
+/// and lookup indexes) as `index_bytes`.
+use super::types::{
+    AckLink, ComponentDescription, Hl7Component, Hl7Field, Hl7Message, Hl7Repetition, Hl7Segment,
+    ReplayRecord, SentAck,
+};
+use crate::validation::ValidationWarning;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::mem::size_of;
+use std::ops::{AddAssign, SubAssign};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct MemoryEstimate {
+    /// The raw message text
+    pub raw_bytes: usize,
+    /// Everything else: the message struct, parsed segments, descriptions,
+    /// warnings, tags and replay records
+    pub parsed_bytes: usize,
+    /// The store's copies of the message's IDs: its key in the message map
+    /// and its entries in the control-ID and patient-ID indexes
+    pub index_bytes: usize,
+}
+
+impl MemoryEstimate {
+    pub fn total(&self) -> usize {
+        self.raw_bytes + self.parsed_bytes + self.index_bytes
+    }
+}
+
+impl AddAssign for MemoryEstimate {
+    fn add_assign(&mut self, other: Self) {
+        self.raw_bytes += other.raw_bytes;
+        self.parsed_bytes += other.parsed_bytes;
+        self.index_bytes += other.index_bytes;
+    }
+}
+
+impl SubAssign for MemoryEstimate {
+    fn sub_assign(&mut self, other: Self) {
+        self.raw_bytes = self.raw_bytes.saturating_sub(other.raw_bytes);
+        self.parsed_bytes = self.parsed_bytes.saturating_sub(other.parsed_bytes);
+        self.index_bytes = self.index_bytes.saturating_sub(other.index_bytes);
+    }
+}
+
+impl Hl7Message {
+    pub fn memory_estimate(&self) -> MemoryEstimate {
+        let parsed = size_of::<Hl7Message>()
+            + self.id.heap_size()
+            + self.source_addr.heap_size()
+            + self.listener.heap_size()
+            + self.tls_peer_subject.heap_size()
+            + self.encoding.heap_size()
+            + self.message_type.heap_size()
+            + self.trigger_event.heap_size()
+            + self.message_control_id.heap_size()
+            + self.sending_application.heap_size()
+            + self.sending_facility.heap_size()
+            + self.receiving_application.heap_size()
+            + self.receiving_facility.heap_size()
+            + self.version.heap_size()
+            + self.segments.heap_size()
+            + self.patient_name.heap_size()
+            + self.patient_id.heap_size()
+            + self.parse_error.heap_size()
+            + self.ack_response.heap_size()
+            + self.ack_code.heap_size()
+            + self.tags.heap_size()
+            + self.validation_warnings.heap_size()
+            + self.message_type_description.heap_size()
+            + self.typical_segments.heap_size()
+            + self.typical_segment_descriptions.heap_size()
+            + self.replays.heap_size()
+            + self.duplicate_of.heap_size()
+            + self.ack_link.heap_size()
+            + self.acks_sent.heap_size();
+        MemoryEstimate {
+            raw_bytes: self.raw.heap_size(),
+            parsed_bytes: parsed,
+            index_bytes: 0,
+        }
+    }
+}
+
+/// Bytes owned on the heap, excluding the value's own inline size
+trait HeapSize {
+    fn heap_size(&self) -> usize;
+}
+
+impl HeapSize for String {
+    fn heap_size(&self) -> usize {
+        self.capacity()
+    }
+}
+
+impl<T: HeapSize> HeapSize for Option<T> {
+    fn heap_size(&self) -> usize {
+        self.as_ref().map_or(0, HeapSize::heap_size)
+    }
+}
+
+impl<T: HeapSize> HeapSize for Vec<T> {
+    fn heap_size(&self) -> usize {
+        self.capacity() * size_of::<T>() + self.iter().map(HeapSize::heap_size).sum::<usize>()
+    }
+}
+
+impl HeapSize for HashMap<String, String> {
+    fn heap_size(&self) -> usize {
+        // hashbrown stores (K, V) pairs plus one control byte per bucket
+        self.capacity() * (size_of::<(String, String)>() + 1)
+            + self
+                .iter()
+                .map(|(k, v)| k.heap_size() + v.heap_size())
+                .sum::<usize>()
+    }
+}
+
+impl HeapSize for Hl7Segment {
+    fn heap_size(&self) -> usize {
+        self.name.heap_size()
+            + self.fields.heap_size()
+            + self.raw.heap_size()
+            + self.description.heap_size()
+    }
+}
+
+impl HeapSize for Hl7Field {
+    fn heap_size(&self) -> usize {
+        self.value.heap_size()
+            + self.unescaped.heap_size()
+            + self.components.heap_size()
+            + self.repetitions.heap_size()
+            + self.description.heap_size()
+            + self.datatype.heap_size()
+            + self.table.heap_size()
+            + self.component_descriptions.heap_size()
+    }
+}
+
+impl HeapSize for ComponentDescription {
+    fn heap_size(&self) -> usize {
+        self.description.heap_size() + self.datatype.heap_size() + self.subcomponents.heap_size()
+    }
+}
+
+impl HeapSize for Hl7Repetition {
+    fn heap_size(&self) -> usize {
+        self.components.heap_size()
+    }
+}
+
+impl HeapSize for Hl7Component {
+    fn heap_size(&self) -> usize {
+        self.subcomponents.heap_size()
+    }
+}
+
+impl HeapSize for ValidationWarning {
+    fn heap_size(&self) -> usize {
+        self.code.heap_size() + self.message.heap_size() + self.segment.heap_size()
+    }
+}
+
+impl HeapSize for ReplayRecord {
+    fn heap_size(&self) -> usize {
+        self.target.heap_size()
+            + self.target_name.heap_size()
+            + self.ack_response.heap_size()
+            + self.ack_code.heap_size()
+            + self.error.heap_size()
+    }
+}
+
+impl HeapSize for AckLink {
+    fn heap_size(&self) -> usize {
+        self.counterpart_id.heap_size() + self.ack_code.heap_size()
+    }
+}
+
+impl HeapSize for SentAck {
+    fn heap_size(&self) -> usize {
+        self.code.heap_size()
+            + self.message.heap_size()
+            + self.target.heap_size()
+            + self.error.heap_size()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hl7::parser::parse_message;
+
+    const ADT: &str = "MSH|^~\\&|APP|FAC|R|R|20240101||ADT^A01|1|P|2.5\r\
+                       PID|||123^^^HOSP~456^^^LAB||Doe^Jane||19800101|F\r\
+                       PV1||I|WARD1^101^A";
+
+    #[test]
+    fn test_parsed_tree_dominates_raw() {
+        let msg = parse_message(ADT, "127.0.0.1:9999").unwrap();
+        let estimate = msg.memory_estimate();
+        assert_eq!(estimate.raw_bytes, msg.raw.capacity());
+        // Segment copies, field values, nested structure and descriptions
+        // easily outweigh the raw text several times over
+        assert!(
+            estimate.parsed_bytes > estimate.raw_bytes * 3,
+            "{estimate:?}"
+        );
+    }
+
+    #[test]
+    fn test_estimate_grows_with_content() {
+        let mut msg = parse_message(ADT, "127.0.0.1:9999").unwrap();
+        let before = msg.memory_estimate();
+        msg.tags.push("a-fairly-long-tag-name".into());
+        let after = msg.memory_estimate();
+        assert_eq!(after.raw_bytes, before.raw_bytes);
+        assert!(after.parsed_bytes > before.parsed_bytes);
+    }
+
+    #[test]
+    fn test_add_sub() {
+        let mut total = MemoryEstimate::default();
+        let one = MemoryEstimate {
+            raw_bytes: 10,
+            parsed_bytes: 40,
+            index_bytes: 5,
+        };
+        total += one;
+        total += one;
+        assert_eq!(total.total(), 110);
+        total -= one;
+        total -= one;
+        total -= one;
+        assert_eq!(total, MemoryEstimate::default());
+    }
+}
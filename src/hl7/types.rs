@@ -10,9 +10,19 @@ pub struct Hl7Message {
     pub raw: String,
     pub received_at: DateTime<Utc>,
     pub source_addr: String,
+    /// Label of the MLLP listener that received the message
+    pub listener: String,
+    /// Subject DN of the verified client certificate (MLLP/S with client auth)
+    pub tls_peer_subject: Option<String>,
+    /// Character encoding the frame was decoded with (e.g. `"windows-1252"`),
+    /// from MSH-18 or the listener default
+    #[serde(default)]
+    pub encoding: Option<String>,
     pub message_type: String,  // e.g. "ADT^A01"
     pub trigger_event: String, // e.g. "A01"
     pub message_control_id: String,
+    /// MSH-3 to MSH-6 exactly as received, component separators and escape
+    /// sequences included
     pub sending_application: String,
     pub sending_facility: String,
     pub receiving_application: String,
@@ -34,6 +44,84 @@ pub struct Hl7Message {
     pub typical_segments: Vec<String>,
     /// Description for each typical segment name, from the embedded dictionary
     pub typical_segment_descriptions: HashMap<String, String>,
+    /// Outbound replays of this message, oldest first
+    pub replays: Vec<ReplayRecord>,
+    /// Segments and typical-segment details were dropped after ingest
+    /// (`parse_mode = "lazy"`) and must be re-parsed from `raw`
+    #[serde(default)]
+    pub detail_deferred: bool,
+    /// ID of the earlier message this one repeats (same MSH-10 or raw content
+    /// from the same host within the duplicate window)
+    #[serde(default)]
+    pub duplicate_of: Option<String>,
+    /// The inbound ACK this message received, or (on an ACK) the message it
+    /// acknowledges
+    #[serde(default)]
+    pub ack_link: Option<AckLink>,
+    /// Acknowledgments HL7 Forge sent for this message, in order
+    #[serde(default)]
+    pub acks_sent: Vec<SentAck>,
+}
+
+/// Link between a message and an inbound ACK that answered it, matched by the
+/// ACK's MSA-2 against the message's MSH-10
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AckLink {
+    /// The ACK (when set on a message) or the acknowledged message (on an ACK)
+    pub counterpart_id: String,
+    /// MSA-1 of the ACK
+    pub ack_code: Option<String>,
+    /// Time from receiving the message to receiving its ACK
+    pub round_trip_ms: i64,
+}
+
+impl AckLink {
+    /// AA/AE/AR, as opposed to the commit-level CA/CE/CR
+    pub fn is_application_ack(&self) -> bool {
+        matches!(self.ack_code.as_deref(), Some("AA" | "AE" | "AR"))
+    }
+}
+
+/// An acknowledgment HL7 Forge sent for a received message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SentAck {
+    pub kind: AckKind,
+    /// MSA-1 of the ACK
+    pub code: String,
+    pub sent_at: DateTime<Utc>,
+    pub message: String,
+    /// Outbound target an application ACK was sent to
+    pub target: Option<String>,
+    /// Write or connection error, if the ACK may not have arrived
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AckKind {
+    /// Original mode (AA/AE/AR) on the receiving connection
+    Original,
+    /// Enhanced mode accept acknowledgment (CA/CE/CR) on the receiving connection
+    Commit,
+    /// Enhanced mode application acknowledgment (AA/AE/AR) over an outbound connection
+    Application,
+}
+
+/// One replay of a stored message to a remote MLLP receiver
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayRecord {
+    /// Target address (`host:port`) the message was sent to
+    pub target: String,
+    /// Name of the configured target, if one was used
+    pub target_name: Option<String>,
+    pub sent_at: DateTime<Utc>,
+    /// Round-trip time from connect until the ACK frame was complete
+    pub latency_ms: Option<u64>,
+    pub ack_response: Option<String>,
+    /// MSA-1 of the returned ACK
+    pub ack_code: Option<String>,
+    /// Connection or protocol error, if the replay failed
+    pub error: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -48,9 +136,77 @@ pub struct Hl7Segment {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Hl7Field {
     pub index: usize,
+    /// Field value exactly as received, escape sequences included
     pub value: String,
+    /// Field value with escape sequences decoded; `None` when identical to `value`
+    pub unescaped: Option<String>,
+    /// Components of the first repetition, escape sequences decoded
     pub components: Vec<String>,
+    /// Full structure: repetitions → components → subcomponents (empty for an empty field)
+    #[serde(default)]
+    pub repetitions: Vec<Hl7Repetition>,
     pub description: Option<String>,
+    /// Data type from the HL7 dictionary (e.g. "XPN")
+    #[serde(default)]
+    pub datatype: Option<String>,
+    /// Code table from the HL7 dictionary (e.g. "0001" for PID-8)
+    #[serde(default)]
+    pub table: Option<String>,
+    /// Names of the components present, by position (only for composite types
+    /// with more than one component or subcomponent in the message)
+    #[serde(default)]
+    pub component_descriptions: Vec<ComponentDescription>,
+}
+
+impl Hl7Field {
+    /// Repetition by 1-based number
+    pub fn repetition(&self, number: usize) -> Option<&Hl7Repetition> {
+        self.repetitions.get(number.checked_sub(1)?)
+    }
+}
+
+/// One occurrence of a repeating field (separated by `~`).
+/// Serialized as a plain array of components.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Hl7Repetition {
+    pub components: Vec<Hl7Component>,
+}
+
+impl Hl7Repetition {
+    /// Component by 1-based number
+    pub fn component(&self, number: usize) -> Option<&Hl7Component> {
+        self.components.get(number.checked_sub(1)?)
+    }
+}
+
+/// One component (separated by `^`), split into its subcomponents (separated
+/// by `&`) with escape sequences decoded. Serialized as a plain array of strings.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Hl7Component {
+    pub subcomponents: Vec<String>,
+}
+
+impl Hl7Component {
+    /// Subcomponent by 1-based number
+    pub fn subcomponent(&self, number: usize) -> Option<&str> {
+        self.subcomponents
+            .get(number.checked_sub(1)?)
+            .map(String::as_str)
+    }
+}
+
+/// Dictionary annotation for one component of a composite field
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ComponentDescription {
+    /// Component name (e.g. "Given Name" for XPN.2)
+    pub description: String,
+    pub datatype: String,
+    /// Subcomponent names when the component is itself composite and
+    /// arrived split by `&` (e.g. HD's "Namespace ID", "Universal ID", …)
+    #[serde(default)]
+    pub subcomponents: Vec<String>,
 }
 
 /// Separators / encoding characters from MSH-1 and MSH-2
@@ -82,6 +238,9 @@ impl Hl7Message {
             raw,
             received_at: Utc::now(),
             source_addr,
+            listener: String::new(),
+            tls_peer_subject: None,
+            encoding: None,
             message_type: String::new(),
             trigger_event: String::new(),
             message_control_id: String::new(),
@@ -102,8 +261,28 @@ impl Hl7Message {
             message_type_description: None,
             typical_segments: Vec::new(),
             typical_segment_descriptions: HashMap::new(),
+            replays: Vec::new(),
+            detail_deferred: false,
+            duplicate_of: None,
+            ack_link: None,
+            acks_sent: Vec::new(),
         }
     }
+
+    pub fn is_ack(&self) -> bool {
+        self.message_type.starts_with("ACK")
+    }
+
+    /// True for a parsed, non-ACK message that no application ACK has been
+    /// linked to yet
+    pub fn awaiting_application_ack(&self) -> bool {
+        self.parse_error.is_none()
+            && !self.is_ack()
+            && !self
+                .ack_link
+                .as_ref()
+                .is_some_and(AckLink::is_application_ack)
+    }
 }
 
 /// Summary for the message list (lightweight, no raw/segments)
@@ -112,6 +291,7 @@ pub struct Hl7MessageSummary {
     pub id: String,
     pub received_at: DateTime<Utc>,
     pub source_addr: String,
+    pub listener: String,
     pub message_type: String,
     pub trigger_event: String,
     pub message_control_id: String,
@@ -129,6 +309,12 @@ pub struct Hl7MessageSummary {
     /// True when at least one warning is a MISSING_SEGMENT error (badge turns red)
     pub has_segment_errors: bool,
     pub message_type_description: Option<String>,
+    /// Number of outbound replays recorded for this message
+    pub replay_count: usize,
+    #[serde(default)]
+    pub duplicate_of: Option<String>,
+    #[serde(default)]
+    pub ack_link: Option<AckLink>,
 }
 
 impl From<&Hl7Message> for Hl7MessageSummary {
@@ -137,13 +323,14 @@ impl From<&Hl7Message> for Hl7MessageSummary {
             id: msg.id.clone(),
             received_at: msg.received_at,
             source_addr: msg.source_addr.clone(),
+            listener: msg.listener.clone(),
             message_type: msg.message_type.clone(),
             trigger_event: msg.trigger_event.clone(),
             message_control_id: msg.message_control_id.clone(),
             sending_facility: msg.sending_facility.clone(),
             patient_name: msg.patient_name.clone(),
             patient_id: msg.patient_id.clone(),
-            segment_count: msg.segments.len(),
+            segment_count: msg.segment_count(),
             parse_error: msg.parse_error.clone(),
             ack_response: msg.ack_response.clone(),
             ack_code: msg.ack_code.clone(),
@@ -155,6 +342,9 @@ impl From<&Hl7Message> for Hl7MessageSummary {
                 .iter()
                 .any(|w| w.code == "MISSING_SEGMENT"),
             message_type_description: msg.message_type_description.clone(),
+            replay_count: msg.replays.len(),
+            duplicate_of: msg.duplicate_of.clone(),
+            ack_link: msg.ack_link.clone(),
         }
     }
 }
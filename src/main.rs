@@ -1,17 +1,22 @@
+mod code_tables;
 mod config;
 mod dictionary;
 mod hl7;
 mod mllp;
+mod mllp_client;
 mod store;
+mod timeline;
+mod tls;
 mod validation;
 mod web;
 
+use anyhow::Context;
 use config::Config;
-use mllp::MllpStats;
+use mllp::{ApplicationAckSender, MllpStats};
 use store::MessageStore;
 use tracing::{info, warn};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
-use web::{create_router, AppState};
+use web::{create_router, AppState, ListenerInfo};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -54,10 +59,17 @@ async fn main() -> anyhow::Result<()> {
         .with(file_layer)
         .init();
 
-    let mllp_port = config.server.mllp_port;
+    let listeners = config.effective_listeners();
     let web_port = config.server.web_port;
 
-    let store = MessageStore::new(config.store.clone());
+    // Site-defined code tables must be in place before any message is validated
+    let user_tables = code_tables::install_user_tables(&config.validation.user_tables)
+        .context("Failed to load user code tables")?;
+    if user_tables > 0 {
+        info!("Loaded {} user code table(s)", user_tables);
+    }
+
+    let store = MessageStore::new(config.store.clone()).context("Failed to open message store")?;
     let stats = MllpStats::new();
 
     info!("╔══════════════════════════════════════════╗");
@@ -66,33 +78,94 @@ async fn main() -> anyhow::Result<()> {
         env!("CARGO_PKG_VERSION")
     );
     info!("╠══════════════════════════════════════════╣");
-    info!("║  MLLP Server:  0.0.0.0:{}              ║", mllp_port);
+    for listener in &listeners {
+        info!("║  MLLP Server:  {:<26}║", listener.bind_addr());
+    }
     info!("║  Web UI:       http://localhost:{}     ║", web_port);
     info!("╚══════════════════════════════════════════╝");
     info!("Effective configuration:\n{}", config);
 
     let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
 
-    // Start MLLP server
-    let mllp_store = store.clone();
-    let mllp_stats = stats.clone();
-    let mllp_shutdown = shutdown_rx.clone();
-    let mllp_config = config.mllp.clone();
-    let mllp_handle = tokio::spawn(async move {
-        let addr = format!("0.0.0.0:{}", mllp_port);
-        if let Err(e) =
-            mllp::start_mllp_server(&addr, mllp_store, mllp_stats, mllp_shutdown, mllp_config).await
-        {
-            warn!("MLLP server error: {}", e);
+    // Start the retention sweeper when any retention rule is configured
+    let retention = config.store.retention.clone();
+    if retention.is_enabled() {
+        tokio::spawn(store.clone().run_sweeper(retention, shutdown_rx.clone()));
+    }
+
+    // Enhanced-mode application ACKs go to a configured outbound target
+    let application_acks = config
+        .mllp
+        .ack
+        .application_ack_target
+        .as_deref()
+        .map(|name| {
+            config
+                .outbound
+                .target(name)
+                .map(|target| ApplicationAckSender::new(target.clone(), config.outbound.clone()))
+                .with_context(|| {
+                    format!("[mllp.ack] application_ack_target '{}' is not an [[outbound.targets]] entry", name)
+                })
+        })
+        .transpose()?;
+
+    // Start one MLLP server per listener
+    let mut mllp_tasks = tokio::task::JoinSet::new();
+    for listener in &listeners {
+        let mllp_store = store.clone();
+        let mllp_stats = stats.clone();
+        let mllp_shutdown = shutdown_rx.clone();
+        let mllp_config = listener.mllp_config(&config.mllp);
+        let application_acks = application_acks.clone();
+        let addr = listener.bind_addr();
+        let label = listener.label();
+        if let Some(charset) = &mllp_config.charset {
+            anyhow::ensure!(
+                hl7::charset::encoding_for(charset).is_some(),
+                "Unsupported charset '{}' for listener '{}'",
+                charset,
+                label
+            );
         }
-    });
+        let tls = listener
+            .tls
+            .as_ref()
+            .map(tls::build_acceptor)
+            .transpose()
+            .with_context(|| format!("TLS setup failed for listener '{}'", label))?;
+        mllp_tasks.spawn(async move {
+            if let Err(e) = mllp::start_mllp_server(
+                &addr,
+                &label,
+                mllp_store,
+                mllp_stats,
+                mllp_shutdown,
+                mllp_config,
+                tls,
+                application_acks,
+            )
+            .await
+            {
+                warn!("MLLP server '{}' error: {}", label, e);
+            }
+        });
+    }
 
     // Start Web server
     let app_state = AppState {
         store: store.clone(),
         stats: stats.clone(),
-        mllp_port,
-        max_connections: config.mllp.max_connections,
+        listeners: listeners
+            .iter()
+            .map(|l| ListenerInfo {
+                label: l.label(),
+                bind_addr: l.bind_addr(),
+                port: l.port,
+                max_connections: l.mllp_config(&config.mllp).max_connections,
+            })
+            .collect(),
+        outbound: config.outbound.clone(),
     };
     let app = create_router(app_state);
     let web_addr = format!("0.0.0.0:{}", web_port);
@@ -110,7 +183,7 @@ async fn main() -> anyhow::Result<()> {
 
     // Wait for a shutdown signal or an unexpected server exit
     tokio::select! {
-        _ = mllp_handle => {
+        _ = mllp_tasks.join_next() => {
             warn!("MLLP server stopped unexpectedly, initiating shutdown");
         }
         _ = web_handle => {
@@ -1,14 +1,20 @@
-use crate::config::MllpConfig;
-use crate::hl7::parser::{build_ack, parse_message};
-use crate::hl7::types::Hl7Message;
+use crate::config::{AckCode, AckConfig, AckRule, MllpConfig, OutboundConfig, OutboundTarget};
+use crate::hl7::ack::{build_ack, build_parse_error_nack, recover_header, AckMode};
+use crate::hl7::charset;
+use crate::hl7::parser::{parse_message, parse_message_unannotated};
+use crate::hl7::types::{AckKind, Hl7Message, SentAck};
+use crate::mllp_client;
 use crate::store::MessageStore;
+use chrono::Utc;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::TcpListener;
 use tokio::sync::watch;
 use tokio::sync::Semaphore;
 use tokio::time::timeout;
+use tokio_rustls::TlsAcceptor;
 use tracing::{debug, info, warn};
 
 /// MLLP framing constants
@@ -38,28 +44,105 @@ impl MllpStats {
     }
 }
 
+/// Per-connection metadata recorded on every message received over it
+struct ConnectionInfo {
+    peer: String,
+    listener: Arc<str>,
+    /// Subject of the verified client certificate (mutual TLS only)
+    tls_peer_subject: Option<String>,
+}
+
+impl ConnectionInfo {
+    fn stamp(&self, msg: &mut Hl7Message) {
+        msg.listener = self.listener.to_string();
+        msg.tls_peer_subject = self.tls_peer_subject.clone();
+    }
+}
+
+/// Destination for enhanced-mode application ACKs
+/// (`[mllp.ack] application_ack_target`)
+#[derive(Clone)]
+pub struct ApplicationAckSender {
+    target: OutboundTarget,
+    outbound: OutboundConfig,
+}
+
+impl ApplicationAckSender {
+    pub fn new(target: OutboundTarget, outbound: OutboundConfig) -> Self {
+        Self { target, outbound }
+    }
+
+    /// Send `ack` for the stored message `id` in the message's `encoding`
+    /// and record the outcome on it
+    async fn send(
+        self,
+        store: MessageStore,
+        id: String,
+        code: &'static str,
+        ack: String,
+        encoding: Option<String>,
+    ) {
+        let addr = self.target.addr();
+        let sent_at = Utc::now();
+        let bytes = charset::encode(&ack, encoding.as_deref());
+        let error = match mllp_client::send_message(&addr, &bytes, &self.outbound).await {
+            Ok(_) => None,
+            Err(e) => {
+                warn!("Failed to send application ACK to {}: {}", addr, e);
+                Some(e.to_string())
+            }
+        };
+        let record = SentAck {
+            kind: AckKind::Application,
+            code: code.to_string(),
+            sent_at,
+            message: ack,
+            target: Some(self.target.name.clone()),
+            error,
+        };
+        if !store.record_sent_ack(&id, record).await {
+            debug!(
+                "Message {} was removed before its application ACK was recorded",
+                id
+            );
+        }
+    }
+}
+
 /// Start the MLLP TCP server
+///
+/// `label` identifies the listener and is recorded on every message it receives.
+/// When `tls` is set, every connection must complete a TLS handshake first (MLLP/S).
+/// Enhanced-mode application ACKs go to `application_acks` when set.
+#[allow(clippy::too_many_arguments)]
 pub async fn start_mllp_server(
     bind_addr: &str,
+    label: &str,
     store: MessageStore,
     stats: MllpStats,
     mut shutdown: watch::Receiver<bool>,
     config: MllpConfig,
+    tls: Option<TlsAcceptor>,
+    application_acks: Option<ApplicationAckSender>,
 ) -> anyhow::Result<()> {
     let max_connections = config.max_connections;
     let semaphore = Arc::new(Semaphore::new(max_connections));
     let config = Arc::new(config);
+    let label: Arc<str> = Arc::from(label);
     let listener = TcpListener::bind(bind_addr).await?;
     info!(
-        "MLLP server listening on {} (max {} connections)",
-        bind_addr, max_connections
+        "MLLP{} server '{}' listening on {} (max {} connections)",
+        if tls.is_some() { "/S" } else { "" },
+        label,
+        bind_addr,
+        max_connections
     );
 
     loop {
         tokio::select! {
             biased;
             _ = shutdown.changed() => {
-                info!("MLLP server shutting down gracefully");
+                info!("MLLP server '{}' shutting down gracefully", label);
                 break;
             }
             result = listener.accept() => {
@@ -67,6 +150,9 @@ pub async fn start_mllp_server(
                 let store = store.clone();
                 let stats = stats.clone();
                 let config = Arc::clone(&config);
+                let label = Arc::clone(&label);
+                let tls = tls.clone();
+                let application_acks = application_acks.clone();
                 let peer = peer_addr.to_string();
                 let shutdown_clone = shutdown.clone();
 
@@ -74,10 +160,28 @@ pub async fn start_mllp_server(
                 match Arc::clone(&semaphore).try_acquire_owned() {
                     Ok(permit) => {
                         stats.active_connections.fetch_add(1, Ordering::Relaxed);
-                        info!("New MLLP connection from {}", peer);
+                        info!("New MLLP connection from {} on '{}'", peer, label);
 
                         tokio::spawn(async move {
-                            if let Err(e) = handle_connection(socket, &peer, &store, &stats, &config, shutdown_clone).await {
+                            let mut conn = ConnectionInfo {
+                                peer: peer.clone(),
+                                listener: label,
+                                tls_peer_subject: None,
+                            };
+                            let result = match tls {
+                                Some(acceptor) => {
+                                    match timeout(config.read_timeout(), acceptor.accept(socket)).await {
+                                        Ok(Ok(stream)) => {
+                                            conn.tls_peer_subject = crate::tls::peer_subject(stream.get_ref().1);
+                                            handle_connection(stream, &conn, &store, &stats, &config, application_acks, shutdown_clone).await
+                                        }
+                                        Ok(Err(e)) => Err(anyhow::anyhow!("TLS handshake failed: {}", e)),
+                                        Err(_) => Err(anyhow::anyhow!("TLS handshake timed out")),
+                                    }
+                                }
+                                None => handle_connection(socket, &conn, &store, &stats, &config, application_acks, shutdown_clone).await,
+                            };
+                            if let Err(e) = result {
                                 warn!("Connection error from {}: {}", peer, e);
                             }
                             stats.active_connections.fetch_sub(1, Ordering::Relaxed);
@@ -88,8 +192,8 @@ pub async fn start_mllp_server(
                     Err(_) => {
                         stats.rejected_connections.fetch_add(1, Ordering::Relaxed);
                         warn!(
-                            "Connection from {} rejected: max connections ({}) reached",
-                            peer, max_connections
+                            "Connection from {} rejected: max connections ({}) reached on '{}'",
+                            peer, max_connections, label
                         );
                         // Socket is dropped here, closing the TCP connection
                         drop(socket);
@@ -102,19 +206,25 @@ pub async fn start_mllp_server(
     Ok(())
 }
 
-async fn handle_connection(
-    mut socket: tokio::net::TcpStream,
-    peer: &str,
+async fn handle_connection<S>(
+    mut socket: S,
+    conn: &ConnectionInfo,
     store: &MessageStore,
     stats: &MllpStats,
     config: &MllpConfig,
+    application_acks: Option<ApplicationAckSender>,
     mut shutdown: tokio::sync::watch::Receiver<bool>,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let peer = conn.peer.as_str();
     let mut buf = vec![0u8; 64 * 1024]; // 64 KB read buffer
     let mut accumulated = Vec::with_capacity(8 * 1024);
     let max_size = config.max_message_size();
     let read_timeout = config.read_timeout();
     let write_timeout = config.write_timeout();
+    let default_charset = config.charset.as_deref().and_then(charset::encoding_for);
 
     let mut shutdown_requested = false;
 
@@ -155,54 +265,167 @@ async fn handle_connection(
         }
 
         // Process all complete MLLP frames in the buffer
-        while let Some((message, consumed)) = extract_mllp_frame(&accumulated) {
+        while let Some((frame, consumed)) = extract_mllp_frame(&accumulated) {
             stats.received.fetch_add(1, Ordering::Relaxed);
 
-            match parse_message(&message, peer) {
+            let decoded = charset::decode(frame, default_charset);
+            let encoding = decoded.encoding.name();
+            let charset_warnings = decoded.warnings();
+            for warning in &charset_warnings {
+                warn!("{} from {}", warning.message, peer);
+            }
+            let message = decoded.text;
+
+            // Lazy stores drop the segment detail on insert, so the
+            // dictionary descriptions are only added when a message is opened
+            let parsed = if store.is_lazy() {
+                parse_message_unannotated(&message, peer)
+            } else {
+                parse_message(&message, peer)
+            };
+            match parsed {
                 Ok(mut msg) => {
                     stats.parsed_ok.fetch_add(1, Ordering::Relaxed);
+                    conn.stamp(&mut msg);
+                    msg.encoding = Some(encoding.to_string());
+                    msg.validation_warnings.extend(charset_warnings);
+                    // Record the message now: a retry sent while this one's
+                    // ACK is delayed must already count as a duplicate
+                    store.check_duplicate(&mut msg);
+
+                    // Enhanced-mode application ACK, sent once the message is stored
+                    let mut application_ack = None;
 
                     // Never ACK an ACK — doing so would create an ACK storm
-                    if msg.message_type.starts_with("ACK") {
+                    if msg.is_ack() {
                         debug!(
                             "Received ACK message from {}, suppressing response to avoid ACK storm",
                             peer
                         );
                     } else {
-                        // Build and send ACK
-                        let ack = build_ack(&msg, "AA");
-                        msg.ack_response = Some(ack.clone());
-                        msg.ack_code = Some("AA".to_string());
-                        let ack_frame = wrap_mllp(&ack);
-                        match timeout(write_timeout, socket.write_all(&ack_frame)).await {
-                            Ok(Ok(())) => {}
-                            Ok(Err(e)) => warn!("Failed to send ACK to {}: {}", peer, e),
-                            Err(_) => warn!("Write timeout sending ACK to {}", peer),
+                        let duplicate_code = config
+                            .ack
+                            .duplicate_code
+                            .filter(|_| msg.duplicate_of.is_some());
+                        let rule =
+                            select_ack_rule(&config.ack, &msg, || rand::random::<f64>() * 100.0);
+                        let (code, delay) = match (duplicate_code, rule) {
+                            (Some(code), _) => (code, Duration::ZERO),
+                            (None, Some(rule)) => (rule.code, rule.delay()),
+                            (None, None) => (AckCode::AA, Duration::ZERO),
+                        };
+                        let (reply, application_code) = plan_acks(msg.ack_mode(), code);
+
+                        if !delay.is_zero() {
+                            debug!("Delaying ACK to {} by {:?}", peer, delay);
+                            tokio::time::sleep(delay).await;
+                        }
+
+                        match reply {
+                            Some((kind, code)) => {
+                                // Build and send ACK
+                                let ack = build_ack(&msg, code);
+                                let ack_frame = wrap_mllp(charset::encode(&ack, Some(encoding)));
+                                let sent_at = Utc::now();
+                                let error = match timeout(
+                                    write_timeout,
+                                    socket.write_all(&ack_frame),
+                                )
+                                .await
+                                {
+                                    Ok(Ok(())) => None,
+                                    Ok(Err(e)) => {
+                                        warn!("Failed to send ACK to {}: {}", peer, e);
+                                        Some(e.to_string())
+                                    }
+                                    Err(_) => {
+                                        warn!("Write timeout sending ACK to {}", peer);
+                                        Some("Write timeout".to_string())
+                                    }
+                                };
+                                msg.acks_sent.push(SentAck {
+                                    kind,
+                                    code: code.to_string(),
+                                    sent_at,
+                                    message: ack.clone(),
+                                    target: None,
+                                    error,
+                                });
+                                msg.ack_response = Some(ack);
+                                msg.ack_code = Some(code.to_string());
+                            }
+                            None => {
+                                debug!("No ACK sent to {} for {}", peer, msg.message_control_id);
+                            }
+                        }
+
+                        if let Some(code) = application_code {
+                            match &application_acks {
+                                Some(sender) => {
+                                    application_ack =
+                                        Some((sender.clone(), code, build_ack(&msg, code)))
+                                }
+                                None => debug!(
+                                    "No application_ack_target configured, skipping {} application ACK for {}",
+                                    code, msg.message_control_id
+                                ),
+                            }
                         }
                     }
 
                     // Store the message (async, non-blocking for the connection)
+                    let id = msg.id.clone();
                     store.insert(msg).await;
+
+                    if let Some((sender, code, ack)) = application_ack {
+                        tokio::spawn(sender.send(
+                            store.clone(),
+                            id,
+                            code,
+                            ack,
+                            Some(encoding.to_string()),
+                        ));
+                    }
                 }
                 Err(e) => {
                     stats.parse_errors.fetch_add(1, Ordering::Relaxed);
                     warn!("Parse error from {}: {}", peer, e);
 
-                    // Send NACK (AE = Application Error)
-                    let nack =
-                        "MSH|^~\\&|HL7Forge|HL7Forge|||||ACK||P|2.5\rMSA|AE|UNKNOWN|Message parse error"
-                            .to_string();
+                    // NACK with AE, or CE when the recovered header asks for
+                    // enhanced mode and MSH-15 allows an error commit ACK
+                    let header = recover_header(&message);
+                    let (reply, _) = plan_acks(header.ack_mode(), AckCode::AE);
 
                     // Store the failed message so it is visible in the UI
                     let mut failed = Hl7Message::new_empty(message.clone(), peer.to_string());
+                    conn.stamp(&mut failed);
+                    failed.encoding = Some(encoding.to_string());
+                    failed.validation_warnings = charset_warnings;
                     failed.message_type = "UNKNOWN".to_string();
                     failed.parse_error = Some(e.clone());
-                    failed.ack_response = Some(nack.clone());
-                    failed.ack_code = Some("AE".to_string());
+                    let nack = reply.map(|(kind, code)| {
+                        let nack = build_parse_error_nack(&header, code, &e);
+                        failed.acks_sent.push(SentAck {
+                            kind,
+                            code: code.to_string(),
+                            sent_at: Utc::now(),
+                            message: nack.clone(),
+                            target: None,
+                            error: None,
+                        });
+                        failed.ack_response = Some(nack.clone());
+                        failed.ack_code = Some(code.to_string());
+                        nack
+                    });
                     store.insert(failed).await;
 
-                    let nack_frame = wrap_mllp(&nack);
-                    let _ = timeout(write_timeout, socket.write_all(&nack_frame)).await;
+                    match nack {
+                        Some(nack) => {
+                            let nack_frame = wrap_mllp(charset::encode(&nack, Some(encoding)));
+                            let _ = timeout(write_timeout, socket.write_all(&nack_frame)).await;
+                        }
+                        None => debug!("No NACK sent to {} for unparseable message", peer),
+                    }
                 }
             }
 
@@ -219,29 +442,78 @@ async fn handle_connection(
     Ok(())
 }
 
+/// Pick the first ACK rule that matches the message.
+///
+/// `roll` yields values in `0.0..100.0` and is called once per matching rule,
+/// which applies when its roll falls below its percentage; otherwise the next
+/// matching rule gets its own chance. Returns `None` when the default (`AA`,
+/// no delay) applies.
+fn select_ack_rule<'a>(
+    config: &'a AckConfig,
+    msg: &Hl7Message,
+    mut roll: impl FnMut() -> f64,
+) -> Option<&'a AckRule> {
+    config
+        .rules
+        .iter()
+        .filter(|r| r.matches(&msg.message_type, &msg.sending_facility))
+        .find(|r| roll() < r.percentage)
+}
+
+/// Decide which ACKs answer a message given the code chosen by the ACK rules.
+///
+/// Returns the ACK to write back on the connection and the code of an
+/// application ACK to send separately. Original mode sends `code` as is;
+/// enhanced mode sends its commit-level equivalent when MSH-15 allows it,
+/// and an application ACK when MSH-16 allows it. A `CE`/`CR` commit means the
+/// message was not accepted, so no application ACK follows it.
+fn plan_acks(
+    mode: AckMode,
+    code: AckCode,
+) -> (Option<(AckKind, &'static str)>, Option<&'static str>) {
+    match mode {
+        AckMode::Original => (code.as_str().map(|c| (AckKind::Original, c)), None),
+        AckMode::Enhanced {
+            accept,
+            application,
+        } => {
+            let commit = code.commit();
+            let reply = commit
+                .as_str()
+                .filter(|_| accept.applies(commit == AckCode::CA))
+                .map(|c| (AckKind::Commit, c));
+            let app_code = code
+                .application()
+                .as_str()
+                .filter(|_| commit == AckCode::CA && application.applies(true));
+            (reply, app_code)
+        }
+    }
+}
+
 /// Extract one complete MLLP frame from the buffer.
-/// Returns (message_content, bytes_consumed) or None if incomplete.
-fn extract_mllp_frame(buf: &[u8]) -> Option<(String, usize)> {
+/// Returns (message_bytes, bytes_consumed) or None if incomplete. The bytes
+/// are not decoded; see [`charset::decode`].
+pub(crate) fn extract_mllp_frame(buf: &[u8]) -> Option<(&[u8], usize)> {
     // Find start byte
     let start_pos = buf.iter().position(|&b| b == MLLP_START)?;
 
     // Find end sequence (FS + CR)
     for i in (start_pos + 1)..buf.len().saturating_sub(1) {
         if buf[i] == MLLP_END_1 && buf[i + 1] == MLLP_END_2 {
-            let message_bytes = &buf[start_pos + 1..i];
-            let message = String::from_utf8_lossy(message_bytes).to_string();
-            return Some((message, i + 2));
+            return Some((&buf[start_pos + 1..i], i + 2));
         }
     }
 
     None // Incomplete frame
 }
 
-/// Wrap a message in MLLP framing
-fn wrap_mllp(message: &str) -> Vec<u8> {
+/// Wrap an encoded message in MLLP framing
+pub(crate) fn wrap_mllp(message: impl AsRef<[u8]>) -> Vec<u8> {
+    let message = message.as_ref();
     let mut frame = Vec::with_capacity(message.len() + 3);
     frame.push(MLLP_START);
-    frame.extend_from_slice(message.as_bytes());
+    frame.extend_from_slice(message);
     frame.push(MLLP_END_1);
     frame.push(MLLP_END_2);
     frame
@@ -260,7 +532,7 @@ mod tests {
         frame.push(MLLP_END_2);
 
         let (extracted, consumed) = extract_mllp_frame(&frame).unwrap();
-        assert_eq!(extracted, msg);
+        assert_eq!(extracted, msg.as_bytes());
         assert_eq!(consumed, frame.len());
     }
 
@@ -278,6 +550,317 @@ mod tests {
         assert_eq!(wrapped[wrapped.len() - 1], MLLP_END_2);
     }
 
+    fn ack_msg(message_type: &str, facility: &str) -> Hl7Message {
+        let mut msg = Hl7Message::new_empty(String::new(), "127.0.0.1:5000".into());
+        msg.message_type = message_type.to_string();
+        msg.sending_facility = facility.to_string();
+        msg
+    }
+
+    #[test]
+    fn test_select_ack_rule_first_match_wins() {
+        use crate::config::AckCode;
+        let config = AckConfig {
+            rules: vec![
+                AckRule {
+                    sending_facility: Some("LAB".into()),
+                    code: AckCode::AR,
+                    ..Default::default()
+                },
+                AckRule {
+                    message_type: Some("ADT^*".into()),
+                    code: AckCode::AE,
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let rule = select_ack_rule(&config, &ack_msg("ADT^A01", "LAB"), || 0.0).unwrap();
+        assert_eq!(rule.code, AckCode::AR);
+        let rule = select_ack_rule(&config, &ack_msg("ADT^A01", "WARD"), || 0.0).unwrap();
+        assert_eq!(rule.code, AckCode::AE);
+        assert!(select_ack_rule(&config, &ack_msg("ORU^R01", "WARD"), || 0.0).is_none());
+    }
+
+    #[test]
+    fn test_select_ack_rule_percentage() {
+        use crate::config::AckCode;
+        let config = AckConfig {
+            rules: vec![AckRule {
+                percentage: 25.0,
+                code: AckCode::None,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let msg = ack_msg("ADT^A01", "WARD");
+        assert!(select_ack_rule(&config, &msg, || 10.0).is_some());
+        assert!(select_ack_rule(&config, &msg, || 25.0).is_none());
+        assert!(select_ack_rule(&config, &msg, || 99.9).is_none());
+    }
+
+    #[test]
+    fn test_select_ack_rule_rolls_per_rule() {
+        use crate::config::AckCode;
+        let config = AckConfig {
+            rules: vec![
+                AckRule {
+                    percentage: 50.0,
+                    code: AckCode::AE,
+                    ..Default::default()
+                },
+                AckRule {
+                    percentage: 50.0,
+                    code: AckCode::AR,
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let msg = ack_msg("ADT^A01", "WARD");
+        // The first rule misses its roll; the second gets a fresh one
+        let mut rolls = [60.0, 10.0].into_iter();
+        let rule = select_ack_rule(&config, &msg, || rolls.next().unwrap()).unwrap();
+        assert_eq!(rule.code, AckCode::AR);
+        let mut rolls = [60.0, 70.0].into_iter();
+        assert!(select_ack_rule(&config, &msg, || rolls.next().unwrap()).is_none());
+    }
+
+    #[test]
+    fn test_plan_acks() {
+        use crate::hl7::ack::AckCondition::*;
+        assert_eq!(
+            plan_acks(AckMode::Original, AckCode::AE),
+            (Some((AckKind::Original, "AE")), None)
+        );
+        assert_eq!(plan_acks(AckMode::Original, AckCode::None), (None, None));
+
+        let enhanced = |accept, application| AckMode::Enhanced {
+            accept,
+            application,
+        };
+        assert_eq!(
+            plan_acks(enhanced(AL, AL), AckCode::AA),
+            (Some((AckKind::Commit, "CA")), Some("AA"))
+        );
+        assert_eq!(
+            plan_acks(enhanced(AL, ER), AckCode::AA),
+            (Some((AckKind::Commit, "CA")), None)
+        );
+        // A rejected or errored commit is not followed by an application ACK
+        assert_eq!(
+            plan_acks(enhanced(ER, ER), AckCode::AR),
+            (Some((AckKind::Commit, "CR")), None)
+        );
+        assert_eq!(plan_acks(enhanced(NE, AL), AckCode::AE), (None, None));
+        assert_eq!(plan_acks(enhanced(SU, NE), AckCode::CE), (None, None));
+        assert_eq!(plan_acks(enhanced(AL, AL), AckCode::None), (None, None));
+    }
+
+    #[tokio::test]
+    async fn test_enhanced_mode_sends_commit_and_application_acks() {
+        use crate::config::StoreConfig;
+        use tokio::net::TcpListener;
+
+        // Receiver standing in for the sender's application ACK endpoint
+        let receiver = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target = OutboundTarget {
+            name: "sender".into(),
+            host: "127.0.0.1".into(),
+            port: receiver.local_addr().unwrap().port(),
+        };
+        let received = tokio::spawn(async move {
+            let (mut socket, _) = receiver.accept().await.unwrap();
+            let mut buf = vec![0u8; 4096];
+            let mut accumulated = Vec::new();
+            let frame = loop {
+                let n = socket.read(&mut buf).await.unwrap();
+                accumulated.extend_from_slice(&buf[..n]);
+                if let Some((frame, _)) = extract_mllp_frame(&accumulated) {
+                    break String::from_utf8(frame.to_vec()).unwrap();
+                }
+            };
+            socket
+                .write_all(&wrap_mllp("MSH|^~\\&|S|S|||||ACK||P|2.5\rMSA|CA|X"))
+                .await
+                .unwrap();
+            frame
+        });
+
+        let store = MessageStore::new(StoreConfig::default()).unwrap();
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        let (_shutdown_tx, shutdown) = watch::channel(false);
+        let conn = ConnectionInfo {
+            peer: "127.0.0.1:5000".into(),
+            listener: Arc::from("test"),
+            tls_peer_subject: None,
+        };
+        let sender = ApplicationAckSender::new(target, OutboundConfig::default());
+        let server_store = store.clone();
+        tokio::spawn(async move {
+            let config = MllpConfig::default();
+            let stats = MllpStats::new();
+            handle_connection(
+                server,
+                &conn,
+                &server_store,
+                &stats,
+                &config,
+                Some(sender),
+                shutdown,
+            )
+            .await
+        });
+
+        client
+            .write_all(&wrap_mllp(
+                "MSH|^~\\&|APP|FAC|R|R|20240101||ADT^A01|ENH1|P|2.5|||AL|AL",
+            ))
+            .await
+            .unwrap();
+        let mut buf = vec![0u8; 4096];
+        let n = client.read(&mut buf).await.unwrap();
+        let (commit, _) = extract_mllp_frame(&buf[..n]).unwrap();
+        let commit = String::from_utf8_lossy(commit);
+        assert!(commit.contains("MSA|CA|ENH1"), "{commit}");
+
+        let application = received.await.unwrap();
+        assert!(application.contains("MSA|AA|ENH1"), "{application}");
+
+        // The application ACK is recorded once the remote answered
+        let mut acks = Vec::new();
+        for _ in 0..50 {
+            let id = store.list_summaries(0, 1).await[0].id.clone();
+            acks = store.get_by_id(&id).await.unwrap().acks_sent;
+            if acks.len() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let kinds: Vec<_> = acks.iter().map(|a| (a.kind, a.code.as_str())).collect();
+        assert_eq!(
+            kinds,
+            vec![(AckKind::Commit, "CA"), (AckKind::Application, "AA")]
+        );
+        assert_eq!(acks[1].target.as_deref(), Some("sender"));
+        assert!(acks[1].error.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_parse_error_nack_follows_enhanced_mode() {
+        use crate::config::StoreConfig;
+
+        let store = MessageStore::new(StoreConfig::default()).unwrap();
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        let (_shutdown_tx, shutdown) = watch::channel(false);
+        let conn = ConnectionInfo {
+            peer: "127.0.0.1:5000".into(),
+            listener: Arc::from("test"),
+            tls_peer_subject: None,
+        };
+        let server_store = store.clone();
+        tokio::spawn(async move {
+            let config = MllpConfig::default();
+            let stats = MllpStats::new();
+            handle_connection(
+                server,
+                &conn,
+                &server_store,
+                &stats,
+                &config,
+                None,
+                shutdown,
+            )
+            .await
+        });
+        let mut buf = vec![0u8; 4096];
+
+        // Leading junk makes the frame unparseable, but its MSH is recoverable
+        client
+            .write_all(&wrap_mllp(
+                "JUNK\rMSH|^~\\&|APP|FAC|R|R|20240101||ADT^A01|BAD1|P|2.5|||ER|NE",
+            ))
+            .await
+            .unwrap();
+        let n = client.read(&mut buf).await.unwrap();
+        let (nack, _) = extract_mllp_frame(&buf[..n]).unwrap();
+        let nack = String::from_utf8_lossy(nack);
+        assert!(nack.contains("MSA|CE|BAD1"), "{nack}");
+
+        // MSH-15 SU: no commit ACK for an error, so the next reply is for OK1
+        client
+            .write_all(&wrap_mllp(
+                "JUNK\rMSH|^~\\&|APP|FAC|R|R|20240101||ADT^A01|BAD2|P|2.5|||SU|NE",
+            ))
+            .await
+            .unwrap();
+        client
+            .write_all(&wrap_mllp(
+                "MSH|^~\\&|APP|FAC|R|R|20240101||ADT^A01|OK1|P|2.5",
+            ))
+            .await
+            .unwrap();
+        let n = client.read(&mut buf).await.unwrap();
+        let (ack, _) = extract_mllp_frame(&buf[..n]).unwrap();
+        let ack = String::from_utf8_lossy(ack);
+        assert!(ack.contains("MSA|AA|OK1"), "{ack}");
+
+        let summaries = store.list_summaries(0, 10).await;
+        let bad2 = summaries
+            .iter()
+            .find(|s| s.message_type == "UNKNOWN" && s.ack_code.is_none());
+        assert!(bad2.is_some(), "{summaries:?}");
+    }
+
+    #[tokio::test]
+    async fn test_retry_during_delayed_ack_is_a_duplicate() {
+        use crate::config::StoreConfig;
+
+        let store = MessageStore::new(StoreConfig::default()).unwrap();
+        let mut config = MllpConfig::default();
+        config.ack.duplicate_code = Some(AckCode::AR);
+        config.ack.rules = vec![AckRule {
+            delay_ms: 500,
+            ..Default::default()
+        }];
+        let config = Arc::new(config);
+        let (_shutdown_tx, shutdown) = watch::channel(false);
+        let connect = |port: u16| {
+            let (client, server) = tokio::io::duplex(64 * 1024);
+            let conn = ConnectionInfo {
+                peer: format!("127.0.0.1:{port}"),
+                listener: Arc::from("test"),
+                tls_peer_subject: None,
+            };
+            let (store, config, shutdown) = (store.clone(), config.clone(), shutdown.clone());
+            tokio::spawn(async move {
+                let stats = MllpStats::new();
+                handle_connection(server, &conn, &store, &stats, &config, None, shutdown).await
+            });
+            client
+        };
+        let frame = wrap_mllp("MSH|^~\\&|APP|FAC|R|R|20240101||ADT^A01|RETRY1|P|2.5");
+        let read_ack = |mut client: tokio::io::DuplexStream| async move {
+            let mut buf = vec![0u8; 4096];
+            let n = client.read(&mut buf).await.unwrap();
+            let (ack, _) = extract_mllp_frame(&buf[..n]).unwrap();
+            String::from_utf8_lossy(ack).into_owned()
+        };
+
+        let mut first = connect(5000);
+        first.write_all(&frame).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        // The sender gives up waiting and retries on a new connection
+        let mut retry = connect(5001);
+        retry.write_all(&frame).await.unwrap();
+
+        let retry_ack = read_ack(retry).await;
+        assert!(retry_ack.contains("MSA|AR|RETRY1"), "{retry_ack}");
+        let first_ack = read_ack(first).await;
+        assert!(first_ack.contains("MSA|AA|RETRY1"), "{first_ack}");
+        assert_eq!(store.duplicate_counts(), (2, 1));
+    }
+
     #[test]
     fn test_mllp_stats_new() {
         let stats = MllpStats::new();
@@ -0,0 +1,120 @@
+use crate::config::OutboundConfig;
+use crate::hl7::charset;
+use crate::mllp::{extract_mllp_frame, wrap_mllp};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tracing::debug;
+
+/// Upper bound for an ACK frame read from a remote system
+const MAX_ACK_SIZE: usize = 10 * 1024 * 1024;
+
+/// Result of sending one message to a remote MLLP receiver
+#[derive(Debug)]
+pub struct SendOutcome {
+    /// The raw ACK frame returned by the remote system
+    pub ack: String,
+    /// Time from connect to a complete ACK frame
+    pub latency: Duration,
+}
+
+/// Send a single encoded message to `addr` over MLLP and wait for the ACK.
+///
+/// Opens a fresh connection per call; the connection is closed once the ACK
+/// frame has been read. Connect and ACK waits are bounded by `config`.
+pub async fn send_message(
+    addr: &str,
+    message: &[u8],
+    config: &OutboundConfig,
+) -> anyhow::Result<SendOutcome> {
+    let started = Instant::now();
+
+    let mut socket = timeout(config.connect_timeout(), TcpStream::connect(addr))
+        .await
+        .map_err(|_| anyhow::anyhow!("Connect timeout after {:?}", config.connect_timeout()))??;
+
+    debug!("Replaying message to {} ({} bytes)", addr, message.len());
+    socket.write_all(&wrap_mllp(message)).await?;
+
+    let ack = timeout(config.ack_timeout(), read_frame(&mut socket))
+        .await
+        .map_err(|_| anyhow::anyhow!("No ACK within {:?}", config.ack_timeout()))??;
+
+    Ok(SendOutcome {
+        ack,
+        latency: started.elapsed(),
+    })
+}
+
+async fn read_frame(socket: &mut TcpStream) -> anyhow::Result<String> {
+    let mut buf = vec![0u8; 8 * 1024];
+    let mut accumulated = Vec::with_capacity(1024);
+
+    loop {
+        let n = socket.read(&mut buf).await?;
+        if n == 0 {
+            anyhow::bail!("Connection closed before an ACK was received");
+        }
+        accumulated.extend_from_slice(&buf[..n]);
+
+        if let Some((frame, _)) = extract_mllp_frame(&accumulated) {
+            return Ok(charset::decode(frame, None).text);
+        }
+        if accumulated.len() > MAX_ACK_SIZE {
+            anyhow::bail!(
+                "ACK exceeded {} bytes without a complete frame",
+                MAX_ACK_SIZE
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    /// Minimal MLLP receiver that answers the first frame with `reply`
+    async fn spawn_receiver(reply: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = vec![0u8; 1024];
+            let mut accumulated = Vec::new();
+            loop {
+                let n = socket.read(&mut buf).await.unwrap();
+                accumulated.extend_from_slice(&buf[..n]);
+                if extract_mllp_frame(&accumulated).is_some() {
+                    break;
+                }
+            }
+            socket.write_all(&wrap_mllp(reply)).await.unwrap();
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_send_message_receives_ack() {
+        let addr = spawn_receiver("MSH|^~\\&|R|R|||||ACK||P|2.5\rMSA|AA|MSG001").await;
+        let outcome = send_message(
+            &addr,
+            b"MSH|^~\\&|S|S|||||ADT^A01|MSG001|P|2.5",
+            &OutboundConfig::default(),
+        )
+        .await
+        .unwrap();
+        assert!(outcome.ack.contains("MSA|AA|MSG001"));
+    }
+
+    #[tokio::test]
+    async fn test_send_message_connection_refused() {
+        // Bind and drop to obtain a port that is very likely closed
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        drop(listener);
+        let result = send_message(&addr, b"MSH|^~\\&|S", &OutboundConfig::default()).await;
+        assert!(result.is_err());
+    }
+}
@@ -1,8 +1,29 @@
-use crate::config::StoreConfig;
-use crate::hl7::types::{Hl7Message, Hl7MessageSummary};
-use std::collections::VecDeque;
-use std::sync::Arc;
-use tokio::sync::{broadcast, RwLock};
+mod duplicates;
+mod merges;
+mod pattern;
+mod query;
+mod retention;
+mod sqlite;
+
+use crate::config::{ParseMode, RetentionConfig, StoreBackend, StoreConfig};
+use crate::hl7::detail::MessageDetail;
+use crate::hl7::path::FieldPath;
+use crate::hl7::size::MemoryEstimate;
+use crate::hl7::types::{AckLink, Hl7Message, Hl7MessageSummary, ReplayRecord, SentAck};
+use chrono::{DateTime, Utc};
+use duplicates::DuplicateTracker;
+use indexmap::IndexMap;
+use lru::LruCache;
+use merges::PatientMerges;
+pub use pattern::PatternSearch;
+pub use query::SearchFilter;
+use retention::Candidate;
+use sqlite::SqliteStore;
+use std::collections::{HashMap, HashSet};
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::sync::{broadcast, watch, RwLock};
 use tracing::{info, warn};
 
 const BROADCAST_CAPACITY: usize = 4096;
@@ -13,93 +34,421 @@ pub enum StoreEvent {
     NewMessage(Box<Hl7MessageSummary>),
     TagsUpdated(Box<Hl7MessageSummary>),
     BookmarkToggled(Box<Hl7MessageSummary>),
+    Replayed(Box<Hl7MessageSummary>),
+    /// A stored message was linked to an inbound ACK
+    AckLinked(Box<Hl7MessageSummary>),
+    /// An acknowledgment sent after the message was stored (enhanced-mode
+    /// application ACK) was recorded on it
+    AckSent(Box<Hl7MessageSummary>),
+    /// IDs removed by a retention sweep
+    Evicted(Vec<String>),
     Cleared,
 }
 
-/// Thread-safe in-memory message store with broadcast notifications
+/// Thread-safe message store with broadcast notifications.
+///
+/// Messages live in memory by default; with `backend = "sqlite"` every read
+/// and write goes to the database instead and `inner` stays empty.
+///
+/// With `parse_mode = "lazy"` messages are stored without their segment
+/// detail, which is re-parsed on read and kept in a small LRU cache.
 #[derive(Clone)]
 pub struct MessageStore {
     inner: Arc<RwLock<StoreInner>>,
+    db: Option<Arc<SqliteStore>>,
     tx: broadcast::Sender<StoreEvent>,
+    lazy: bool,
+    detail_cache: Option<Arc<Mutex<LruCache<String, MessageDetail>>>>,
+    duplicates: Option<Arc<Mutex<DuplicateTracker>>>,
+    /// Patient identifiers linked by ADT merge messages
+    merges: Arc<Mutex<PatientMerges>>,
 }
 
 struct StoreInner {
-    messages: VecDeque<Hl7Message>,
+    /// Messages in arrival order (oldest first), keyed by UUID
+    messages: IndexMap<String, Hl7Message>,
+    /// MSH-10 → IDs of messages with that control ID, oldest first
+    by_control_id: HashMap<String, Vec<String>>,
+    /// PID-3 → IDs of messages for that patient, oldest first
+    by_patient_id: HashMap<String, Vec<String>>,
     capacity: usize,
     max_bytes: usize,
-    current_bytes: usize,
+    /// Sum of [`Hl7Message::memory_estimate`] over `messages`
+    memory: MemoryEstimate,
+}
+
+impl StoreInner {
+    fn push(&mut self, msg: Hl7Message) {
+        let mut index_bytes = map_entry_size(&msg.id);
+        if !msg.message_control_id.is_empty() {
+            index_bytes += index(&mut self.by_control_id, &msg.message_control_id, &msg.id);
+        }
+        if let Some(patient_id) = msg.patient_id.as_ref().filter(|p| !p.is_empty()) {
+            index_bytes += index(&mut self.by_patient_id, patient_id, &msg.id);
+        }
+        self.memory += msg.memory_estimate();
+        self.memory.index_bytes += index_bytes;
+        self.messages.insert(msg.id.clone(), msg);
+    }
+
+    /// Remove the given messages in one pass, keeping the indexes in sync.
+    /// Returns the estimated memory freed.
+    fn remove_ids(&mut self, ids: &HashSet<&str>) -> MemoryEstimate {
+        let mut freed = MemoryEstimate::default();
+        let by_control_id = &mut self.by_control_id;
+        let by_patient_id = &mut self.by_patient_id;
+        self.messages.retain(|id, m| {
+            if !ids.contains(id.as_str()) {
+                return true;
+            }
+            freed += m.memory_estimate();
+            freed.index_bytes +=
+                map_entry_size(id) + unindex(by_control_id, &m.message_control_id, id);
+            if let Some(patient_id) = &m.patient_id {
+                freed.index_bytes += unindex(by_patient_id, patient_id, id);
+            }
+            false
+        });
+        self.memory -= freed;
+        freed
+    }
+
+    fn clear(&mut self) {
+        self.messages.clear();
+        self.by_control_id.clear();
+        self.by_patient_id.clear();
+        self.memory = MemoryEstimate::default();
+    }
+
+    /// Summaries for the IDs of an index entry, newest first
+    fn lookup(&self, ids: Option<&Vec<String>>) -> Vec<Hl7MessageSummary> {
+        ids.into_iter()
+            .flatten()
+            .rev()
+            .filter_map(|id| self.messages.get(id))
+            .map(Hl7MessageSummary::from)
+            .collect()
+    }
+}
+
+/// Estimated size of one ID copy held by the message map or an index
+fn id_copy_size(id: &str) -> usize {
+    std::mem::size_of::<String>() + id.len()
+}
+
+/// Estimated size of a message map entry: the key copy, its hash and its
+/// slot in the map's index table
+fn map_entry_size(id: &str) -> usize {
+    id_copy_size(id) + 2 * std::mem::size_of::<usize>()
+}
+
+/// Estimated size of an index key with its (empty) ID list
+fn index_key_size(key: &str) -> usize {
+    id_copy_size(key) + std::mem::size_of::<Vec<String>>()
+}
+
+/// Add `id` under `key`. Returns the estimated bytes added.
+fn index(index: &mut HashMap<String, Vec<String>>, key: &str, id: &str) -> usize {
+    let mut added = id_copy_size(id);
+    index
+        .entry(key.to_string())
+        .or_insert_with(|| {
+            added += index_key_size(key);
+            Vec::new()
+        })
+        .push(id.to_string());
+    added
+}
+
+/// Remove `id` from under `key`, dropping the key once it has no IDs left.
+/// Returns the estimated bytes freed.
+fn unindex(index: &mut HashMap<String, Vec<String>>, key: &str, id: &str) -> usize {
+    let Some(ids) = index.get_mut(key) else {
+        return 0;
+    };
+    let before = ids.len();
+    ids.retain(|i| i != id);
+    let mut freed = (before - ids.len()) * id_copy_size(id);
+    if ids.is_empty() {
+        index.remove(key);
+        freed += index_key_size(key);
+    }
+    freed
+}
+
+/// Case-insensitive substring match on the summary fields used by the
+/// plain-text search. `query_lower` must already be lowercased.
+fn matches_text(m: &Hl7Message, query_lower: &str) -> bool {
+    m.message_type.to_lowercase().contains(query_lower)
+        || m.sending_facility.to_lowercase().contains(query_lower)
+        || m.patient_name
+            .as_deref()
+            .unwrap_or("")
+            .to_lowercase()
+            .contains(query_lower)
+        || m.patient_id
+            .as_deref()
+            .unwrap_or("")
+            .to_lowercase()
+            .contains(query_lower)
+        || m.message_control_id.to_lowercase().contains(query_lower)
+        || m.source_addr.contains(query_lower)
+        || m.listener.to_lowercase().contains(query_lower)
+}
+
+/// Log a database error and fall back to an empty result
+fn db_result<T: Default>(result: anyhow::Result<T>, operation: &str) -> T {
+    result.unwrap_or_else(|e| {
+        warn!("SQLite {} failed: {:#}", operation, e);
+        T::default()
+    })
 }
 
 impl MessageStore {
-    pub fn new(config: StoreConfig) -> Self {
+    /// Create the store; opens (or creates) the database for the SQLite backend.
+    pub fn new(config: StoreConfig) -> anyhow::Result<Self> {
         let (tx, _) = broadcast::channel(BROADCAST_CAPACITY);
-        Self {
+        let db = match config.backend {
+            StoreBackend::Memory => None,
+            StoreBackend::Sqlite => {
+                let db = SqliteStore::open(&config.sqlite_path, config.max_messages)?;
+                info!(
+                    "Using SQLite message store at {}",
+                    config.sqlite_path.display()
+                );
+                Some(Arc::new(db))
+            }
+        };
+        let mut merges = PatientMerges::default();
+        if let Some(db) = &db {
+            for (prior, surviving) in db.load_merges()? {
+                merges.record(&prior, &surviving);
+            }
+        }
+        Ok(Self {
             inner: Arc::new(RwLock::new(StoreInner {
-                messages: VecDeque::with_capacity(1024),
+                messages: IndexMap::with_capacity(1024),
+                by_control_id: HashMap::new(),
+                by_patient_id: HashMap::new(),
                 capacity: config.max_messages,
                 max_bytes: config.max_memory_bytes(),
-                current_bytes: 0,
+                memory: MemoryEstimate::default(),
             })),
+            db,
             tx,
-        }
+            lazy: config.parse_mode == ParseMode::Lazy,
+            detail_cache: NonZeroUsize::new(config.detail_cache_size)
+                .filter(|_| config.parse_mode == ParseMode::Lazy)
+                .map(|size| Arc::new(Mutex::new(LruCache::new(size)))),
+            duplicates: config
+                .duplicate_window()
+                .map(|window| Arc::new(Mutex::new(DuplicateTracker::new(window)))),
+            merges: Arc::new(Mutex::new(merges)),
+        })
     }
 
-    /// Insert a message and broadcast summary to all WebSocket subscribers
-    pub async fn insert(&self, msg: Hl7Message) {
+    /// Insert a message and broadcast summary to all WebSocket subscribers.
+    /// Repeats of a recently seen message get `duplicate_of` set, and an
+    /// inbound ACK is linked to the message it acknowledges.
+    pub async fn insert(&self, mut msg: Hl7Message) {
+        if msg.duplicate_of.is_none() {
+            self.check_duplicate(&mut msg);
+        }
+        let acknowledged = self.link_ack(&mut msg).await;
+        self.record_merges(&msg).await;
         let summary = Hl7MessageSummary::from(&msg);
+        if self.lazy {
+            msg.defer_detail();
+        }
+
+        let count = match &self.db {
+            Some(db) => match db.insert(&msg).await {
+                Ok((evicted, count)) => {
+                    if evicted > 0 {
+                        info!(
+                            "Evicted {} messages from SQLite store (store now {} messages)",
+                            evicted, count
+                        );
+                    }
+                    count
+                }
+                Err(e) => {
+                    warn!("Failed to persist message {}: {:#}", msg.id, e);
+                    return;
+                }
+            },
+            None => self.insert_memory(msg).await,
+        };
+
+        // Broadcast to WebSocket subscribers (ignore if no receivers)
+        let _ = self.tx.send(StoreEvent::NewMessage(Box::new(summary)));
+        if let Some(acknowledged) = acknowledged {
+            let _ = self.tx.send(StoreEvent::AckLinked(Box::new(acknowledged)));
+        }
+
+        if count % 1000 == 0 {
+            info!("Store now holds {} messages", count);
+        }
+    }
 
+    /// Remember the MRG-1 → PID-3 links of a merge message
+    async fn record_merges(&self, msg: &Hl7Message) {
+        for (prior, surviving) in merges::merge_pairs(msg) {
+            let added = self
+                .merges
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .record(&prior, &surviving);
+            if !added {
+                continue;
+            }
+            info!("Patient {} merged into {}", prior, surviving);
+            if let Some(db) = &self.db {
+                db_result(db.record_merge(&prior, &surviving).await, "merge");
+            }
+        }
+    }
+
+    /// `patient_id` followed by every identifier merged with it
+    pub fn linked_patient_ids(&self, patient_id: &str) -> Vec<String> {
+        self.merges
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .linked(patient_id)
+    }
+
+    /// If `ack` is an acknowledgment, find the newest earlier message whose
+    /// MSH-10 matches its MSA-2 and whose sender (MSH-3/4) is the ACK's
+    /// receiver (MSH-5/6), and link the two to each other. Control IDs are
+    /// only unique per sender, so among several matches one received on the
+    /// same listener from the same host wins. Returns the updated summary of
+    /// the acknowledged message.
+    async fn link_ack(&self, ack: &mut Hl7Message) -> Option<Hl7MessageSummary> {
+        if !ack.is_ack() {
+            return None;
+        }
+        let msa = |field: usize| {
+            ack.get_path(&FieldPath {
+                segment: "MSA".into(),
+                occurrence: None,
+                field,
+                repetition: None,
+                component: None,
+                subcomponent: None,
+            })
+            .filter(|v| !v.is_empty())
+        };
+        let control_id = msa(2)?;
+        let ack_code = msa(1);
+        let mut candidates = Vec::new();
+        for summary in self.find_by_control_id(&control_id).await {
+            if summary.id == ack.id
+                || summary.message_type.starts_with("ACK")
+                || summary.received_at > ack.received_at
+            {
+                continue;
+            }
+            let Some(msg) = self.get_by_id(&summary.id).await else {
+                continue;
+            };
+            if msg.sending_application == ack.receiving_application
+                && msg.sending_facility == ack.receiving_facility
+            {
+                candidates.push(summary);
+            }
+        }
+        // Stable sort: the newest stays first among equally good matches
+        candidates.sort_by_key(|s| {
+            std::cmp::Reverse((
+                s.listener == ack.listener,
+                source_host(&s.source_addr) == source_host(&ack.source_addr),
+            ))
+        });
+        let original = candidates.into_iter().next()?;
+
+        let round_trip_ms = (ack.received_at - original.received_at).num_milliseconds();
+        ack.ack_link = Some(AckLink {
+            counterpart_id: original.id.clone(),
+            ack_code: ack_code.clone(),
+            round_trip_ms,
+        });
+        let link = AckLink {
+            counterpart_id: ack.id.clone(),
+            ack_code,
+            round_trip_ms,
+        };
+        self.modify(&original.id, move |msg| {
+            // A commit ACK arriving after the application ACK does not replace it
+            let keep = msg.ack_link.as_ref().is_some_and(|existing| {
+                existing.is_application_ack() && !link.is_application_ack()
+            });
+            if !keep {
+                msg.ack_link = Some(link);
+            }
+            !keep
+        })
+        .await
+    }
+
+    /// Append to the in-memory ring buffer, evicting first if a limit is hit.
+    /// Returns the new message count.
+    async fn insert_memory(&self, msg: Hl7Message) -> usize {
         let mut inner = self.inner.write().await;
 
         // Evict oldest 10% when either size or count limit is breached
         // Bookmarked messages are protected from eviction
-        if inner.current_bytes >= inner.max_bytes || inner.messages.len() >= inner.capacity {
+        if inner.memory.total() >= inner.max_bytes || inner.messages.len() >= inner.capacity {
             let target_count = inner.messages.len() / 10;
-            let mut evict_indices: Vec<usize> = Vec::with_capacity(target_count);
-            for (i, m) in inner.messages.iter().enumerate() {
-                if evict_indices.len() >= target_count {
-                    break;
-                }
-                if !m.bookmarked {
-                    evict_indices.push(i);
-                }
-            }
-            if evict_indices.is_empty() {
+            let evict_ids: Vec<String> = inner
+                .messages
+                .values()
+                .filter(|m| !m.bookmarked)
+                .take(target_count)
+                .map(|m| m.id.clone())
+                .collect();
+            if evict_ids.is_empty() {
                 warn!(
                     "Eviction triggered but all candidate messages are bookmarked — skipping eviction"
                 );
             } else {
-                let freed_bytes: usize = evict_indices
-                    .iter()
-                    .map(|&i| inner.messages[i].raw.len())
-                    .sum();
-                // Remove in reverse order to keep indices valid
-                for &i in evict_indices.iter().rev() {
-                    inner.messages.remove(i);
-                }
-                inner.current_bytes = inner.current_bytes.saturating_sub(freed_bytes);
+                let evicted = evict_ids.len();
+                let freed = inner.remove_ids(&evict_ids.iter().map(String::as_str).collect());
                 info!(
                     "Evicted {} messages from store ({} MB freed, store now {} messages / {} MB)",
-                    evict_indices.len(),
-                    freed_bytes / 1024 / 1024,
+                    evicted,
+                    freed.total() / 1024 / 1024,
                     inner.messages.len(),
-                    inner.current_bytes / 1024 / 1024,
+                    inner.memory.total() / 1024 / 1024,
                 );
             }
         }
 
-        inner.current_bytes += msg.raw.len();
-        inner.messages.push_back(msg);
-        let count = inner.messages.len();
-        drop(inner);
-
-        // Broadcast to WebSocket subscribers (ignore if no receivers)
-        let _ = self.tx.send(StoreEvent::NewMessage(Box::new(summary)));
+        inner.push(msg);
+        inner.messages.len()
+    }
 
-        if count % 1000 == 0 {
-            info!("Store now holds {} messages", count);
+    /// Set `duplicate_of` if `msg` repeats a recently seen message, or
+    /// remember it as an original. Called on receipt, before the ACK is
+    /// chosen and possibly delayed, so a retry arriving in the meantime is
+    /// caught; [`insert`](Self::insert) does it for messages not yet checked.
+    pub fn check_duplicate(&self, msg: &mut Hl7Message) {
+        if let Some(tracker) = &self.duplicates {
+            msg.duplicate_of = tracker.lock().unwrap_or_else(|e| e.into_inner()).check(msg);
         }
     }
 
+    /// True with `parse_mode = "lazy"`: inserted messages drop their segment detail
+    pub fn is_lazy(&self) -> bool {
+        self.lazy
+    }
+
+    /// `(messages checked, duplicates flagged)` since startup or the last clear
+    pub fn duplicate_counts(&self) -> (u64, u64) {
+        self.duplicates.as_ref().map_or((0, 0), |tracker| {
+            tracker.lock().unwrap_or_else(|e| e.into_inner()).counts()
+        })
+    }
+
     /// Get a broadcast receiver for real-time updates
     pub fn subscribe(&self) -> broadcast::Receiver<StoreEvent> {
         self.tx.subscribe()
@@ -107,10 +456,13 @@ impl MessageStore {
 
     /// Get all message summaries (lightweight)
     pub async fn list_summaries(&self, offset: usize, limit: usize) -> Vec<Hl7MessageSummary> {
+        if let Some(db) = &self.db {
+            return db_result(db.list_summaries(offset, limit).await, "list");
+        }
         let inner = self.inner.read().await;
         inner
             .messages
-            .iter()
+            .values()
             .rev() // newest first
             .skip(offset)
             .take(limit)
@@ -120,34 +472,183 @@ impl MessageStore {
 
     /// Get a full message by ID
     pub async fn get_by_id(&self, id: &str) -> Option<Hl7Message> {
-        let inner = self.inner.read().await;
-        inner.messages.iter().find(|m| m.id == id).cloned()
+        let msg = match &self.db {
+            Some(db) => db_result(db.get(id).await, "lookup"),
+            None => self.inner.read().await.messages.get(id).cloned(),
+        };
+        msg.map(|msg| self.hydrate(msg))
     }
 
-    /// Search messages by filter text (matches message type, patient name, facility, etc.)
-    pub async fn search(&self, query: &str, limit: usize) -> Vec<Hl7MessageSummary> {
+    /// Restore the detail of a lazily stored message, from the cache when
+    /// it was parsed recently
+    fn hydrate(&self, mut msg: Hl7Message) -> Hl7Message {
+        if !msg.detail_deferred {
+            return msg;
+        }
+        let Some(cache) = &self.detail_cache else {
+            let detail = msg.parse_detail();
+            msg.restore_detail(detail);
+            return msg;
+        };
+        let cached = cache
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&msg.id)
+            .cloned();
+        let detail = cached.unwrap_or_else(|| {
+            let detail = msg.parse_detail();
+            cache
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .put(msg.id.clone(), detail.clone());
+            detail
+        });
+        msg.restore_detail(detail);
+        msg
+    }
+
+    /// Search messages by filter text (matches message type, patient name, facility, etc.),
+    /// optionally restricted to a single listener
+    pub async fn search(
+        &self,
+        query: &str,
+        listener: Option<&str>,
+        limit: usize,
+    ) -> Vec<Hl7MessageSummary> {
         let query_lower = query.to_lowercase();
+        if let Some(db) = &self.db {
+            return db_result(db.search(&query_lower, listener, limit).await, "search");
+        }
         let inner = self.inner.read().await;
         inner
             .messages
-            .iter()
+            .values()
             .rev()
-            .filter(|m| {
-                m.message_type.to_lowercase().contains(&query_lower)
-                    || m.sending_facility.to_lowercase().contains(&query_lower)
-                    || m.patient_name
-                        .as_deref()
-                        .unwrap_or("")
-                        .to_lowercase()
-                        .contains(&query_lower)
-                    || m.patient_id
-                        .as_deref()
-                        .unwrap_or("")
-                        .to_lowercase()
-                        .contains(&query_lower)
-                    || m.message_control_id.to_lowercase().contains(&query_lower)
-                    || m.source_addr.contains(&query_lower)
-            })
+            .filter(|m| listener.is_none_or(|l| m.listener == l))
+            .filter(|m| matches_text(m, &query_lower))
+            .take(limit)
+            .map(Hl7MessageSummary::from)
+            .collect()
+    }
+
+    /// Search with a field-path filter (see [`SearchFilter`]), newest first.
+    /// Lazily stored messages are re-parsed for the match, so the scan stops
+    /// after [`pattern::SCAN_TIME_LIMIT`] like a regex search; the returned
+    /// flag is true when it was cut short.
+    pub async fn search_filter(
+        &self,
+        filter: SearchFilter,
+        listener: Option<&str>,
+        limit: usize,
+    ) -> (Vec<Hl7MessageSummary>, bool) {
+        let deadline = Instant::now() + pattern::SCAN_TIME_LIMIT;
+        if let Some(db) = &self.db {
+            let scan = db.scan(listener, limit, Some(deadline), move |m| filter.matches(m));
+            return db_result(scan.await, "filter search");
+        }
+        self.scan_memory(listener, limit, Some(deadline), move |m| filter.matches(m))
+            .await
+    }
+
+    /// Regex search, newest first. Stops after [`pattern::SCAN_TIME_LIMIT`];
+    /// the returned flag is true when the scan was cut short.
+    pub async fn search_pattern(
+        &self,
+        search: PatternSearch,
+        listener: Option<&str>,
+        limit: usize,
+    ) -> (Vec<Hl7MessageSummary>, bool) {
+        let deadline = Instant::now() + pattern::SCAN_TIME_LIMIT;
+        if let Some(db) = &self.db {
+            let scan = db.scan(listener, limit, Some(deadline), move |m| search.matches(m));
+            return db_result(scan.await, "regex search");
+        }
+        self.scan_memory(listener, limit, Some(deadline), move |m| search.matches(m))
+            .await
+    }
+
+    /// Newest-first scan of the in-memory messages on a blocking thread, so
+    /// a slow filter does not stall the async workers. Returns the summaries
+    /// of up to `limit` messages accepted by `filter`; stops early once
+    /// `deadline` has passed, and the flag in the result tells whether that
+    /// happened.
+    async fn scan_memory<F>(
+        &self,
+        listener: Option<&str>,
+        limit: usize,
+        deadline: Option<Instant>,
+        filter: F,
+    ) -> (Vec<Hl7MessageSummary>, bool)
+    where
+        F: Fn(&Hl7Message) -> bool + Send + 'static,
+    {
+        let inner = Arc::clone(&self.inner);
+        let listener = listener.map(str::to_string);
+        let scan = tokio::task::spawn_blocking(move || {
+            let inner = inner.blocking_read();
+            let mut results = Vec::new();
+            for msg in inner.messages.values().rev() {
+                if results.len() >= limit {
+                    break;
+                }
+                if deadline.is_some_and(|d| Instant::now() >= d) {
+                    return (results, true);
+                }
+                if listener.as_deref().is_none_or(|l| msg.listener == l) && filter(msg) {
+                    results.push(Hl7MessageSummary::from(msg));
+                }
+            }
+            (results, false)
+        });
+        scan.await.unwrap_or_else(|e| {
+            warn!("Message scan failed: {}", e);
+            (Vec::new(), false)
+        })
+    }
+
+    /// Messages with the given MSH-10 control ID, newest first
+    pub async fn find_by_control_id(&self, control_id: &str) -> Vec<Hl7MessageSummary> {
+        if let Some(db) = &self.db {
+            return db_result(db.find_by_control_id(control_id).await, "lookup");
+        }
+        let inner = self.inner.read().await;
+        inner.lookup(inner.by_control_id.get(control_id))
+    }
+
+    /// Messages for the given PID-3 patient ID and every identifier merged
+    /// with it, newest first
+    pub async fn find_by_patient_id(&self, patient_id: &str) -> Vec<Hl7MessageSummary> {
+        let ids = self.linked_patient_ids(patient_id);
+        let mut results = Vec::new();
+        for id in &ids {
+            results.extend(match &self.db {
+                Some(db) => db_result(db.find_by_patient_id(id).await, "lookup"),
+                None => {
+                    let inner = self.inner.read().await;
+                    inner.lookup(inner.by_patient_id.get(id))
+                }
+            });
+        }
+        if ids.len() > 1 {
+            results.sort_by_key(|s| std::cmp::Reverse(s.received_at));
+        }
+        results
+    }
+
+    /// Messages that have not received an application ACK, newest first
+    pub async fn list_unacked(&self, offset: usize, limit: usize) -> Vec<Hl7MessageSummary> {
+        if let Some(db) = &self.db {
+            let scan = db.scan(None, offset + limit, None, |m| m.awaiting_application_ack());
+            let (summaries, _) = db_result(scan.await, "unacked list");
+            return summaries.into_iter().skip(offset).collect();
+        }
+        let inner = self.inner.read().await;
+        inner
+            .messages
+            .values()
+            .rev()
+            .filter(|m| m.awaiting_application_ack())
+            .skip(offset)
             .take(limit)
             .map(Hl7MessageSummary::from)
             .collect()
@@ -155,63 +656,205 @@ impl MessageStore {
 
     /// Total message count
     pub async fn count(&self) -> usize {
+        if let Some(db) = &self.db {
+            return db_result(db.count().await, "count");
+        }
         self.inner.read().await.messages.len()
     }
 
+    /// Apply `f` to a message; `f` returns whether it changed anything.
+    /// Returns the updated summary when a change was made.
+    async fn modify<F>(&self, id: &str, f: F) -> Option<Hl7MessageSummary>
+    where
+        F: FnOnce(&mut Hl7Message) -> bool + Send + 'static,
+    {
+        if let Some(db) = &self.db {
+            return match db_result(db.update(id, f).await, "update") {
+                Some((msg, true)) => Some(Hl7MessageSummary::from(&msg)),
+                _ => None,
+            };
+        }
+        let mut inner = self.inner.write().await;
+        let msg = inner.messages.get_mut(id)?;
+        let before = msg.memory_estimate();
+        if !f(msg) {
+            return None;
+        }
+        let after = msg.memory_estimate();
+        let summary = Hl7MessageSummary::from(&*msg);
+        inner.memory -= before;
+        inner.memory += after;
+        Some(summary)
+    }
+
+    /// Estimated memory held by the in-memory store, and its configured
+    /// budget in bytes. The SQLite backend keeps messages on disk: its
+    /// estimate is zero and it has no budget, only `max_messages`.
+    pub async fn memory_usage(&self) -> (MemoryEstimate, Option<usize>) {
+        let inner = self.inner.read().await;
+        (inner.memory, self.db.is_none().then_some(inner.max_bytes))
+    }
+
     /// Add a tag to a message and broadcast the update
     pub async fn add_tag(&self, id: &str, tag: String) -> bool {
-        let mut inner = self.inner.write().await;
-        if let Some(msg) = inner.messages.iter_mut().find(|m| m.id == id) {
-            if !msg.tags.contains(&tag) {
+        let summary = self
+            .modify(id, move |msg| {
+                if msg.tags.contains(&tag) {
+                    return false;
+                }
                 msg.tags.push(tag);
-                let summary = Hl7MessageSummary::from(&*msg);
-                drop(inner);
+                true
+            })
+            .await;
+        match summary {
+            Some(summary) => {
                 let _ = self.tx.send(StoreEvent::TagsUpdated(Box::new(summary)));
-                return true;
+                true
             }
+            None => false,
         }
-        false
     }
 
     /// Remove a tag from a message and broadcast the update
     pub async fn remove_tag(&self, id: &str, tag: &str) -> bool {
-        let mut inner = self.inner.write().await;
-        if let Some(msg) = inner.messages.iter_mut().find(|m| m.id == id) {
-            if let Some(pos) = msg.tags.iter().position(|t| t == tag) {
-                msg.tags.remove(pos);
-                let summary = Hl7MessageSummary::from(&*msg);
-                drop(inner);
+        let tag = tag.to_string();
+        let summary = self
+            .modify(id, move |msg| {
+                match msg.tags.iter().position(|t| *t == tag) {
+                    Some(pos) => {
+                        msg.tags.remove(pos);
+                        true
+                    }
+                    None => false,
+                }
+            })
+            .await;
+        match summary {
+            Some(summary) => {
                 let _ = self.tx.send(StoreEvent::TagsUpdated(Box::new(summary)));
-                return true;
+                true
             }
+            None => false,
         }
-        false
     }
 
     /// Toggle bookmark on a message, returns the new bookmark state or None if not found
     pub async fn toggle_bookmark(&self, id: &str) -> Option<bool> {
-        let mut inner = self.inner.write().await;
-        if let Some(msg) = inner.messages.iter_mut().find(|m| m.id == id) {
-            msg.bookmarked = !msg.bookmarked;
-            let new_state = msg.bookmarked;
-            let summary = Hl7MessageSummary::from(&*msg);
-            drop(inner);
-            let _ = self.tx.send(StoreEvent::BookmarkToggled(Box::new(summary)));
-            return Some(new_state);
+        let summary = self
+            .modify(id, |msg| {
+                msg.bookmarked = !msg.bookmarked;
+                true
+            })
+            .await?;
+        let new_state = summary.bookmarked;
+        let _ = self.tx.send(StoreEvent::BookmarkToggled(Box::new(summary)));
+        Some(new_state)
+    }
+
+    /// Attach a replay result to a message and broadcast the update.
+    /// Returns false if the message no longer exists (e.g. evicted mid-replay).
+    pub async fn record_replay(&self, id: &str, record: ReplayRecord) -> bool {
+        let summary = self
+            .modify(id, move |msg| {
+                msg.replays.push(record);
+                true
+            })
+            .await;
+        match summary {
+            Some(summary) => {
+                let _ = self.tx.send(StoreEvent::Replayed(Box::new(summary)));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Record an acknowledgment sent for a stored message and broadcast the
+    /// update. Returns false if the message no longer exists.
+    pub async fn record_sent_ack(&self, id: &str, ack: SentAck) -> bool {
+        let summary = self
+            .modify(id, move |msg| {
+                msg.acks_sent.push(ack);
+                true
+            })
+            .await;
+        match summary {
+            Some(summary) => {
+                let _ = self.tx.send(StoreEvent::AckSent(Box::new(summary)));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remove every message that violates the retention policy and broadcast
+    /// the removed IDs. Returns the removed IDs.
+    pub async fn sweep(&self, policy: &RetentionConfig, now: DateTime<Utc>) -> Vec<String> {
+        let expired = match &self.db {
+            Some(db) => db_result(db.sweep(policy, now).await, "retention sweep"),
+            None => {
+                let mut inner = self.inner.write().await;
+                let candidates = inner.messages.values().rev().map(|m| Candidate {
+                    id: &m.id,
+                    received_at: m.received_at,
+                    message_type: &m.message_type,
+                    tagged: !m.tags.is_empty(),
+                    bookmarked: m.bookmarked,
+                });
+                let expired = retention::select_expired(candidates, policy, now);
+                inner.remove_ids(&expired.iter().map(String::as_str).collect());
+                expired
+            }
+        };
+
+        if !expired.is_empty() {
+            info!("Retention sweep removed {} messages", expired.len());
+            let _ = self.tx.send(StoreEvent::Evicted(expired.clone()));
+        }
+        expired
+    }
+
+    /// Apply the retention policy every `sweep_interval_secs` until shutdown
+    pub async fn run_sweeper(self, policy: RetentionConfig, mut shutdown: watch::Receiver<bool>) {
+        let mut interval = tokio::time::interval(policy.sweep_interval());
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    self.sweep(&policy, Utc::now()).await;
+                }
+                _ = shutdown.changed() => break,
+            }
         }
-        None
     }
 
     /// Clear all messages
     pub async fn clear(&self) {
+        if let Some(db) = &self.db {
+            db_result(db.clear().await, "clear");
+        }
         let mut inner = self.inner.write().await;
-        inner.messages.clear();
-        inner.current_bytes = 0;
+        inner.clear();
+        self.merges
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clear();
+        if let Some(cache) = &self.detail_cache {
+            cache.lock().unwrap_or_else(|e| e.into_inner()).clear();
+        }
+        if let Some(tracker) = &self.duplicates {
+            tracker.lock().unwrap_or_else(|e| e.into_inner()).clear();
+        }
         info!("Message store cleared");
         let _ = self.tx.send(StoreEvent::Cleared);
     }
 }
 
+/// Host part of a peer address (`10.0.0.5:41234` → `10.0.0.5`)
+fn source_host(addr: &str) -> &str {
+    addr.rsplit_once(':').map_or(addr, |(host, _)| host)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -221,7 +864,9 @@ mod tests {
         MessageStore::new(StoreConfig {
             max_messages,
             max_memory_mb: 512,
+            ..Default::default()
         })
+        .unwrap()
     }
 
     fn make_msg(id: &str) -> Hl7Message {
@@ -274,6 +919,53 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_search_by_listener() {
+        let store = make_store(100);
+        let mut adt = make_msg("adt");
+        adt.listener = "ADT channel".into();
+        adt.message_type = "ADT^A01".into();
+        let mut lab = make_msg("lab");
+        lab.listener = "Lab channel".into();
+        lab.message_type = "ADT^A01".into();
+        store.insert(adt).await;
+        store.insert(lab).await;
+
+        let all = store.search("ADT^A01", None, 10).await;
+        assert_eq!(all.len(), 2);
+        let lab_only = store.search("ADT^A01", Some("Lab channel"), 10).await;
+        assert_eq!(lab_only.len(), 1);
+        assert_eq!(lab_only[0].listener, "Lab channel");
+        assert_eq!(store.search("adt channel", None, 10).await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_record_replay() {
+        let store = make_store(100);
+        store.insert(make_msg("r")).await;
+        let mut rx = store.subscribe();
+
+        let record = ReplayRecord {
+            target: "127.0.0.1:2576".into(),
+            target_name: None,
+            sent_at: chrono::Utc::now(),
+            latency_ms: Some(12),
+            ack_response: Some("MSH|^~\\&\rMSA|AA|1".into()),
+            ack_code: Some("AA".into()),
+            error: None,
+        };
+        assert!(store.record_replay("r", record.clone()).await);
+        assert!(!store.record_replay("missing", record).await);
+
+        let fetched = store.get_by_id("r").await.unwrap();
+        assert_eq!(fetched.replays.len(), 1);
+        assert_eq!(fetched.replays[0].ack_code.as_deref(), Some("AA"));
+        match rx.recv().await.unwrap() {
+            StoreEvent::Replayed(summary) => assert_eq!(summary.replay_count, 1),
+            _ => panic!("Expected Replayed event"),
+        }
+    }
+
     #[tokio::test]
     async fn test_bookmarked_message_survives_eviction() {
         let store = make_store(10);
@@ -294,4 +986,508 @@ mod tests {
         // The first non-bookmarked message should be evicted
         assert!(store.get_by_id("msg-1").await.is_none());
     }
+
+    #[tokio::test]
+    async fn test_sqlite_backend_round_trip() {
+        let path = std::env::temp_dir().join(format!("hl7-forge-{}.db", uuid::Uuid::new_v4()));
+        let config = StoreConfig {
+            backend: StoreBackend::Sqlite,
+            sqlite_path: path.clone(),
+            ..Default::default()
+        };
+        {
+            let store = MessageStore::new(config.clone()).unwrap();
+            let mut rx = store.subscribe();
+            store.insert(make_msg("s1")).await;
+            assert!(matches!(
+                rx.recv().await.unwrap(),
+                StoreEvent::NewMessage(_)
+            ));
+            assert!(store.add_tag("s1", "keep".into()).await);
+            assert!(!store.add_tag("s1", "keep".into()).await);
+            assert_eq!(store.toggle_bookmark("s1").await, Some(true));
+            assert_eq!(store.toggle_bookmark("missing").await, None);
+        }
+
+        // A fresh store on the same file sees the persisted message and its state
+        let store = MessageStore::new(config).unwrap();
+        assert_eq!(store.count().await, 1);
+        let msg = store.get_by_id("s1").await.unwrap();
+        assert_eq!(msg.tags, vec!["keep".to_string()]);
+        assert!(msg.bookmarked);
+        assert_eq!(store.search("127.0.0.1", None, 10).await.len(), 1);
+        // max_memory_mb does not apply to rows on disk
+        assert_eq!(store.memory_usage().await.1, None);
+
+        store.clear().await;
+        assert_eq!(store.count().await, 0);
+        drop(store);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sweep_broadcasts_evicted_ids() {
+        let store = make_store(100);
+        let mut old = make_msg("old");
+        old.received_at = Utc::now() - chrono::Duration::days(30);
+        store.insert(old).await;
+        store.insert(make_msg("new")).await;
+        let mut rx = store.subscribe();
+
+        let policy = RetentionConfig {
+            max_age_days: Some(7),
+            ..Default::default()
+        };
+        assert_eq!(store.sweep(&policy, Utc::now()).await, vec!["old"]);
+        assert_eq!(store.count().await, 1);
+        assert!(store.get_by_id("new").await.is_some());
+        match rx.recv().await.unwrap() {
+            StoreEvent::Evicted(ids) => assert_eq!(ids, vec!["old"]),
+            _ => panic!("Expected Evicted event"),
+        }
+
+        // Nothing left to remove: no event
+        assert!(store.sweep(&policy, Utc::now()).await.is_empty());
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_search_filter() {
+        use crate::hl7::parser::parse_message;
+        let store = make_store(100);
+        for (ward, control_id) in [("WARD1", "A"), ("WARD2", "B"), ("WARD1", "C")] {
+            let raw = format!(
+                "MSH|^~\\&|LIS|LAB|EHR|HOSP|20240101||ORU^R01|{control_id}|P|2.5\rPV1||I|{ward}^1"
+            );
+            let mut msg = parse_message(&raw, "127.0.0.1:5000").unwrap();
+            msg.id = control_id.to_string();
+            store.insert(msg).await;
+        }
+
+        let filter = SearchFilter::parse("MSH-9.1=ORU AND PV1-3.1=WARD1")
+            .unwrap()
+            .unwrap();
+        let ids: Vec<String> = store
+            .search_filter(filter, None, 10)
+            .await
+            .0
+            .into_iter()
+            .map(|s| s.id)
+            .collect();
+        assert_eq!(ids, vec!["C", "A"]);
+
+        let filter = SearchFilter::parse("NOT PV1-3.1=WARD1").unwrap().unwrap();
+        assert_eq!(store.search_filter(filter, None, 10).await.0[0].id, "B");
+        let filter = SearchFilter::parse("PV1-3.1=WARD1").unwrap().unwrap();
+        assert_eq!(store.search_filter(filter, None, 1).await.0.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_search_pattern() {
+        let store = make_store(100);
+        for (id, raw) in [
+            ("a", "MSH|^~\\&|X\rOBX|1|NM|GLU||95"),
+            ("b", "MSH|^~\\&|X\rOBX|1|ST|NOTE||text"),
+        ] {
+            let mut msg = Hl7Message::new_empty(raw.into(), "127.0.0.1:5000".into());
+            msg.id = id.into();
+            store.insert(msg).await;
+        }
+
+        let search = PatternSearch::new(r"OBX\|\d+\|NM", None).unwrap();
+        let (results, timed_out) = store.search_pattern(search, None, 10).await;
+        assert!(!timed_out);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].id, "a");
+
+        let search = PatternSearch::new("^127\\.", Some("source_addr")).unwrap();
+        assert_eq!(store.search_pattern(search, None, 10).await.0.len(), 2);
+    }
+
+    fn make_indexed_msg(i: usize) -> Hl7Message {
+        let mut msg = make_msg(&format!("msg-{i}"));
+        msg.message_control_id = format!("CTRL{i}");
+        msg.patient_id = Some(format!("PAT{}", i % 100));
+        msg
+    }
+
+    #[tokio::test]
+    async fn test_indexes_follow_eviction_sweep_and_clear() {
+        let store = make_store(10);
+        for i in 0..10 {
+            store.insert(make_indexed_msg(i)).await;
+        }
+        assert_eq!(store.find_by_control_id("CTRL3").await[0].id, "msg-3");
+        assert_eq!(store.find_by_patient_id("PAT3").await.len(), 1);
+
+        // Capacity reached: msg-0 is evicted and disappears from every index
+        store.insert(make_indexed_msg(100)).await;
+        assert!(store.get_by_id("msg-0").await.is_none());
+        assert!(store.find_by_control_id("CTRL0").await.is_empty());
+        let pat0: Vec<String> = store
+            .find_by_patient_id("PAT0")
+            .await
+            .into_iter()
+            .map(|s| s.id)
+            .collect();
+        assert_eq!(pat0, vec!["msg-100"]);
+
+        // Retention sweep keeps the indexes in sync too
+        let mut old = make_indexed_msg(7);
+        old.id = "old".into();
+        old.message_control_id = "OLD".into();
+        old.received_at = chrono::Utc::now() - chrono::Duration::days(30);
+        store.insert(old).await;
+        let policy = RetentionConfig {
+            max_age_days: Some(1),
+            ..Default::default()
+        };
+        store.sweep(&policy, Utc::now()).await;
+        assert!(store.find_by_control_id("OLD").await.is_empty());
+        assert_eq!(store.find_by_patient_id("PAT7").await.len(), 1);
+
+        store.clear().await;
+        assert!(store.find_by_control_id("CTRL5").await.is_empty());
+        assert!(store.find_by_patient_id("PAT5").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_memory_accounting_tracks_parsed_size() {
+        let store = make_store(10);
+        let msg = make_indexed_msg(1);
+        let single = msg.memory_estimate();
+        assert!(single.parsed_bytes > single.raw_bytes);
+        store.insert(msg).await;
+        let stored = store.memory_usage().await.0;
+        assert_eq!(
+            (stored.raw_bytes, stored.parsed_bytes),
+            (single.raw_bytes, single.parsed_bytes)
+        );
+        // The map key and the control-ID and patient-ID index entries
+        assert_eq!(
+            stored.index_bytes,
+            map_entry_size("msg-1")
+                + 2 * id_copy_size("msg-1")
+                + index_key_size("CTRL1")
+                + index_key_size("PAT1")
+        );
+        // A second message for the same patient shares the index key
+        store.insert(make_indexed_msg(101)).await;
+        let shared = store.memory_usage().await.0.index_bytes - stored.index_bytes;
+        assert_eq!(
+            shared,
+            map_entry_size("msg-101") + 2 * id_copy_size("msg-101") + index_key_size("CTRL101")
+        );
+        store
+            .sweep(
+                &RetentionConfig {
+                    max_age_days: Some(0),
+                    ..Default::default()
+                },
+                Utc::now() + chrono::Duration::days(1),
+            )
+            .await;
+        assert_eq!(store.memory_usage().await.0, MemoryEstimate::default());
+        store.insert(make_indexed_msg(1)).await;
+
+        // Tagging grows the estimate and removing the tag shrinks it again
+        // (the tag vector keeps its capacity)
+        store.add_tag("msg-1", "follow-up".into()).await;
+        let tagged = store.memory_usage().await.0;
+        assert!(tagged.parsed_bytes > single.parsed_bytes);
+        store.remove_tag("msg-1", "follow-up").await;
+        assert!(store.memory_usage().await.0.parsed_bytes < tagged.parsed_bytes);
+
+        store.clear().await;
+        assert_eq!(store.memory_usage().await.0, MemoryEstimate::default());
+    }
+
+    #[tokio::test]
+    async fn test_memory_budget_evicts_on_parsed_size() {
+        // The budget would fit all 20 messages by raw size alone, but not
+        // once the parsed tree is counted
+        let raw_per_msg = make_indexed_msg(0).raw.len();
+        let store = make_store(1000);
+        store.inner.write().await.max_bytes = raw_per_msg * 20;
+        for i in 0..20 {
+            store.insert(make_indexed_msg(i)).await;
+        }
+        let (memory, budget) = store.memory_usage().await;
+        assert!(memory.raw_bytes < budget.unwrap());
+        assert!(store.count().await < 20);
+    }
+
+    #[tokio::test]
+    async fn test_lazy_mode_parses_detail_on_read() {
+        use crate::hl7::parser::parse_message;
+        let store = MessageStore::new(StoreConfig {
+            parse_mode: ParseMode::Lazy,
+            detail_cache_size: 2,
+            ..Default::default()
+        })
+        .unwrap();
+        let raw =
+            "MSH|^~\\&|APP|FAC|R|R|20240101||ADT^A01|C1|P|2.5\rPID|||123||Doe^Jane\rPV1||I|WARD1";
+        let mut msg = parse_message(raw, "127.0.0.1:5000").unwrap();
+        msg.id = "lazy".into();
+        let full = msg.memory_estimate();
+        store.insert(msg).await;
+
+        // Only raw text and summary fields are held
+        assert!(store.memory_usage().await.0.total() < full.total() / 2);
+        assert_eq!(store.list_summaries(0, 10).await[0].segment_count, 3);
+
+        let detail = store.get_by_id("lazy").await.unwrap();
+        assert!(!detail.detail_deferred);
+        assert_eq!(detail.segments.len(), 3);
+        assert!(detail.segments[1].description.is_some());
+        let cache = store.detail_cache.as_ref().unwrap();
+        assert!(cache.lock().unwrap().contains("lazy"));
+
+        // Field-path filters see the re-parsed segments
+        let filter = SearchFilter::parse("PV1-3=WARD1").unwrap().unwrap();
+        assert_eq!(store.search_filter(filter, None, 10).await.0.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_insert_flags_duplicates() {
+        let store = make_store(100);
+        let mut rx = store.subscribe();
+        let mut original = make_msg("orig");
+        original.message_control_id = "C1".into();
+        let mut retry = original.clone();
+        retry.id = "retry".into();
+
+        store.insert(original).await;
+        store.check_duplicate(&mut retry);
+        assert_eq!(retry.duplicate_of.as_deref(), Some("orig"));
+        // Checked on receipt, counted once
+        store.insert(retry).await;
+
+        assert_eq!(
+            store
+                .get_by_id("retry")
+                .await
+                .unwrap()
+                .duplicate_of
+                .as_deref(),
+            Some("orig")
+        );
+        assert!(store
+            .get_by_id("orig")
+            .await
+            .unwrap()
+            .duplicate_of
+            .is_none());
+        let _ = rx.recv().await;
+        match rx.recv().await {
+            Ok(StoreEvent::NewMessage(summary)) => {
+                assert_eq!(summary.duplicate_of.as_deref(), Some("orig"))
+            }
+            _ => panic!("expected NewMessage"),
+        }
+        assert_eq!(store.duplicate_counts(), (2, 1));
+
+        // A cleared store starts over: the same message is an original again
+        store.clear().await;
+        assert_eq!(store.duplicate_counts(), (0, 0));
+        let mut again = make_msg("again");
+        again.message_control_id = "C1".into();
+        store.check_duplicate(&mut again);
+        assert_eq!(again.duplicate_of, None);
+
+        let off = MessageStore::new(StoreConfig {
+            duplicate_window_secs: 0,
+            ..Default::default()
+        })
+        .unwrap();
+        let mut x = make_msg("x");
+        off.check_duplicate(&mut x);
+        assert_eq!(x.duplicate_of, None);
+        assert_eq!(off.duplicate_counts(), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn test_inbound_ack_links_to_message() {
+        use crate::hl7::parser::parse_message;
+        let store = make_store(100);
+        let mut rx = store.subscribe();
+        let mut original = parse_message(
+            "MSH|^~\\&|APP|FAC|R|R|20240101||ORU^R01|C42|P|2.5\rOBX|1|NM|GLU||95",
+            "127.0.0.1:5000",
+        )
+        .unwrap();
+        original.id = "orig".into();
+        let sent_at = original.received_at;
+        store.insert(original).await;
+        assert_eq!(store.list_unacked(0, 10).await.len(), 1);
+
+        let ack = |id: &str, code: &str, after_ms: i64| {
+            let mut ack = parse_message(
+                &format!("MSH|^~\\&|R|R|APP|FAC|20240101||ACK^R01|{id}|P|2.5\rMSA|{code}|C42"),
+                "127.0.0.1:6000",
+            )
+            .unwrap();
+            ack.id = id.into();
+            ack.received_at = sent_at + chrono::Duration::milliseconds(after_ms);
+            ack
+        };
+        store.insert(ack("app-ack", "AA", 250)).await;
+        // A late commit ACK does not replace the application ACK
+        store.insert(ack("commit-ack", "CA", 400)).await;
+
+        let orig = store.get_by_id("orig").await.unwrap();
+        let link = orig.ack_link.unwrap();
+        assert_eq!(link.counterpart_id, "app-ack");
+        assert_eq!(link.ack_code.as_deref(), Some("AA"));
+        assert_eq!(link.round_trip_ms, 250);
+        let back = store.get_by_id("app-ack").await.unwrap().ack_link.unwrap();
+        assert_eq!(back.counterpart_id, "orig");
+        assert_eq!(back.round_trip_ms, 250);
+
+        // ACKs themselves are never "unacked"
+        assert!(store.list_unacked(0, 10).await.is_empty());
+
+        let mut linked = 0;
+        while let Ok(event) = rx.try_recv() {
+            if let StoreEvent::AckLinked(summary) = event {
+                assert_eq!(summary.id, "orig");
+                linked += 1;
+            }
+        }
+        assert_eq!(linked, 1);
+    }
+
+    #[tokio::test]
+    async fn test_ack_links_to_its_own_sender_on_control_id_collision() {
+        use crate::hl7::parser::parse_message;
+        let store = make_store(100);
+        let start = Utc::now();
+        let insert = |sender: &str, listener: &str, id: &str, after_ms: i64| {
+            let mut msg = parse_message(
+                &format!("MSH|^~\\&|{sender}|R|R|20240101||ORU^R01|C42|P|2.5\rOBX|1|NM|GLU||95"),
+                "10.0.0.1:5000",
+            )
+            .unwrap();
+            msg.id = id.into();
+            msg.listener = listener.into();
+            msg.received_at = start + chrono::Duration::milliseconds(after_ms);
+            msg
+        };
+        store.insert(insert("LAB|HOSP", "lab", "lab-adt", 0)).await;
+        store.insert(insert("LAB|HOSP", "lab", "lab-lab", 10)).await;
+        // Newest, but from another sender
+        store.insert(insert("RAD|CLINIC", "lab", "rad", 20)).await;
+
+        let mut ack = parse_message(
+            "MSH|^~\\&|R|R|LAB|HOSP|20240101||ACK^R01|A1|P|2.5\rMSA|AA|C42",
+            "10.0.0.1:6000",
+        )
+        .unwrap();
+        ack.id = "ack".into();
+        ack.listener = "lab".into();
+        ack.received_at = start + chrono::Duration::milliseconds(30);
+        store.insert(ack).await;
+
+        assert!(store.get_by_id("rad").await.unwrap().ack_link.is_none());
+        let link = store.get_by_id("ack").await.unwrap().ack_link.unwrap();
+        assert_eq!(link.counterpart_id, "lab-lab");
+
+        // The same listener beats a newer match on another one
+        let mut first = insert("LAB|HOSP", "adt", "adt", 40);
+        first.message_control_id = "C43".into();
+        store.insert(first).await;
+        let mut second = insert("LAB|HOSP", "lab", "lab", 50);
+        second.message_control_id = "C43".into();
+        store.insert(second).await;
+        let mut ack = parse_message(
+            "MSH|^~\\&|R|R|LAB|HOSP|20240101||ACK^R01|A2|P|2.5\rMSA|AA|C43",
+            "10.0.0.1:6001",
+        )
+        .unwrap();
+        ack.id = "ack2".into();
+        ack.listener = "adt".into();
+        ack.received_at = start + chrono::Duration::milliseconds(60);
+        store.insert(ack).await;
+        let link = store.get_by_id("ack2").await.unwrap().ack_link.unwrap();
+        assert_eq!(link.counterpart_id, "adt");
+    }
+
+    #[tokio::test]
+    async fn test_patient_lookup_follows_merges() {
+        use crate::hl7::parser::parse_message;
+        let store = make_store(100);
+        let insert = |raw: &str, id: &str| {
+            let mut msg = parse_message(raw, "127.0.0.1:5000").unwrap();
+            msg.id = id.into();
+            msg
+        };
+        store
+            .insert(insert(
+                "MSH|^~\\&|ADT|H|R|R|20240101||ADT^A01|1|P|2.5\rPID|||OLD^^^H||Doe^Jane",
+                "before",
+            ))
+            .await;
+        store
+            .insert(insert(
+                "MSH|^~\\&|ADT|H|R|R|20240102||ADT^A40|2|P|2.5\rPID|||NEW^^^H||Doe^Jane\rMRG|OLD^^^H",
+                "merge",
+            ))
+            .await;
+        store
+            .insert(insert(
+                "MSH|^~\\&|LAB|H|R|R|20240103||ORU^R01|3|P|2.5\rPID|||NEW^^^H||Doe^Jane",
+                "after",
+            ))
+            .await;
+
+        // The old MRN finds messages sent after the merge, and vice versa
+        for id in ["OLD", "NEW"] {
+            let ids: Vec<String> = store
+                .find_by_patient_id(id)
+                .await
+                .into_iter()
+                .map(|s| s.id)
+                .collect();
+            assert_eq!(ids, vec!["after", "merge", "before"], "lookup by {id}");
+        }
+        assert_eq!(store.linked_patient_ids("OLD"), vec!["OLD", "NEW"]);
+
+        store.clear().await;
+        assert_eq!(store.linked_patient_ids("OLD"), vec!["OLD"]);
+    }
+
+    /// Lookup cost must not grow with store size. Run with
+    /// `cargo test --release bench_indexed_lookups -- --ignored --nocapture`
+    #[tokio::test]
+    #[ignore]
+    async fn bench_indexed_lookups() {
+        async fn time_lookups(size: usize) -> std::time::Duration {
+            let store = make_store(size + 1);
+            for i in 0..size {
+                store.insert(make_indexed_msg(i)).await;
+            }
+            let start = std::time::Instant::now();
+            for round in 0..10_000 {
+                let i = (round * 7919) % size;
+                assert!(store.get_by_id(&format!("msg-{i}")).await.is_some());
+                assert!(!store
+                    .find_by_control_id(&format!("CTRL{i}"))
+                    .await
+                    .is_empty());
+            }
+            start.elapsed()
+        }
+
+        let small = time_lookups(1_000).await;
+        let large = time_lookups(100_000).await;
+        println!("10k lookups: 1k messages {small:?}, 100k messages {large:?}");
+        // A linear scan would be ~100x slower; allow generous noise
+        assert!(
+            large < small * 5,
+            "lookups slowed from {small:?} to {large:?}"
+        );
+    }
 }
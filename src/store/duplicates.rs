@@ -0,0 +1,188 @@
+/// Duplicate detection for senders that retry after a lost ACK.
+///
+/// A message is a duplicate when an earlier message from the same host was
+/// received within the window and either carried the same MSH-10 control ID
+/// (with the same MSH-3/MSH-4 sender) or had byte-identical raw content.
+/// The tracker remembers what it has seen independently of the store, so
+/// evicting the original does not hide a retry; clearing the store resets it.
+use crate::hl7::types::Hl7Message;
+use chrono::{DateTime, Duration, Utc};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+
+pub struct DuplicateTracker {
+    window: Duration,
+    /// Originals in arrival order, oldest first
+    recent: VecDeque<Seen>,
+    /// Key hash → ID of the original message
+    originals: HashMap<u64, String>,
+    checked: u64,
+    duplicates: u64,
+}
+
+struct Seen {
+    received_at: DateTime<Utc>,
+    id: String,
+    keys: Vec<u64>,
+}
+
+impl DuplicateTracker {
+    pub fn new(window: std::time::Duration) -> Self {
+        Self {
+            window: Duration::from_std(window).unwrap_or(Duration::MAX),
+            recent: VecDeque::new(),
+            originals: HashMap::new(),
+            checked: 0,
+            duplicates: 0,
+        }
+    }
+
+    /// ID of the original `msg` repeats, without recording `msg`
+    pub fn find(&mut self, msg: &Hl7Message) -> Option<String> {
+        self.expire(msg.received_at);
+        keys(msg)
+            .iter()
+            .find_map(|key| self.originals.get(key))
+            .cloned()
+    }
+
+    /// Like [`find`](Self::find), but also counts `msg` and remembers it
+    /// as an original when it is not a duplicate. Checking a message that
+    /// is already remembered as an original is a no-op.
+    pub fn check(&mut self, msg: &Hl7Message) -> Option<String> {
+        let original = self.find(msg);
+        if original.as_deref() == Some(msg.id.as_str()) {
+            return None;
+        }
+        self.checked += 1;
+        if original.is_some() {
+            self.duplicates += 1;
+            return original;
+        }
+        let keys = keys(msg);
+        for key in &keys {
+            self.originals.insert(*key, msg.id.clone());
+        }
+        self.recent.push_back(Seen {
+            received_at: msg.received_at,
+            id: msg.id.clone(),
+            keys,
+        });
+        None
+    }
+
+    /// `(messages checked, duplicates found)` since startup or the last clear
+    pub fn counts(&self) -> (u64, u64) {
+        (self.checked, self.duplicates)
+    }
+
+    /// Forget every original and reset the counts, for a cleared store
+    pub fn clear(&mut self) {
+        self.recent.clear();
+        self.originals.clear();
+        self.checked = 0;
+        self.duplicates = 0;
+    }
+
+    /// Forget originals that fell out of the window before `now`
+    fn expire(&mut self, now: DateTime<Utc>) {
+        let cutoff = now - self.window;
+        while let Some(seen) = self.recent.front() {
+            if seen.received_at >= cutoff {
+                break;
+            }
+            let seen = self.recent.pop_front().expect("front exists");
+            for key in seen.keys {
+                // A later original may have taken over the key
+                if self.originals.get(&key) == Some(&seen.id) {
+                    self.originals.remove(&key);
+                }
+            }
+        }
+    }
+}
+
+/// Hashes identifying `msg`: control ID (when present) and raw content,
+/// both scoped to the sending host
+fn keys(msg: &Hl7Message) -> Vec<u64> {
+    let host = super::source_host(&msg.source_addr);
+    let mut keys = Vec::with_capacity(2);
+    if !msg.message_control_id.is_empty() {
+        keys.push(hash((
+            "control_id",
+            host,
+            &msg.sending_application,
+            &msg.sending_facility,
+            &msg.message_control_id,
+        )));
+    }
+    keys.push(hash(("content", host, &msg.raw)));
+    keys
+}
+
+fn hash(value: impl Hash) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn msg(id: &str, control_id: &str, raw: &str, source: &str, secs: i64) -> Hl7Message {
+        let mut msg = Hl7Message::new_empty(raw.into(), source.into());
+        msg.id = id.into();
+        msg.message_control_id = control_id.into();
+        msg.sending_facility = "LAB".into();
+        msg.received_at = DateTime::<Utc>::from_timestamp(1_700_000_000 + secs, 0).unwrap();
+        msg
+    }
+
+    fn tracker() -> DuplicateTracker {
+        DuplicateTracker::new(std::time::Duration::from_secs(60))
+    }
+
+    #[test]
+    fn test_control_id_and_content_matches() {
+        let mut t = tracker();
+        assert_eq!(t.check(&msg("a", "C1", "raw-1", "10.0.0.1:4000", 0)), None);
+        // Retry on a new connection: same host, different port
+        assert_eq!(
+            t.check(&msg("b", "C1", "raw-1b", "10.0.0.1:4001", 5)),
+            Some("a".into())
+        );
+        // Same bytes without a control ID
+        assert_eq!(t.check(&msg("c", "", "raw-2", "10.0.0.1:4000", 6)), None);
+        assert_eq!(
+            t.check(&msg("d", "", "raw-2", "10.0.0.1:4000", 7)),
+            Some("c".into())
+        );
+        assert_eq!(t.counts(), (4, 2));
+    }
+
+    #[test]
+    fn test_other_host_is_not_a_duplicate() {
+        let mut t = tracker();
+        t.check(&msg("a", "C1", "raw", "10.0.0.1:4000", 0));
+        assert_eq!(t.check(&msg("b", "C1", "raw", "10.0.0.2:4000", 1)), None);
+    }
+
+    #[test]
+    fn test_window_expiry() {
+        let mut t = tracker();
+        t.check(&msg("a", "C1", "raw", "10.0.0.1:4000", 0));
+        assert_eq!(
+            t.find(&msg("b", "C1", "raw", "10.0.0.1:4000", 60)),
+            Some("a".into())
+        );
+        assert_eq!(t.check(&msg("c", "C1", "raw", "10.0.0.1:4000", 61)), None);
+        // `c` is the new original
+        assert_eq!(
+            t.check(&msg("d", "C1", "raw", "10.0.0.1:4000", 62)),
+            Some("c".into())
+        );
+        assert_eq!(t.counts(), (3, 1));
+    }
+}
@@ -0,0 +1,137 @@
+/// Patient identifier merges from ADT A34/A35/A36/A39/A40/A41.
+///
+/// A merge message carries the prior identifier in MRG-1 and the surviving
+/// one in PID-3 of the preceding PID segment (A40 may repeat the PID/MRG
+/// pair). Links are kept in both directions, so a lookup by either the old
+/// or the new identifier finds the whole chain of merged IDs. The registry
+/// outlives the merge message itself, so evicting it keeps the link.
+use crate::hl7::types::{Hl7Message, Hl7Segment};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+const MERGE_EVENTS: &[&str] = &["A34", "A35", "A36", "A39", "A40", "A41"];
+
+#[derive(Default)]
+pub struct PatientMerges {
+    links: HashMap<String, HashSet<String>>,
+}
+
+impl PatientMerges {
+    /// Link `prior` and `surviving`; returns false if they already were
+    pub fn record(&mut self, prior: &str, surviving: &str) -> bool {
+        let added = self
+            .links
+            .entry(prior.to_string())
+            .or_default()
+            .insert(surviving.to_string());
+        self.links
+            .entry(surviving.to_string())
+            .or_default()
+            .insert(prior.to_string());
+        added
+    }
+
+    /// `id` followed by every identifier merged with it, directly or
+    /// through a chain of merges
+    pub fn linked(&self, id: &str) -> Vec<String> {
+        let mut seen = vec![id.to_string()];
+        let mut queue = VecDeque::from([id]);
+        while let Some(current) = queue.pop_front() {
+            for next in self.links.get(current).into_iter().flatten() {
+                if !seen.contains(next) {
+                    seen.push(next.clone());
+                    queue.push_back(next);
+                }
+            }
+        }
+        seen
+    }
+
+    pub fn clear(&mut self) {
+        self.links.clear();
+    }
+}
+
+/// `(prior, surviving)` identifier pairs carried by a merge message
+pub fn merge_pairs(msg: &Hl7Message) -> Vec<(String, String)> {
+    if !msg.message_type.starts_with("ADT") || !MERGE_EVENTS.contains(&msg.trigger_event.as_str()) {
+        return Vec::new();
+    }
+    let mut pairs = Vec::new();
+    let mut surviving = None;
+    for segment in &msg.segments {
+        match segment.name.as_str() {
+            "PID" => surviving = first_component(segment, 3),
+            "MRG" => {
+                if let (Some(prior), Some(new)) = (first_component(segment, 1), &surviving) {
+                    if prior != *new {
+                        pairs.push((prior, new.clone()));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    pairs
+}
+
+/// First component of the first repetition, ignoring the assigning authority
+fn first_component(segment: &Hl7Segment, field: usize) -> Option<String> {
+    segment
+        .fields
+        .iter()
+        .find(|f| f.index == field)?
+        .repetition(1)?
+        .component(1)?
+        .subcomponent(1)
+        .filter(|id| !id.is_empty())
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hl7::parser::parse_message;
+
+    #[test]
+    fn test_merge_pairs() {
+        let a34 = parse_message(
+            "MSH|^~\\&|ADT|HOSP|R|R|20240101||ADT^A34|M1|P|2.5\rEVN|A34\rPID|||NEW1^^^HOSP||Doe^Jane\rMRG|OLD1^^^HOSP",
+            "127.0.0.1:5000",
+        )
+        .unwrap();
+        assert_eq!(merge_pairs(&a34), vec![("OLD1".into(), "NEW1".into())]);
+
+        // A40 with two PID/MRG groups
+        let a40 = parse_message(
+            "MSH|^~\\&|ADT|HOSP|R|R|20240101||ADT^A40|M2|P|2.5\rEVN|A40\r\
+             PID|||N1||Doe^Jane\rMRG|O1\rPID|||N2||Roe^Rick\rMRG|O2~O2B",
+            "127.0.0.1:5000",
+        )
+        .unwrap();
+        assert_eq!(
+            merge_pairs(&a40),
+            vec![("O1".into(), "N1".into()), ("O2".into(), "N2".into())]
+        );
+
+        let a08 = parse_message(
+            "MSH|^~\\&|ADT|HOSP|R|R|20240101||ADT^A08|M3|P|2.5\rPID|||N1\rMRG|O1",
+            "127.0.0.1:5000",
+        )
+        .unwrap();
+        assert!(merge_pairs(&a08).is_empty());
+    }
+
+    #[test]
+    fn test_linked_follows_chains() {
+        let mut merges = PatientMerges::default();
+        assert!(merges.record("A", "B"));
+        assert!(!merges.record("A", "B"));
+        merges.record("B", "C");
+        merges.record("X", "Y");
+        assert_eq!(merges.linked("A"), vec!["A", "B", "C"]);
+        assert_eq!(merges.linked("C"), vec!["C", "B", "A"]);
+        assert_eq!(merges.linked("Z"), vec!["Z"]);
+        merges.clear();
+        assert_eq!(merges.linked("A"), vec!["A"]);
+    }
+}
@@ -0,0 +1,183 @@
+/// Regex search (`/api/search?mode=regex`).
+///
+/// The pattern runs against the raw message by default, or against a chosen
+/// set of summary fields. Compiled patterns are capped at
+/// [`PATTERN_SIZE_LIMIT`] and a scan stops after [`SCAN_TIME_LIMIT`].
+use crate::hl7::types::Hl7Message;
+use regex::{Regex, RegexBuilder};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Upper bound on the compiled size of a search pattern
+pub const PATTERN_SIZE_LIMIT: usize = 1 << 20;
+
+/// A regex scan stops after this long and returns what it found so far
+pub const SCAN_TIME_LIMIT: Duration = Duration::from_secs(2);
+
+/// Compile `pattern` with the size guardrail applied
+pub fn compile(pattern: &str) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(pattern)
+        .size_limit(PATTERN_SIZE_LIMIT)
+        .dfa_size_limit(PATTERN_SIZE_LIMIT)
+        .build()
+}
+
+/// A message property a regex search can target, named as in the JSON API
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatternField {
+    Raw,
+    MessageType,
+    SendingFacility,
+    PatientName,
+    PatientId,
+    MessageControlId,
+    SourceAddr,
+    Listener,
+}
+
+impl FromStr for PatternField {
+    type Err = PatternError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.trim() {
+            "raw" => Self::Raw,
+            "message_type" => Self::MessageType,
+            "sending_facility" => Self::SendingFacility,
+            "patient_name" => Self::PatientName,
+            "patient_id" => Self::PatientId,
+            "message_control_id" => Self::MessageControlId,
+            "source_addr" => Self::SourceAddr,
+            "listener" => Self::Listener,
+            other => return Err(PatternError::UnknownField(other.to_string())),
+        })
+    }
+}
+
+impl PatternField {
+    fn value<'a>(&self, msg: &'a Hl7Message) -> &'a str {
+        match self {
+            Self::Raw => &msg.raw,
+            Self::MessageType => &msg.message_type,
+            Self::SendingFacility => &msg.sending_facility,
+            Self::PatientName => msg.patient_name.as_deref().unwrap_or(""),
+            Self::PatientId => msg.patient_id.as_deref().unwrap_or(""),
+            Self::MessageControlId => &msg.message_control_id,
+            Self::SourceAddr => &msg.source_addr,
+            Self::Listener => &msg.listener,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum PatternError {
+    /// The pattern is not valid regex syntax
+    InvalidPattern(String),
+    /// The compiled pattern exceeds [`PATTERN_SIZE_LIMIT`]
+    TooLarge,
+    UnknownField(String),
+}
+
+impl PatternError {
+    /// Machine-readable error code for API responses
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::InvalidPattern(_) => "invalid_regex",
+            Self::TooLarge => "regex_too_large",
+            Self::UnknownField(_) => "unknown_field",
+        }
+    }
+}
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidPattern(e) => write!(f, "{}", e),
+            Self::TooLarge => write!(
+                f,
+                "Pattern exceeds the compiled size limit of {} bytes",
+                PATTERN_SIZE_LIMIT
+            ),
+            Self::UnknownField(name) => write!(f, "Unknown search field '{}'", name),
+        }
+    }
+}
+
+/// A compiled regex and the fields it is matched against
+#[derive(Debug)]
+pub struct PatternSearch {
+    regex: Regex,
+    fields: Vec<PatternField>,
+}
+
+impl PatternSearch {
+    /// `fields` is a comma-separated list of field names; `None` or empty
+    /// means the raw message.
+    pub fn new(pattern: &str, fields: Option<&str>) -> Result<Self, PatternError> {
+        let fields = match fields.map(str::trim).filter(|f| !f.is_empty()) {
+            Some(list) => list
+                .split(',')
+                .map(str::parse)
+                .collect::<Result<Vec<_>, _>>()?,
+            None => vec![PatternField::Raw],
+        };
+        let regex = compile(pattern).map_err(|e| match e {
+            regex::Error::CompiledTooBig(_) => PatternError::TooLarge,
+            e => PatternError::InvalidPattern(e.to_string()),
+        })?;
+        Ok(Self { regex, fields })
+    }
+
+    pub fn matches(&self, msg: &Hl7Message) -> bool {
+        self.fields
+            .iter()
+            .any(|field| self.regex.is_match(field.value(msg)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn msg() -> Hl7Message {
+        let mut msg = Hl7Message::new_empty(
+            "MSH|^~\\&|APP|FAC\rOBX|1|NM|GLU||123".into(),
+            "10.0.0.5:4000".into(),
+        );
+        msg.message_type = "ORU^R01".into();
+        msg.patient_name = Some("Doe, Jane".into());
+        msg
+    }
+
+    #[test]
+    fn test_matches_raw_by_default() {
+        let search = PatternSearch::new(r"OBX\|\d+\|NM", None).unwrap();
+        assert!(search.matches(&msg()));
+        let search = PatternSearch::new(r"^ORU", None).unwrap();
+        assert!(!search.matches(&msg()));
+    }
+
+    #[test]
+    fn test_matches_selected_fields() {
+        let search = PatternSearch::new(r"^ORU\^R0[1-3]$", Some("message_type")).unwrap();
+        assert!(search.matches(&msg()));
+        let search = PatternSearch::new(r"(?i)^doe,", Some("message_type, patient_name")).unwrap();
+        assert!(search.matches(&msg()));
+        let search = PatternSearch::new(r"GLU", Some("patient_name")).unwrap();
+        assert!(!search.matches(&msg()));
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = PatternSearch::new("(unclosed", None).unwrap_err();
+        assert_eq!(err.code(), "invalid_regex");
+
+        let err = PatternSearch::new("x", Some("raw,nope")).unwrap_err();
+        assert_eq!(err.code(), "unknown_field");
+        assert!(err.to_string().contains("nope"));
+
+        // A large counted repetition blows past the compiled size limit
+        let err = PatternSearch::new(r"\w{5000}", None).unwrap_err();
+        assert_eq!(err.code(), "regex_too_large");
+    }
+}
@@ -0,0 +1,368 @@
+/// Field-path search filters for `/api/search`.
+///
+/// A query is a boolean expression over conditions and plain words:
+///
+/// ```text
+/// MSH-9.1=ORU AND (PV1-3.1=WARD1 OR PV1-3.1=WARD2) NOT OBX-8=N
+/// OBX-5>100   MSH-4~^LAB   PID-5.1="van der Berg"   smith
+/// ```
+///
+/// A condition is `PATH OP VALUE` with no spaces around the operator (quote
+/// values that contain spaces). Operators: `=` and `!=` (case-insensitive),
+/// `>`, `>=`, `<`, `<=` (numeric when both sides are numbers, otherwise
+/// lexicographic, which also orders HL7 timestamps) and `~` (regex). Unless
+/// the path pins them, every segment occurrence and repetition is checked and
+/// any match counts. Plain words match the summary fields like the plain-text
+/// search. Adjacent terms are joined by AND; `NOT` binds tighter than `AND`,
+/// which binds tighter than `OR`.
+use crate::hl7::path::FieldPath;
+use crate::hl7::types::Hl7Message;
+use regex::Regex;
+use std::cmp::Ordering;
+
+#[derive(Debug)]
+pub struct SearchFilter {
+    expr: Expr,
+}
+
+#[derive(Debug)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Condition(Condition),
+    /// Lowercased plain word, matched like the plain-text search
+    Text(String),
+}
+
+#[derive(Debug)]
+struct Condition {
+    path: FieldPath,
+    op: Op,
+    value: String,
+}
+
+#[derive(Debug)]
+enum Op {
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    Regex(Regex),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+    Word(String),
+}
+
+impl SearchFilter {
+    /// Parse a query. Returns `Ok(None)` when it contains no field condition,
+    /// so callers can fall back to the plain-text search.
+    pub fn parse(query: &str) -> Result<Option<Self>, String> {
+        let tokens = match tokenize(query) {
+            Ok(tokens) => tokens,
+            // A stray quote in plain text (`O"Brien`) is not a filter error
+            Err(e) if looks_like_filter(query) => return Err(e),
+            Err(_) => return Ok(None),
+        };
+        let has_condition = tokens.iter().any(|t| match t {
+            Token::Word(word) => split_condition(word).is_some(),
+            _ => false,
+        });
+        if !has_condition {
+            return Ok(None);
+        }
+
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.parse_or()?;
+        if let Some(token) = parser.tokens.get(parser.pos) {
+            return Err(format!("Unexpected {:?} in query", token));
+        }
+        Ok(Some(Self { expr }))
+    }
+
+    /// Messages stored without their segment detail are re-parsed first
+    pub fn matches(&self, msg: &Hl7Message) -> bool {
+        self.expr.matches(&msg.with_detail())
+    }
+}
+
+impl Expr {
+    fn matches(&self, msg: &Hl7Message) -> bool {
+        match self {
+            Expr::And(a, b) => a.matches(msg) && b.matches(msg),
+            Expr::Or(a, b) => a.matches(msg) || b.matches(msg),
+            Expr::Not(e) => !e.matches(msg),
+            Expr::Condition(c) => c.matches(msg),
+            Expr::Text(word) => super::matches_text(msg, word),
+        }
+    }
+}
+
+impl Condition {
+    fn matches(&self, msg: &Hl7Message) -> bool {
+        let values = msg.get_path_all(&self.path);
+        match &self.op {
+            // True when no value equals, including when the field is absent
+            Op::Ne => !values.iter().any(|v| v.eq_ignore_ascii_case(&self.value)),
+            Op::Eq => values.iter().any(|v| v.eq_ignore_ascii_case(&self.value)),
+            Op::Regex(re) => values.iter().any(|v| re.is_match(v)),
+            Op::Gt | Op::Ge | Op::Lt | Op::Le => values
+                .iter()
+                .filter(|v| !v.is_empty())
+                .any(|v| self.op.accepts(compare(v, &self.value))),
+        }
+    }
+}
+
+impl Op {
+    fn accepts(&self, ordering: Ordering) -> bool {
+        match self {
+            Op::Gt => ordering == Ordering::Greater,
+            Op::Ge => ordering != Ordering::Less,
+            Op::Lt => ordering == Ordering::Less,
+            Op::Le => ordering != Ordering::Greater,
+            _ => false,
+        }
+    }
+}
+
+/// Numeric comparison when both sides parse as numbers, otherwise lexicographic
+fn compare(value: &str, target: &str) -> Ordering {
+    match (value.trim().parse::<f64>(), target.parse::<f64>()) {
+        (Ok(a), Ok(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+        _ => value.cmp(target),
+    }
+}
+
+/// Split a query into parentheses, keywords and words, keeping quoted runs together
+fn tokenize(query: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = query.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            _ => {
+                let mut word = String::new();
+                let mut quoted = false;
+                while let Some(&c) = chars.peek() {
+                    if !quoted && (c.is_whitespace() || c == '(' || c == ')') {
+                        break;
+                    }
+                    if c == '"' {
+                        quoted = !quoted;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                if quoted {
+                    return Err(format!("Unterminated quote in '{}'", word));
+                }
+                tokens.push(match word.to_ascii_uppercase().as_str() {
+                    "AND" => Token::And,
+                    "OR" => Token::Or,
+                    "NOT" => Token::Not,
+                    _ => Token::Word(word),
+                });
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// Whether any whitespace-separated word of a query that failed to tokenize
+/// is a field condition
+fn looks_like_filter(query: &str) -> bool {
+    query
+        .split_whitespace()
+        .any(|word| split_condition(word.trim_matches(['(', ')'])).is_some())
+}
+
+/// Split `PATH OP VALUE` into its parts if `word` has that shape
+fn split_condition(word: &str) -> Option<(FieldPath, &str, &str)> {
+    let op_start = word.find(['=', '!', '>', '<', '~'])?;
+    let path = word[..op_start].parse().ok()?;
+    let rest = &word[op_start..];
+    let op = ["!=", ">=", "<=", "=", ">", "<", "~"]
+        .into_iter()
+        .find(|op| rest.starts_with(op))?;
+    Some((path, op, &rest[op.len()..]))
+}
+
+fn parse_condition(word: &str) -> Result<Expr, String> {
+    let Some((path, op, value)) = split_condition(word) else {
+        return Ok(Expr::Text(unquote(word).to_lowercase()));
+    };
+    let value = unquote(value).to_string();
+    let op = match op {
+        "=" => Op::Eq,
+        "!=" => Op::Ne,
+        ">" => Op::Gt,
+        ">=" => Op::Ge,
+        "<" => Op::Lt,
+        "<=" => Op::Le,
+        _ => Op::Regex(
+            super::pattern::compile(&value)
+                .map_err(|e| format!("Invalid regex in '{}': {}", word, e))?,
+        ),
+    };
+    Ok(Expr::Condition(Condition { path, op, value }))
+}
+
+fn unquote(s: &str) -> &str {
+    s.strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .unwrap_or(s)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_unary()?;
+        loop {
+            match self.peek() {
+                Some(Token::And) => self.pos += 1,
+                // Implicit AND between adjacent terms
+                Some(Token::Open | Token::Not | Token::Word(_)) => {}
+                _ => break,
+            }
+            expr = Expr::And(Box::new(expr), Box::new(self.parse_unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Not) => Ok(Expr::Not(Box::new(self.parse_unary()?))),
+            Some(Token::Open) => {
+                let expr = self.parse_or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(expr),
+                    _ => Err("Missing closing parenthesis".into()),
+                }
+            }
+            Some(Token::Word(word)) => parse_condition(&word),
+            Some(token) => Err(format!("Unexpected {:?} in query", token)),
+            None => Err("Query ends unexpectedly".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hl7::parser::parse_message;
+
+    const ORU: &str = "MSH|^~\\&|LIS|MAIN_LAB|EHR|HOSP|20240101120000||ORU^R01|C1|P|2.5\r\
+                       PID|||123||van der Berg^Anna\r\
+                       PV1||I|WARD1^101^A\r\
+                       OBX|1|NM|GLU||95|mg/dL\r\
+                       OBX|2|NM|CHOL||240|mg/dL";
+
+    fn matches(query: &str) -> bool {
+        let msg = parse_message(ORU, "127.0.0.1:9999").unwrap();
+        SearchFilter::parse(query)
+            .unwrap()
+            .expect("query should contain a condition")
+            .matches(&msg)
+    }
+
+    #[test]
+    fn test_plain_text_is_not_a_filter() {
+        assert!(SearchFilter::parse("ADT^A01").unwrap().is_none());
+        assert!(SearchFilter::parse("adt channel").unwrap().is_none());
+        assert!(SearchFilter::parse("").unwrap().is_none());
+        // Unbalanced quotes in plain text fall back to the text search
+        assert!(SearchFilter::parse("O\"Brien").unwrap().is_none());
+        assert!(SearchFilter::parse("\"Doe John").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_operators() {
+        assert!(matches("PV1-3.1=WARD1"));
+        assert!(matches("pv1-3.1=ward1"));
+        assert!(!matches("PV1-3.1=WARD2"));
+        assert!(matches("PV1-3.1!=WARD2"));
+        assert!(matches("OBX-5>100"));
+        assert!(matches("OBX-5>=240"));
+        assert!(!matches("OBX-5>240"));
+        assert!(matches("OBX-5<100"));
+        assert!(!matches("OBX[2]-5<100"));
+        assert!(matches("MSH-4~^MAIN"));
+        assert!(!matches("MSH-4~^LAB"));
+        assert!(matches("MSH-7>=20240101"));
+        assert!(matches("PID-5.1=\"van der Berg\""));
+        assert!(matches("NTE-3!=x")); // absent field is "not equal"
+        assert!(!matches("NTE-3=x"));
+    }
+
+    #[test]
+    fn test_boolean_combinations() {
+        assert!(matches("MSH-9.1=ORU AND PV1-3.1=WARD1"));
+        assert!(matches("MSH-9.1=ORU PV1-3.1=WARD1")); // implicit AND
+        assert!(!matches("MSH-9.1=ADT AND PV1-3.1=WARD1"));
+        assert!(matches("MSH-9.1=ADT OR PV1-3.1=WARD1"));
+        assert!(!matches("NOT PV1-3.1=WARD1"));
+        assert!(matches(
+            "MSH-9.1=ORU AND NOT (PV1-3.1=WARD2 OR PV1-3.1=WARD3)"
+        ));
+        assert!(matches("MSH-9.1=ORU OR MSH-9.1=ADT AND PV1-3.1=WARD9")); // AND binds tighter
+        assert!(matches("PV1-3.1=WARD1 main_lab")); // plain word on summary fields
+        assert!(!matches("PV1-3.1=WARD1 other"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for bad in [
+            "PV1-3.1=WARD1 AND",
+            "(PV1-3.1=WARD1",
+            "PV1-3.1=WARD1)",
+            "MSH-4~[unclosed",
+            "PID-5.1=\"open",
+            "O\"Brien PID-3=1",
+        ] {
+            assert!(SearchFilter::parse(bad).is_err(), "{bad} should fail");
+        }
+    }
+}
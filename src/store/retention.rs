@@ -0,0 +1,132 @@
+/// Retention policy evaluation shared by the memory and SQLite backends.
+///
+/// Backends feed their messages newest-first as [`Candidate`]s and delete
+/// whatever IDs [`select_expired`] returns.
+use crate::config::RetentionConfig;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+
+/// The fields of a stored message that retention rules look at
+pub struct Candidate<'a> {
+    pub id: &'a str,
+    pub received_at: DateTime<Utc>,
+    pub message_type: &'a str,
+    pub tagged: bool,
+    pub bookmarked: bool,
+}
+
+/// IDs of messages that violate `policy` at time `now`.
+///
+/// `candidates` must be ordered newest first so that the per-type limit keeps
+/// the most recent messages. Protected messages (bookmarked, or tagged with
+/// `keep_tagged`) are never selected but still count towards the limit.
+pub fn select_expired<'a>(
+    candidates: impl IntoIterator<Item = Candidate<'a>>,
+    policy: &RetentionConfig,
+    now: DateTime<Utc>,
+) -> Vec<String> {
+    let cutoff = policy
+        .max_age()
+        .and_then(|age| chrono::Duration::from_std(age).ok())
+        .and_then(|age| now.checked_sub_signed(age));
+    let mut per_type: HashMap<&str, usize> = HashMap::new();
+
+    candidates
+        .into_iter()
+        .filter_map(|c| {
+            let seen = per_type.entry(c.message_type).or_default();
+            *seen += 1;
+            if c.bookmarked || (policy.keep_tagged && c.tagged) {
+                return None;
+            }
+            let too_old = cutoff.is_some_and(|cutoff| c.received_at < cutoff);
+            let over_limit = policy.max_per_type.is_some_and(|max| *seen > max);
+            (too_old || over_limit).then(|| c.id.to_string())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(
+        id: &'static str,
+        age_days: i64,
+        message_type: &'static str,
+    ) -> Candidate<'static> {
+        Candidate {
+            id,
+            received_at: Utc::now() - chrono::Duration::days(age_days),
+            message_type,
+            tagged: false,
+            bookmarked: false,
+        }
+    }
+
+    #[test]
+    fn test_disabled_policy_selects_nothing() {
+        let policy = RetentionConfig::default();
+        let expired = select_expired(vec![candidate("a", 400, "ADT")], &policy, Utc::now());
+        assert!(expired.is_empty());
+    }
+
+    #[test]
+    fn test_max_age() {
+        let policy = RetentionConfig {
+            max_age_days: Some(7),
+            ..Default::default()
+        };
+        let candidates = vec![candidate("new", 1, "ADT"), candidate("old", 8, "ADT")];
+        assert_eq!(select_expired(candidates, &policy, Utc::now()), vec!["old"]);
+    }
+
+    #[test]
+    fn test_max_per_type_keeps_newest() {
+        let policy = RetentionConfig {
+            max_per_type: Some(2),
+            ..Default::default()
+        };
+        // Newest first
+        let candidates = vec![
+            candidate("adt-3", 0, "ADT"),
+            candidate("oru-1", 0, "ORU"),
+            candidate("adt-2", 1, "ADT"),
+            candidate("adt-1", 2, "ADT"),
+        ];
+        assert_eq!(
+            select_expired(candidates, &policy, Utc::now()),
+            vec!["adt-1"]
+        );
+    }
+
+    #[test]
+    fn test_protected_messages_are_kept() {
+        let policy = RetentionConfig {
+            max_age_days: Some(1),
+            keep_tagged: true,
+            ..Default::default()
+        };
+        let mut tagged = candidate("tagged", 5, "ADT");
+        tagged.tagged = true;
+        let mut bookmarked = candidate("bookmarked", 5, "ADT");
+        bookmarked.bookmarked = true;
+        let candidates = vec![tagged, bookmarked, candidate("plain", 5, "ADT")];
+        assert_eq!(
+            select_expired(candidates, &policy, Utc::now()),
+            vec!["plain"]
+        );
+
+        // Without keep_tagged, tags offer no protection (bookmarks still do)
+        let policy = RetentionConfig {
+            keep_tagged: false,
+            ..policy
+        };
+        let mut tagged = candidate("tagged", 5, "ADT");
+        tagged.tagged = true;
+        assert_eq!(
+            select_expired(vec![tagged], &policy, Utc::now()),
+            vec!["tagged"]
+        );
+    }
+}
@@ -0,0 +1,625 @@
+/// SQLite persistence backend for [`MessageStore`](super::MessageStore).
+///
+/// Each message is stored as one row: the full `Hl7Message` as JSON (raw text,
+/// tags, bookmark, ACK, validation warnings, …), its list summary as JSON, and
+/// a handful of plain columns used for ordering, searching and eviction.
+///
+/// `rusqlite::Connection` is blocking and not `Sync`, so every query runs on
+/// the blocking thread pool behind a mutex.
+use super::retention::{self, Candidate};
+use crate::config::RetentionConfig;
+use crate::hl7::types::{Hl7Message, Hl7MessageSummary};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS messages (
+    seq                INTEGER PRIMARY KEY AUTOINCREMENT,
+    id                 TEXT NOT NULL UNIQUE,
+    received_at        TEXT NOT NULL,
+    message_type       TEXT NOT NULL,
+    message_control_id TEXT NOT NULL,
+    sending_facility   TEXT NOT NULL,
+    patient_name       TEXT,
+    patient_id         TEXT,
+    source_addr        TEXT NOT NULL,
+    listener           TEXT NOT NULL,
+    bookmarked         INTEGER NOT NULL DEFAULT 0,
+    data               TEXT NOT NULL,
+    summary            TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_messages_control_id ON messages (message_control_id);
+CREATE INDEX IF NOT EXISTS idx_messages_patient_id ON messages (patient_id);
+CREATE TABLE IF NOT EXISTS patient_merges (
+    prior_id     TEXT NOT NULL,
+    surviving_id TEXT NOT NULL,
+    PRIMARY KEY (prior_id, surviving_id)
+);
+";
+
+pub struct SqliteStore {
+    conn: Arc<Mutex<Connection>>,
+    capacity: usize,
+}
+
+impl SqliteStore {
+    /// Open (or create) the database file and apply the schema.
+    pub fn open(path: &Path, capacity: usize) -> anyhow::Result<Self> {
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Cannot create {}", dir.display()))?;
+        }
+        let conn = Connection::open(path)
+            .with_context(|| format!("Cannot open SQLite database {}", path.display()))?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+            capacity,
+        })
+    }
+
+    /// Run `f` with the connection on the blocking thread pool.
+    async fn with_conn<T, F>(&self, f: F) -> anyhow::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> anyhow::Result<T> + Send + 'static,
+    {
+        let conn = Arc::clone(&self.conn);
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn.lock().unwrap_or_else(|e| e.into_inner());
+            f(&mut conn)
+        })
+        .await?
+    }
+
+    /// Insert a message, evicting the oldest 10% of non-bookmarked rows first
+    /// when the count limit is reached. Returns `(evicted, count_after_insert)`.
+    pub async fn insert(&self, msg: &Hl7Message) -> anyhow::Result<(usize, usize)> {
+        let row = Row::from_message(msg)?;
+        let capacity = self.capacity;
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            let count: usize = tx.query_row("SELECT COUNT(*) FROM messages", [], |r| r.get(0))?;
+            let mut evicted = 0;
+            if count >= capacity {
+                evicted = tx.execute(
+                    "DELETE FROM messages WHERE seq IN (
+                         SELECT seq FROM messages WHERE bookmarked = 0 ORDER BY seq LIMIT ?1
+                     )",
+                    params![count / 10],
+                )?;
+            }
+            row.insert(&tx)?;
+            tx.commit()?;
+            Ok((evicted, count - evicted + 1))
+        })
+        .await
+    }
+
+    pub async fn list_summaries(
+        &self,
+        offset: usize,
+        limit: usize,
+    ) -> anyhow::Result<Vec<Hl7MessageSummary>> {
+        self.with_conn(move |conn| {
+            let mut stmt = conn.prepare_cached(
+                "SELECT summary FROM messages ORDER BY seq DESC LIMIT ?1 OFFSET ?2",
+            )?;
+            let rows = stmt.query_map(params![limit, offset], |r| r.get::<_, String>(0))?;
+            rows.map(|json| Ok(serde_json::from_str(&json?)?)).collect()
+        })
+        .await
+    }
+
+    pub async fn get(&self, id: &str) -> anyhow::Result<Option<Hl7Message>> {
+        let id = id.to_string();
+        self.with_conn(move |conn| {
+            let json: Option<String> = conn
+                .query_row("SELECT data FROM messages WHERE id = ?1", [&id], |r| {
+                    r.get(0)
+                })
+                .optional()?;
+            json.map(|j| serde_json::from_str(&j).map_err(Into::into))
+                .transpose()
+        })
+        .await
+    }
+
+    /// Case-insensitive substring search over the summary columns, newest first.
+    pub async fn search(
+        &self,
+        query_lower: &str,
+        listener: Option<&str>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<Hl7MessageSummary>> {
+        let query = query_lower.to_string();
+        let listener = listener.map(str::to_string);
+        self.with_conn(move |conn| {
+            let mut stmt = conn.prepare_cached(
+                "SELECT summary FROM messages
+                 WHERE (?2 IS NULL OR listener = ?2)
+                   AND (instr(lower(message_type), ?1) > 0
+                     OR instr(lower(sending_facility), ?1) > 0
+                     OR instr(lower(coalesce(patient_name, '')), ?1) > 0
+                     OR instr(lower(coalesce(patient_id, '')), ?1) > 0
+                     OR instr(lower(message_control_id), ?1) > 0
+                     OR instr(source_addr, ?1) > 0
+                     OR instr(lower(listener), ?1) > 0)
+                 ORDER BY seq DESC LIMIT ?3",
+            )?;
+            let rows =
+                stmt.query_map(params![query, listener, limit], |r| r.get::<_, String>(0))?;
+            rows.map(|json| Ok(serde_json::from_str(&json?)?)).collect()
+        })
+        .await
+    }
+
+    pub async fn find_by_control_id(
+        &self,
+        control_id: &str,
+    ) -> anyhow::Result<Vec<Hl7MessageSummary>> {
+        self.find_where("message_control_id", control_id).await
+    }
+
+    pub async fn find_by_patient_id(
+        &self,
+        patient_id: &str,
+    ) -> anyhow::Result<Vec<Hl7MessageSummary>> {
+        self.find_where("patient_id", patient_id).await
+    }
+
+    /// Summaries of rows whose indexed `column` equals `value`, newest first
+    async fn find_where(
+        &self,
+        column: &'static str,
+        value: &str,
+    ) -> anyhow::Result<Vec<Hl7MessageSummary>> {
+        let value = value.to_string();
+        self.with_conn(move |conn| {
+            let mut stmt = conn.prepare_cached(&format!(
+                "SELECT summary FROM messages WHERE {} = ?1 ORDER BY seq DESC",
+                column
+            ))?;
+            let rows = stmt.query_map([&value], |r| r.get::<_, String>(0))?;
+            rows.map(|json| Ok(serde_json::from_str(&json?)?)).collect()
+        })
+        .await
+    }
+
+    /// Newest-first scan over full messages, returning the summaries of up to
+    /// `limit` messages accepted by `filter`. Used for searches that cannot be
+    /// expressed in SQL. Stops early once `deadline` has passed; the flag in
+    /// the result tells whether that happened.
+    pub async fn scan<F>(
+        &self,
+        listener: Option<&str>,
+        limit: usize,
+        deadline: Option<Instant>,
+        filter: F,
+    ) -> anyhow::Result<(Vec<Hl7MessageSummary>, bool)>
+    where
+        F: Fn(&Hl7Message) -> bool + Send + 'static,
+    {
+        let listener = listener.map(str::to_string);
+        self.with_conn(move |conn| {
+            let mut stmt = conn.prepare_cached(
+                "SELECT data FROM messages WHERE (?1 IS NULL OR listener = ?1) ORDER BY seq DESC",
+            )?;
+            let mut rows = stmt.query(params![listener])?;
+            let mut results = Vec::new();
+            while results.len() < limit {
+                if deadline.is_some_and(|d| Instant::now() >= d) {
+                    return Ok((results, true));
+                }
+                let Some(row) = rows.next()? else { break };
+                let msg: Hl7Message = serde_json::from_str(&row.get::<_, String>(0)?)?;
+                if filter(&msg) {
+                    results.push(Hl7MessageSummary::from(&msg));
+                }
+            }
+            Ok((results, false))
+        })
+        .await
+    }
+
+    /// Load a message, apply `f`, and write it back if `f` returns true.
+    /// Returns the (possibly updated) message, or `None` if it does not exist.
+    pub async fn update<F>(&self, id: &str, f: F) -> anyhow::Result<Option<(Hl7Message, bool)>>
+    where
+        F: FnOnce(&mut Hl7Message) -> bool + Send + 'static,
+    {
+        let id = id.to_string();
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            let json: Option<String> = tx
+                .query_row("SELECT data FROM messages WHERE id = ?1", [&id], |r| {
+                    r.get(0)
+                })
+                .optional()?;
+            let Some(json) = json else {
+                return Ok(None);
+            };
+            let mut msg: Hl7Message = serde_json::from_str(&json)?;
+            let changed = f(&mut msg);
+            if changed {
+                Row::from_message(&msg)?.update(&tx)?;
+                tx.commit()?;
+            }
+            Ok(Some((msg, changed)))
+        })
+        .await
+    }
+
+    pub async fn count(&self) -> anyhow::Result<usize> {
+        self.with_conn(
+            |conn| Ok(conn.query_row("SELECT COUNT(*) FROM messages", [], |r| r.get(0))?),
+        )
+        .await
+    }
+
+    pub async fn clear(&self) -> anyhow::Result<()> {
+        self.with_conn(|conn| {
+            conn.execute("DELETE FROM messages", [])?;
+            conn.execute("DELETE FROM patient_merges", [])?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Persist a patient identifier merge
+    pub async fn record_merge(&self, prior: &str, surviving: &str) -> anyhow::Result<()> {
+        let (prior, surviving) = (prior.to_string(), surviving.to_string());
+        self.with_conn(move |conn| {
+            conn.execute(
+                "INSERT OR IGNORE INTO patient_merges (prior_id, surviving_id) VALUES (?1, ?2)",
+                params![prior, surviving],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// All recorded merges as `(prior, surviving)` pairs. Blocking; called
+    /// once at startup.
+    pub fn load_merges(&self) -> anyhow::Result<Vec<(String, String)>> {
+        let conn = self.conn.lock().unwrap_or_else(|e| e.into_inner());
+        let mut stmt = conn.prepare("SELECT prior_id, surviving_id FROM patient_merges")?;
+        let rows = stmt.query_map([], |r| Ok((r.get(0)?, r.get(1)?)))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Delete every message that violates `policy`. Returns the removed IDs.
+    pub async fn sweep(
+        &self,
+        policy: &RetentionConfig,
+        now: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<String>> {
+        let policy = policy.clone();
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            let rows = {
+                let mut stmt = tx.prepare(
+                    "SELECT id, received_at, message_type, bookmarked,
+                            json_array_length(summary, '$.tags') > 0
+                     FROM messages ORDER BY seq DESC",
+                )?;
+                let rows = stmt.query_map([], |r| {
+                    Ok((
+                        r.get::<_, String>(0)?,
+                        r.get::<_, String>(1)?,
+                        r.get::<_, String>(2)?,
+                        r.get::<_, bool>(3)?,
+                        r.get::<_, bool>(4)?,
+                    ))
+                })?;
+                rows.collect::<Result<Vec<_>, _>>()?
+            };
+            let mut candidates = Vec::with_capacity(rows.len());
+            for (id, received_at, message_type, bookmarked, tagged) in &rows {
+                candidates.push(Candidate {
+                    id,
+                    received_at: DateTime::parse_from_rfc3339(received_at)?.with_timezone(&Utc),
+                    message_type,
+                    tagged: *tagged,
+                    bookmarked: *bookmarked,
+                });
+            }
+            let expired = retention::select_expired(candidates, &policy, now);
+            {
+                let mut stmt = tx.prepare("DELETE FROM messages WHERE id = ?1")?;
+                for id in &expired {
+                    stmt.execute([id])?;
+                }
+            }
+            tx.commit()?;
+            Ok(expired)
+        })
+        .await
+    }
+}
+
+/// Column values for one message row
+struct Row {
+    id: String,
+    received_at: String,
+    message_type: String,
+    message_control_id: String,
+    sending_facility: String,
+    patient_name: Option<String>,
+    patient_id: Option<String>,
+    source_addr: String,
+    listener: String,
+    bookmarked: bool,
+    data: String,
+    summary: String,
+}
+
+impl Row {
+    fn from_message(msg: &Hl7Message) -> anyhow::Result<Self> {
+        Ok(Self {
+            id: msg.id.clone(),
+            received_at: msg.received_at.to_rfc3339(),
+            message_type: msg.message_type.clone(),
+            message_control_id: msg.message_control_id.clone(),
+            sending_facility: msg.sending_facility.clone(),
+            patient_name: msg.patient_name.clone(),
+            patient_id: msg.patient_id.clone(),
+            source_addr: msg.source_addr.clone(),
+            listener: msg.listener.clone(),
+            bookmarked: msg.bookmarked,
+            data: serde_json::to_string(msg)?,
+            summary: serde_json::to_string(&Hl7MessageSummary::from(msg))?,
+        })
+    }
+
+    fn insert(&self, conn: &Connection) -> rusqlite::Result<()> {
+        conn.execute(
+            "INSERT INTO messages (id, received_at, message_type, message_control_id,
+                 sending_facility, patient_name, patient_id, source_addr, listener,
+                 bookmarked, data, summary)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            params![
+                self.id,
+                self.received_at,
+                self.message_type,
+                self.message_control_id,
+                self.sending_facility,
+                self.patient_name,
+                self.patient_id,
+                self.source_addr,
+                self.listener,
+                self.bookmarked,
+                self.data,
+                self.summary,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Rewrite the mutable columns (tags, bookmark, replays, … live in `data`)
+    fn update(&self, conn: &Connection) -> rusqlite::Result<()> {
+        conn.execute(
+            "UPDATE messages SET bookmarked = ?2, data = ?3, summary = ?4 WHERE id = ?1",
+            params![self.id, self.bookmarked, self.data, self.summary],
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// Temporary database file removed on drop
+    struct TempDb(PathBuf);
+
+    impl TempDb {
+        fn new() -> Self {
+            Self(std::env::temp_dir().join(format!("hl7-forge-{}.db", uuid::Uuid::new_v4())))
+        }
+    }
+
+    impl Drop for TempDb {
+        fn drop(&mut self) {
+            for suffix in ["", "-wal", "-shm"] {
+                let _ = std::fs::remove_file(format!("{}{}", self.0.display(), suffix));
+            }
+        }
+    }
+
+    fn make_msg(id: &str, message_type: &str) -> Hl7Message {
+        let mut msg = Hl7Message::new_empty(format!("raw-{id}"), "127.0.0.1:5000".into());
+        msg.id = id.to_string();
+        msg.message_type = message_type.to_string();
+        msg
+    }
+
+    #[tokio::test]
+    async fn test_messages_survive_reopen() {
+        let db = TempDb::new();
+        {
+            let store = SqliteStore::open(&db.0, 100).unwrap();
+            let mut msg = make_msg("a", "ADT^A01");
+            msg.ack_code = Some("AA".into());
+            msg.validation_warnings
+                .push(crate::validation::ValidationWarning {
+                    code: "MISSING_FIELD".into(),
+                    message: "PID-8 missing".into(),
+                    segment: "PID".into(),
+                    field: Some(8),
+                });
+            store.insert(&msg).await.unwrap();
+            store
+                .update("a", |m| {
+                    m.tags.push("Bug #1".into());
+                    m.bookmarked = true;
+                    true
+                })
+                .await
+                .unwrap();
+        }
+
+        let store = SqliteStore::open(&db.0, 100).unwrap();
+        let msg = store.get("a").await.unwrap().unwrap();
+        assert_eq!(msg.raw, "raw-a");
+        assert_eq!(msg.tags, vec!["Bug #1".to_string()]);
+        assert!(msg.bookmarked);
+        assert_eq!(msg.ack_code.as_deref(), Some("AA"));
+        assert_eq!(msg.validation_warnings.len(), 1);
+        let summaries = store.list_summaries(0, 10).await.unwrap();
+        assert_eq!(summaries[0].tags, vec!["Bug #1".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_pagination_newest_first() {
+        let db = TempDb::new();
+        let store = SqliteStore::open(&db.0, 100).unwrap();
+        for i in 0..5 {
+            store
+                .insert(&make_msg(&format!("m{i}"), "ADT^A01"))
+                .await
+                .unwrap();
+        }
+        let page: Vec<String> = store
+            .list_summaries(1, 2)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|s| s.id)
+            .collect();
+        assert_eq!(page, vec!["m3", "m2"]);
+        assert_eq!(store.count().await.unwrap(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_search_and_listener_filter() {
+        let db = TempDb::new();
+        let store = SqliteStore::open(&db.0, 100).unwrap();
+        let mut a = make_msg("a", "ADT^A01");
+        a.patient_name = Some("Müller, Hans".into());
+        a.listener = "ADT".into();
+        let mut b = make_msg("b", "ORU^R01");
+        b.listener = "LAB".into();
+        store.insert(&a).await.unwrap();
+        store.insert(&b).await.unwrap();
+
+        assert_eq!(store.search("adt^", None, 10).await.unwrap().len(), 1);
+        assert_eq!(store.search("hans", None, 10).await.unwrap()[0].id, "a");
+        assert_eq!(store.search("", Some("LAB"), 10).await.unwrap()[0].id, "b");
+        assert!(store.search("nothing", None, 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_eviction_skips_bookmarked() {
+        let db = TempDb::new();
+        let store = SqliteStore::open(&db.0, 10).unwrap();
+        for i in 0..10 {
+            store
+                .insert(&make_msg(&format!("m{i}"), "ADT^A01"))
+                .await
+                .unwrap();
+        }
+        store
+            .update("m0", |m| {
+                m.bookmarked = true;
+                true
+            })
+            .await
+            .unwrap();
+
+        let (evicted, count) = store.insert(&make_msg("trigger", "ADT^A01")).await.unwrap();
+        assert_eq!((evicted, count), (1, 10));
+        assert!(store.get("m0").await.unwrap().is_some());
+        assert!(store.get("m1").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_update_missing_and_clear() {
+        let db = TempDb::new();
+        let store = SqliteStore::open(&db.0, 10).unwrap();
+        assert!(store.update("none", |_| true).await.unwrap().is_none());
+        store.insert(&make_msg("a", "ADT^A01")).await.unwrap();
+        store.clear().await.unwrap();
+        assert_eq!(store.count().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_sweep_applies_retention_policy() {
+        let db = TempDb::new();
+        let store = SqliteStore::open(&db.0, 100).unwrap();
+        let mut old = make_msg("old", "ADT^A01");
+        old.received_at = Utc::now() - chrono::Duration::days(10);
+        let mut old_tagged = make_msg("old-tagged", "ADT^A01");
+        old_tagged.received_at = old.received_at;
+        old_tagged.tags.push("keep".into());
+        store.insert(&old).await.unwrap();
+        store.insert(&old_tagged).await.unwrap();
+        store.insert(&make_msg("new", "ADT^A01")).await.unwrap();
+
+        let policy = RetentionConfig {
+            max_age_days: Some(7),
+            keep_tagged: true,
+            ..Default::default()
+        };
+        let removed = store.sweep(&policy, Utc::now()).await.unwrap();
+        assert_eq!(removed, vec!["old"]);
+        assert_eq!(store.count().await.unwrap(), 2);
+        assert!(store.get("old-tagged").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_scan_filters_full_messages() {
+        let db = TempDb::new();
+        let store = SqliteStore::open(&db.0, 100).unwrap();
+        for (id, listener) in [("a", "LAB"), ("b", "ADT"), ("c", "LAB")] {
+            let mut msg = make_msg(id, "ADT^A01");
+            msg.listener = listener.into();
+            store.insert(&msg).await.unwrap();
+        }
+        let (lab, timed_out) = store
+            .scan(Some("LAB"), 10, None, |m| m.id != "c")
+            .await
+            .unwrap();
+        assert!(!timed_out);
+        assert_eq!(lab.len(), 1);
+        assert_eq!(lab[0].id, "a");
+        let (first_two, _) = store.scan(None, 2, None, |_| true).await.unwrap();
+        assert_eq!(first_two.len(), 2);
+
+        // An expired deadline stops the scan before the first row
+        let (none, timed_out) = store
+            .scan(None, 10, Some(Instant::now()), |_| true)
+            .await
+            .unwrap();
+        assert!(none.is_empty());
+        assert!(timed_out);
+    }
+
+    #[tokio::test]
+    async fn test_find_by_indexed_columns() {
+        let db = TempDb::new();
+        let store = SqliteStore::open(&db.0, 100).unwrap();
+        for (id, control_id, patient_id) in [("a", "C1", "P1"), ("b", "C2", "P1")] {
+            let mut msg = make_msg(id, "ADT^A01");
+            msg.message_control_id = control_id.into();
+            msg.patient_id = Some(patient_id.into());
+            store.insert(&msg).await.unwrap();
+        }
+        let by_control = store.find_by_control_id("C2").await.unwrap();
+        assert_eq!(by_control.len(), 1);
+        assert_eq!(by_control[0].id, "b");
+        let by_patient = store.find_by_patient_id("P1").await.unwrap();
+        assert_eq!(
+            by_patient.iter().map(|s| s.id.as_str()).collect::<Vec<_>>(),
+            vec!["b", "a"]
+        );
+        assert!(store.find_by_patient_id("P9").await.unwrap().is_empty());
+    }
+}
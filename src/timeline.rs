@@ -0,0 +1,334 @@
+/// Per-patient message timeline (`GET /api/patients/:id/timeline`).
+///
+/// Lists every stored message for a PID-3 patient ID in arrival order,
+/// grouped into ADT events, orders and results, and replays the ADT events
+/// through a simple encounter state machine to flag transitions that cannot
+/// happen, such as a discharge before any admit or a transfer after a
+/// discharge.
+///
+/// Only messages still in the store are considered, so a timeline that
+/// starts mid-stay (the admit was evicted) can flag a false
+/// `TRANSFER_BEFORE_ADMIT`.
+use crate::hl7::types::Hl7MessageSummary;
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+pub struct Timeline {
+    pub patient_id: String,
+    /// Other identifiers merged with `patient_id` by ADT merge messages;
+    /// their messages are part of the timeline
+    pub merged_ids: Vec<String>,
+    /// Name from the most recent message that carried one
+    pub patient_name: Option<String>,
+    /// Encounter state after the last ADT event
+    pub state: EncounterState,
+    pub events: Vec<TimelineEvent>,
+    /// Number of events with a flag
+    pub flag_count: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TimelineEvent {
+    #[serde(flatten)]
+    pub message: Hl7MessageSummary,
+    pub category: EventCategory,
+    /// Set when the event is not a valid transition from the current state
+    pub flag: Option<TransitionFlag>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventCategory {
+    Adt,
+    Order,
+    Result,
+    Other,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EncounterState {
+    /// No admit or registration seen yet
+    NotAdmitted,
+    PreAdmitted,
+    /// Inpatient admit (A01, A06)
+    Admitted,
+    /// Outpatient registration (A04, A07)
+    Registered,
+    Discharged,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TransitionFlag {
+    /// Machine-readable code, e.g. `"DISCHARGE_BEFORE_ADMIT"`
+    pub code: &'static str,
+    pub message: String,
+}
+
+/// Build the timeline from the patient's message summaries in any order.
+/// `linked_ids` starts with `patient_id`, followed by the merged identifiers.
+pub fn build(linked_ids: Vec<String>, mut summaries: Vec<Hl7MessageSummary>) -> Timeline {
+    summaries.sort_by_key(|s| s.received_at);
+    let patient_name = summaries.iter().rev().find_map(|s| s.patient_name.clone());
+
+    let mut state = EncounterState::NotAdmitted;
+    // What the last discharge ended, restored by a cancel discharge (A13)
+    let mut before_discharge = EncounterState::NotAdmitted;
+    let events: Vec<TimelineEvent> = summaries
+        .into_iter()
+        .map(|message| {
+            let category = categorize(&message.message_type);
+            let flag = if category == EventCategory::Adt {
+                let (next, flag) = transition(state, before_discharge, &message.trigger_event);
+                if next == EncounterState::Discharged && state != EncounterState::Discharged {
+                    before_discharge = state;
+                }
+                state = next;
+                flag
+            } else {
+                None
+            };
+            TimelineEvent {
+                message,
+                category,
+                flag,
+            }
+        })
+        .collect();
+
+    let mut linked_ids = linked_ids.into_iter();
+    Timeline {
+        patient_id: linked_ids.next().unwrap_or_default(),
+        merged_ids: linked_ids.collect(),
+        patient_name,
+        state,
+        flag_count: events.iter().filter(|e| e.flag.is_some()).count(),
+        events,
+    }
+}
+
+fn categorize(message_type: &str) -> EventCategory {
+    match message_type.split('^').next().unwrap_or_default() {
+        "ADT" => EventCategory::Adt,
+        "ORM" | "OML" | "OMG" | "OMI" | "OMP" | "RDE" | "RDS" => EventCategory::Order,
+        "ORU" | "OUL" | "MDM" => EventCategory::Result,
+        _ => EventCategory::Other,
+    }
+}
+
+/// Apply one ADT trigger event. Returns the next state and, for an invalid
+/// transition, a flag. Flagged events still move the state so one bad
+/// message does not cascade into flags on every later one. A cancel
+/// discharge returns to `before_discharge`, the state the discharge ended.
+fn transition(
+    state: EncounterState,
+    before_discharge: EncounterState,
+    event: &str,
+) -> (EncounterState, Option<TransitionFlag>) {
+    use EncounterState::*;
+    let active = matches!(state, Admitted | Registered);
+    let flag = |code: &'static str, what: &str| {
+        Some(TransitionFlag {
+            code,
+            message: format!("{}: {}", event, what),
+        })
+    };
+    match event {
+        "A01" | "A06" => (
+            Admitted,
+            if state == Admitted {
+                flag("ADMIT_WHILE_ADMITTED", "admit while already admitted")
+            } else {
+                None
+            },
+        ),
+        "A04" | "A07" => (
+            Registered,
+            if state == Admitted {
+                flag("REGISTER_WHILE_ADMITTED", "registration while admitted")
+            } else {
+                None
+            },
+        ),
+        "A05" => (
+            PreAdmitted,
+            if active {
+                flag(
+                    "PREADMIT_WHILE_ADMITTED",
+                    "pre-admit during an open encounter",
+                )
+            } else {
+                None
+            },
+        ),
+        "A02" => match state {
+            Admitted | Registered => (state, None),
+            Discharged => (
+                state,
+                flag("TRANSFER_AFTER_DISCHARGE", "transfer after discharge"),
+            ),
+            _ => (
+                state,
+                flag("TRANSFER_BEFORE_ADMIT", "transfer before admit"),
+            ),
+        },
+        "A03" => match state {
+            Admitted | Registered => (Discharged, None),
+            Discharged => (
+                Discharged,
+                flag("DISCHARGE_AFTER_DISCHARGE", "discharge after discharge"),
+            ),
+            _ => (
+                Discharged,
+                flag("DISCHARGE_BEFORE_ADMIT", "discharge before admit"),
+            ),
+        },
+        // Cancel admit / cancel transfer / cancel discharge
+        "A11" => match state {
+            Admitted | Registered | PreAdmitted => (NotAdmitted, None),
+            _ => (
+                state,
+                flag("CANCEL_WITHOUT_ADMIT", "cancel admit without an admit"),
+            ),
+        },
+        "A12" => match state {
+            Admitted | Registered => (state, None),
+            _ => (
+                state,
+                flag(
+                    "CANCEL_WITHOUT_TRANSFER",
+                    "cancel transfer outside an encounter",
+                ),
+            ),
+        },
+        "A13" => match state {
+            Discharged => (before_discharge, None),
+            _ => (
+                state,
+                flag(
+                    "CANCEL_WITHOUT_DISCHARGE",
+                    "cancel discharge without a discharge",
+                ),
+            ),
+        },
+        // Updates, merges, demographics and other events do not change the encounter
+        _ => (state, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hl7::types::Hl7Message;
+    use chrono::{DateTime, Utc};
+
+    fn summary(message_type: &str, secs: i64) -> Hl7MessageSummary {
+        let mut msg = Hl7Message::new_empty(String::new(), "127.0.0.1:5000".into());
+        msg.message_type = message_type.into();
+        msg.trigger_event = message_type.split('^').nth(1).unwrap_or("").into();
+        msg.patient_id = Some("P1".into());
+        msg.received_at = DateTime::<Utc>::from_timestamp(1_700_000_000 + secs, 0).unwrap();
+        Hl7MessageSummary::from(&msg)
+    }
+
+    fn flags(types: &[&str]) -> Vec<Option<&'static str>> {
+        let summaries = types
+            .iter()
+            .enumerate()
+            .map(|(i, t)| summary(t, i as i64))
+            .collect();
+        build(vec!["P1".into()], summaries)
+            .events
+            .iter()
+            .map(|e| e.flag.as_ref().map(|f| f.code))
+            .collect()
+    }
+
+    #[test]
+    fn test_valid_stay_has_no_flags() {
+        let timeline = build(
+            vec!["P1".into()],
+            vec![
+                summary("ADT^A03", 40),
+                summary("ADT^A01", 0),
+                summary("ORM^O01", 10),
+                summary("ADT^A02", 20),
+                summary("ORU^R01", 30),
+            ],
+        );
+        assert_eq!(timeline.flag_count, 0);
+        assert_eq!(timeline.state, EncounterState::Discharged);
+        let order: Vec<_> = timeline
+            .events
+            .iter()
+            .map(|e| (e.message.trigger_event.as_str(), e.category))
+            .collect();
+        assert_eq!(
+            order,
+            vec![
+                ("A01", EventCategory::Adt),
+                ("O01", EventCategory::Order),
+                ("A02", EventCategory::Adt),
+                ("R01", EventCategory::Result),
+                ("A03", EventCategory::Adt),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_impossible_transitions_are_flagged() {
+        assert_eq!(
+            flags(&["ADT^A03", "ADT^A01"]),
+            vec![Some("DISCHARGE_BEFORE_ADMIT"), None]
+        );
+        assert_eq!(
+            flags(&["ADT^A01", "ADT^A03", "ADT^A02"]),
+            vec![None, None, Some("TRANSFER_AFTER_DISCHARGE")]
+        );
+        assert_eq!(
+            flags(&["ADT^A01", "ADT^A01"]),
+            vec![None, Some("ADMIT_WHILE_ADMITTED")]
+        );
+        assert_eq!(
+            flags(&["ADT^A01", "ADT^A03", "ADT^A13", "ADT^A02", "ADT^A08"]),
+            vec![None, None, None, None, None]
+        );
+        assert_eq!(
+            flags(&["ADT^A02", "ADT^A13"]),
+            vec![
+                Some("TRANSFER_BEFORE_ADMIT"),
+                Some("CANCEL_WITHOUT_DISCHARGE")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cancel_discharge_restores_prior_state() {
+        let state = |types: &[&str]| {
+            let summaries = types
+                .iter()
+                .enumerate()
+                .map(|(i, t)| summary(t, i as i64))
+                .collect();
+            build(vec!["P1".into()], summaries).state
+        };
+        assert_eq!(
+            state(&["ADT^A04", "ADT^A03", "ADT^A13"]),
+            EncounterState::Registered
+        );
+        assert_eq!(
+            state(&["ADT^A01", "ADT^A03", "ADT^A13"]),
+            EncounterState::Admitted
+        );
+        // A repeated discharge keeps the state the first one ended
+        assert_eq!(
+            state(&["ADT^A04", "ADT^A03", "ADT^A03", "ADT^A13"]),
+            EncounterState::Registered
+        );
+        // Back to Registered, so a new registration is not REGISTER_WHILE_ADMITTED
+        assert_eq!(
+            flags(&["ADT^A04", "ADT^A03", "ADT^A13", "ADT^A04"]),
+            vec![None, None, None, None]
+        );
+    }
+}
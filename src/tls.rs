@@ -0,0 +1,296 @@
+/// TLS support for MLLP listeners (MLLP/S).
+///
+/// Certificates and keys are loaded from PEM files at startup. When a client
+/// CA bundle is configured, every client must present a certificate signed by
+/// it (mutual TLS); the verified subject is recorded on each stored message.
+use crate::config::TlsConfig;
+use anyhow::Context;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{RootCertStore, ServerConfig, ServerConnection};
+use tokio_rustls::TlsAcceptor;
+
+/// Build a TLS acceptor from the listener's certificate, key and optional client CA.
+pub fn build_acceptor(config: &TlsConfig) -> anyhow::Result<TlsAcceptor> {
+    let provider = Arc::new(ring::default_provider());
+    let certs = load_certs(&config.cert)?;
+    let key = load_key(&config.key)?;
+
+    let builder = ServerConfig::builder_with_provider(Arc::clone(&provider))
+        .with_safe_default_protocol_versions()?;
+
+    let builder = match &config.client_ca {
+        Some(ca_path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(ca_path)? {
+                roots
+                    .add(cert)
+                    .with_context(|| format!("Invalid CA certificate in {}", ca_path.display()))?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .context("Failed to build client certificate verifier")?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let server_config = builder
+        .with_single_cert(certs, key)
+        .with_context(|| format!("Certificate/key mismatch in {}", config.cert.display()))?;
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+/// Subject DN of the client certificate, if the client presented one.
+pub fn peer_subject(conn: &ServerConnection) -> Option<String> {
+    let cert = conn.peer_certificates()?.first()?;
+    let (_, parsed) = x509_parser::parse_x509_certificate(cert.as_ref()).ok()?;
+    Some(parsed.subject().to_string())
+}
+
+fn load_certs(path: &Path) -> anyhow::Result<Vec<CertificateDer<'static>>> {
+    let file = File::open(path).with_context(|| format!("Cannot open {}", path.display()))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Invalid PEM in {}", path.display()))?;
+    if certs.is_empty() {
+        anyhow::bail!("No certificates found in {}", path.display());
+    }
+    Ok(certs)
+}
+
+fn load_key(path: &Path) -> anyhow::Result<PrivateKeyDer<'static>> {
+    let file = File::open(path).with_context(|| format!("Cannot open {}", path.display()))?;
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .with_context(|| format!("Invalid PEM in {}", path.display()))?
+        .with_context(|| format!("No private key found in {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{MllpConfig, StoreConfig};
+    use crate::mllp::{extract_mllp_frame, start_mllp_server, wrap_mllp, MllpStats};
+    use crate::store::MessageStore;
+    use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, KeyPair};
+    use std::path::PathBuf;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_rustls::rustls::pki_types::ServerName;
+    use tokio_rustls::rustls::ClientConfig;
+    use tokio_rustls::TlsConnector;
+
+    /// Self-signed CA plus a server and a client certificate issued by it
+    struct TestPki {
+        dir: PathBuf,
+        ca_pem: String,
+        client_cert_pem: String,
+        client_key_pem: String,
+    }
+
+    impl TestPki {
+        fn generate() -> Self {
+            let dir = std::env::temp_dir().join(format!("hl7-forge-tls-{}", uuid::Uuid::new_v4()));
+            std::fs::create_dir_all(&dir).unwrap();
+
+            let ca_key = KeyPair::generate().unwrap();
+            let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+            ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            ca_params
+                .distinguished_name
+                .push(DnType::CommonName, "HL7 Forge Test CA");
+            let ca = ca_params.self_signed(&ca_key).unwrap();
+
+            let server_key = KeyPair::generate().unwrap();
+            let server = CertificateParams::new(vec!["localhost".to_string()])
+                .unwrap()
+                .signed_by(&server_key, &ca, &ca_key)
+                .unwrap();
+
+            let client_key = KeyPair::generate().unwrap();
+            let mut client_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+            client_params
+                .distinguished_name
+                .push(DnType::CommonName, "orchestra-test");
+            let client = client_params.signed_by(&client_key, &ca, &ca_key).unwrap();
+
+            std::fs::write(dir.join("ca.pem"), ca.pem()).unwrap();
+            std::fs::write(dir.join("server.pem"), server.pem()).unwrap();
+            std::fs::write(dir.join("server.key"), server_key.serialize_pem()).unwrap();
+
+            Self {
+                dir,
+                ca_pem: ca.pem(),
+                client_cert_pem: client.pem(),
+                client_key_pem: client_key.serialize_pem(),
+            }
+        }
+
+        fn tls_config(&self, mutual: bool) -> TlsConfig {
+            TlsConfig {
+                cert: self.dir.join("server.pem"),
+                key: self.dir.join("server.key"),
+                client_ca: mutual.then(|| self.dir.join("ca.pem")),
+            }
+        }
+
+        fn connector(&self, with_client_cert: bool) -> TlsConnector {
+            let mut roots = RootCertStore::empty();
+            for cert in rustls_pemfile::certs(&mut self.ca_pem.as_bytes()) {
+                roots.add(cert.unwrap()).unwrap();
+            }
+            let builder = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_root_certificates(roots);
+            let config = if with_client_cert {
+                let certs = rustls_pemfile::certs(&mut self.client_cert_pem.as_bytes())
+                    .collect::<Result<Vec<_>, _>>()
+                    .unwrap();
+                let key = rustls_pemfile::private_key(&mut self.client_key_pem.as_bytes())
+                    .unwrap()
+                    .unwrap();
+                builder.with_client_auth_cert(certs, key).unwrap()
+            } else {
+                builder.with_no_client_auth()
+            };
+            TlsConnector::from(Arc::new(config))
+        }
+    }
+
+    impl Drop for TestPki {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    /// Start an MLLP/S listener on a free local port and return its address
+    async fn start_tls_server(store: MessageStore, tls: TlsConfig) -> String {
+        let probe = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = probe.local_addr().unwrap().to_string();
+        drop(probe);
+
+        let acceptor = build_acceptor(&tls).unwrap();
+        let (shutdown_tx, rx) = tokio::sync::watch::channel(false);
+        let bind = addr.clone();
+        tokio::spawn(async move {
+            let _shutdown_tx = shutdown_tx; // keep the channel open for the server's lifetime
+            start_mllp_server(
+                &bind,
+                "tls-test",
+                store,
+                MllpStats::new(),
+                rx,
+                MllpConfig::default(),
+                Some(acceptor),
+                None,
+            )
+            .await
+            .unwrap();
+        });
+        // Wait until the listener accepts connections
+        for _ in 0..50 {
+            if TcpStream::connect(&addr).await.is_ok() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        addr
+    }
+
+    async fn send_over_tls(
+        addr: &str,
+        connector: TlsConnector,
+        message: &str,
+    ) -> std::io::Result<String> {
+        let tcp = TcpStream::connect(addr).await?;
+        let server_name = ServerName::try_from("localhost").unwrap();
+        let mut stream = connector.connect(server_name, tcp).await?;
+        stream.write_all(&wrap_mllp(message)).await?;
+
+        let mut buf = vec![0u8; 4096];
+        let mut accumulated = Vec::new();
+        loop {
+            let n = stream.read(&mut buf).await?;
+            if n == 0 {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+            accumulated.extend_from_slice(&buf[..n]);
+            if let Some((frame, _)) = extract_mllp_frame(&accumulated) {
+                return Ok(String::from_utf8_lossy(frame).into_owned());
+            }
+        }
+    }
+
+    const MSG: &str = "MSH|^~\\&|APP|FAC|R|R|20240101||ADT^A01|TLS001|P|2.5\rPID|||123";
+
+    fn make_store() -> MessageStore {
+        MessageStore::new(StoreConfig {
+            max_messages: 100,
+            max_memory_mb: 16,
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_mutual_tls_records_peer_subject() {
+        let pki = TestPki::generate();
+        let store = make_store();
+        let addr = start_tls_server(store.clone(), pki.tls_config(true)).await;
+
+        let ack = send_over_tls(&addr, pki.connector(true), MSG)
+            .await
+            .unwrap();
+        assert!(ack.contains("MSA|AA|TLS001"));
+
+        let stored = store.list_summaries(0, 10).await;
+        assert_eq!(stored.len(), 1);
+        let msg = store.get_by_id(&stored[0].id).await.unwrap();
+        assert_eq!(msg.listener, "tls-test");
+        let subject = msg.tls_peer_subject.unwrap();
+        assert!(subject.contains("CN=orchestra-test"), "got {subject}");
+    }
+
+    #[tokio::test]
+    async fn test_mutual_tls_rejects_client_without_certificate() {
+        let pki = TestPki::generate();
+        let store = make_store();
+        let addr = start_tls_server(store.clone(), pki.tls_config(true)).await;
+
+        let result = send_over_tls(&addr, pki.connector(false), MSG).await;
+        assert!(result.is_err());
+        assert_eq!(store.count().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_server_only_tls_has_no_peer_subject() {
+        let pki = TestPki::generate();
+        let store = make_store();
+        let addr = start_tls_server(store.clone(), pki.tls_config(false)).await;
+
+        let ack = send_over_tls(&addr, pki.connector(false), MSG)
+            .await
+            .unwrap();
+        assert!(ack.contains("MSA|AA|TLS001"));
+        let stored = store.list_summaries(0, 10).await;
+        let msg = store.get_by_id(&stored[0].id).await.unwrap();
+        assert!(msg.tls_peer_subject.is_none());
+    }
+
+    #[test]
+    fn test_build_acceptor_missing_files() {
+        let config = TlsConfig {
+            cert: PathBuf::from("/nonexistent/server.pem"),
+            key: PathBuf::from("/nonexistent/server.key"),
+            client_ca: None,
+        };
+        let err = build_acceptor(&config).err().unwrap();
+        assert!(err.to_string().contains("/nonexistent/server.pem"));
+    }
+}
@@ -1,14 +1,18 @@
 /// HL7 v2.x message validation.
 ///
 /// Checks required segments and fields per message type and returns a list
-/// of human-readable warnings.  Rules are intentionally pragmatic: they cover
-/// the most critical constraints from the HL7 v2.5.1 specification for the
-/// message types most commonly seen in hospital integration engines.
+/// of human-readable warnings.  Field-level rules come from the dictionary for
+/// the message's HL7 version: every segment present is checked for required
+/// fields, repetitions and lengths.  The hand-written rules per message type
+/// cover what the dictionary cannot express — which segments a message needs
+/// and fields that integration engines commonly treat as required even though
+/// the standard marks them optional or conditional.
 ///
 /// The validator is non-blocking — every message is stored regardless of
 /// warnings.  Warnings are surfaced in the UI so developers can spot missing
 /// fields at a glance without consulting the spec.
-use crate::hl7::types::{Hl7Message, Hl7Segment};
+use crate::dictionary::{FieldDef, Usage};
+use crate::hl7::types::{Hl7Component, Hl7Message, Hl7Repetition, Hl7Segment};
 use serde::{Deserialize, Serialize};
 
 // ─── Public types ─────────────────────────────────────────────────────────────
@@ -35,6 +39,9 @@ pub fn validate_message(msg: &Hl7Message) -> Vec<ValidationWarning> {
     // Always validate MSH required fields first
     validate_msh(msg, &mut warnings);
 
+    // Usage, cardinality and length of every field, from the dictionary
+    validate_fields(msg, &mut warnings);
+
     // Message-type–specific rules
     match msg.message_type.as_str() {
         t if t.starts_with("ADT^") => validate_adt(msg, &mut warnings),
@@ -47,6 +54,7 @@ pub fn validate_message(msg: &Hl7Message) -> Vec<ValidationWarning> {
     }
 
     validate_data_types(msg, &mut warnings);
+    validate_codes(msg, &mut warnings);
 
     warnings
 }
@@ -96,6 +104,8 @@ fn warn_missing_field(
 
 // ─── MSH — universal required fields ─────────────────────────────────────────
 
+/// MSH-3 and MSH-4 are optional in the standard but needed to route ACKs;
+/// the fields the standard requires are covered by [`validate_fields`].
 fn validate_msh(msg: &Hl7Message, warnings: &mut Vec<ValidationWarning>) {
     let Some(msh) = find_segment(msg, "MSH") else {
         // Without MSH the parser would have failed; guard anyway
@@ -117,28 +127,6 @@ fn validate_msh(msg: &Hl7Message, warnings: &mut Vec<ValidationWarning>) {
     if field_value(msh, 4).is_none() {
         warn_missing_field(warnings, "MSH", 4, "Sending Facility", "all HL7 messages");
     }
-    // MSH-9: Message Type
-    if field_value(msh, 9).is_none() {
-        warn_missing_field(warnings, "MSH", 9, "Message Type", "all HL7 messages");
-    }
-    // MSH-10: Message Control ID
-    if field_value(msh, 10).is_none() {
-        warn_missing_field(
-            warnings,
-            "MSH",
-            10,
-            "Message Control ID",
-            "all HL7 messages",
-        );
-    }
-    // MSH-11: Processing ID (P/T/D)
-    if field_value(msh, 11).is_none() {
-        warn_missing_field(warnings, "MSH", 11, "Processing ID", "all HL7 messages");
-    }
-    // MSH-12: Version ID
-    if field_value(msh, 12).is_none() {
-        warn_missing_field(warnings, "MSH", 12, "Version ID", "all HL7 messages");
-    }
 }
 
 // ─── ADT ──────────────────────────────────────────────────────────────────────
@@ -157,14 +145,7 @@ fn validate_adt(msg: &Hl7Message, warnings: &mut Vec<ValidationWarning>) {
         return; // Can't validate PID fields without the segment
     };
 
-    // PID-3: Patient Identifier List
-    if field_value(pid, 3).is_none() {
-        warn_missing_field(warnings, "PID", 3, "Patient Identifier List", ctx);
-    }
-    // PID-5: Patient Name
-    if field_value(pid, 5).is_none() {
-        warn_missing_field(warnings, "PID", 5, "Patient Name", ctx);
-    }
+    // PID-3 and PID-5 are required by the standard
     // PID-7: Date/Time of Birth (recommended)
     // PID-8: Administrative Sex
     if field_value(pid, 8).is_none() {
@@ -188,15 +169,9 @@ fn validate_adt(msg: &Hl7Message, warnings: &mut Vec<ValidationWarning>) {
             | "ADT^A12"
             | "ADT^A13"
     );
-    if pv1_required {
-        if let Some(pv1) = find_segment(msg, "PV1") {
-            // PV1-2: Patient Class (I/O/E/P/R/B/C/N/U)
-            if field_value(pv1, 2).is_none() {
-                warn_missing_field(warnings, "PV1", 2, "Patient Class", ctx);
-            }
-        } else {
-            warn_missing_segment(warnings, "PV1", ctx);
-        }
+    // PV1-2 (Patient Class) is required by the standard
+    if pv1_required && !has_segment(msg, "PV1") {
+        warn_missing_segment(warnings, "PV1", ctx);
     }
 }
 
@@ -215,10 +190,7 @@ fn validate_oru_r01(msg: &Hl7Message, warnings: &mut Vec<ValidationWarning>) {
     if field_value(obr, 3).is_none() {
         warn_missing_field(warnings, "OBR", 3, "Filler Order Number", ctx);
     }
-    // OBR-4: Universal Service Identifier
-    if field_value(obr, 4).is_none() {
-        warn_missing_field(warnings, "OBR", 4, "Universal Service Identifier", ctx);
-    }
+    // OBR-4 (Universal Service Identifier) is required by the standard
 
     // OBX is required (at least one observation)
     if !has_segment(msg, "OBX") {
@@ -235,24 +207,7 @@ fn validate_oru_r01(msg: &Hl7Message, warnings: &mut Vec<ValidationWarning>) {
                 field: Some(2),
             });
         }
-        // OBX-3: Observation Identifier
-        if field_value(seg, 3).is_none() {
-            warnings.push(ValidationWarning {
-                code: "MISSING_FIELD".into(),
-                message: "OBX-3 (Observation Identifier) is required for ORU^R01".into(),
-                segment: "OBX".into(),
-                field: Some(3),
-            });
-        }
-        // OBX-11: Observation Result Status
-        if field_value(seg, 11).is_none() {
-            warnings.push(ValidationWarning {
-                code: "MISSING_FIELD".into(),
-                message: "OBX-11 (Observation Result Status) is required for ORU^R01".into(),
-                segment: "OBX".into(),
-                field: Some(11),
-            });
-        }
+        // OBX-3 and OBX-11 are required by the standard
     }
 }
 
@@ -265,13 +220,10 @@ fn validate_orm_o01(msg: &Hl7Message, warnings: &mut Vec<ValidationWarning>) {
         warn_missing_segment(warnings, "PID", ctx);
     }
 
-    let Some(orc) = find_segment(msg, "ORC") else {
+    // ORC-1 (Order Control) is required by the standard
+    if !has_segment(msg, "ORC") {
         warn_missing_segment(warnings, "ORC", ctx);
         return;
-    };
-    // ORC-1: Order Control
-    if field_value(orc, 1).is_none() {
-        warn_missing_field(warnings, "ORC", 1, "Order Control", ctx);
     }
 
     if !has_segment(msg, "OBR") {
@@ -334,21 +286,99 @@ fn validate_mdm(msg: &Hl7Message, warnings: &mut Vec<ValidationWarning>) {
         warn_missing_segment(warnings, "PV1", ctx);
     }
 
-    let Some(txa) = find_segment(msg, "TXA") else {
+    // TXA-2 (Document Type) and TXA-17 (Document Completion Status) are
+    // required by the standard
+    // TXA-9: Originator Code/Name
+    if !has_segment(msg, "TXA") {
         warn_missing_segment(warnings, "TXA", ctx);
-        return;
-    };
-    // TXA-2: Document Type
-    if field_value(txa, 2).is_none() {
-        warn_missing_field(warnings, "TXA", 2, "Document Type", ctx);
     }
-    // TXA-9: Originator Code/Name
-    // TXA-17: Document Completion Status
-    if field_value(txa, 17).is_none() {
-        warn_missing_field(warnings, "TXA", 17, "Document Completion Status", ctx);
+}
+
+// ─── Dictionary-driven field rules ──────────────────────────────────────────
+
+/// Check every field of every known segment against the dictionary metadata
+/// for the message's version:
+/// - **usage R** — the field must be valued (`MISSING_FIELD`)
+/// - **max cardinality** — no more repetitions than allowed (`TOO_MANY_REPETITIONS`)
+/// - **max length** — each repetition fits, separators included (`FIELD_TOO_LONG`)
+///
+/// Conditional (C) and backward-compatible (B) fields are never required.
+fn validate_fields(msg: &Hl7Message, warnings: &mut Vec<ValidationWarning>) {
+    let dict = crate::dictionary::get(&msg.version);
+    for seg in &msg.segments {
+        let Some(seg_def) = dict.segments.get(&seg.name) else {
+            continue;
+        };
+        for def in &seg_def.fields {
+            let field = seg
+                .fields
+                .iter()
+                .find(|f| f.index == def.seq && !f.value.is_empty());
+            let Some(field) = field else {
+                if def.usage == Usage::Required {
+                    warnings.push(ValidationWarning {
+                        code: "MISSING_FIELD".into(),
+                        message: format!(
+                            "{}-{} ({}) is required in HL7 v{}",
+                            seg.name, def.seq, def.desc, dict.version
+                        ),
+                        segment: seg.name.clone(),
+                        field: Some(def.seq),
+                    });
+                }
+                continue;
+            };
+            if let Some(max) = def.max_cardinality {
+                let count = field.repetitions.len();
+                if count > max {
+                    warnings.push(ValidationWarning {
+                        code: "TOO_MANY_REPETITIONS".into(),
+                        message: format!(
+                            "{}-{} ({}) repeats {} times; at most {} allowed",
+                            seg.name, def.seq, def.desc, count, max
+                        ),
+                        segment: seg.name.clone(),
+                        field: Some(def.seq),
+                    });
+                }
+            }
+            if let Some(max) = def.max_length {
+                let longest = field
+                    .repetitions
+                    .iter()
+                    .map(repetition_length)
+                    .max()
+                    .unwrap_or(0);
+                if longest > max {
+                    warnings.push(ValidationWarning {
+                        code: "FIELD_TOO_LONG".into(),
+                        message: format!(
+                            "{}-{} ({}) is {} characters long; at most {} allowed",
+                            seg.name, def.seq, def.desc, longest, max
+                        ),
+                        segment: seg.name.clone(),
+                        field: Some(def.seq),
+                    });
+                }
+            }
+        }
     }
 }
 
+/// Length of one repetition with escape sequences decoded, counting the
+/// component and subcomponent separators
+fn repetition_length(rep: &Hl7Repetition) -> usize {
+    let component_length = |c: &Hl7Component| {
+        c.subcomponents
+            .iter()
+            .map(|s| s.chars().count())
+            .sum::<usize>()
+            + c.subcomponents.len().saturating_sub(1)
+    };
+    rep.components.iter().map(component_length).sum::<usize>()
+        + rep.components.len().saturating_sub(1)
+}
+
 // ─── Data type validation ────────────────────────────────────────────────────
 
 /// Validate field values against the data types declared in the HL7 dictionary.
@@ -356,27 +386,27 @@ fn validate_mdm(msg: &Hl7Message, warnings: &mut Vec<ValidationWarning>) {
 /// Covers the four primitive types with unambiguous, checkable formats:
 /// - **NM** (Numeric) — digits, optional sign, optional single decimal point
 /// - **DT** (Date) — YYYY[MM[DD]] — all digits, valid month/day ranges
-/// - **TS** (Timestamp) — YYYY[MM[DD[HH[MM[SS[.S+]]]]]][+/-HHMM]
+/// - **TS** / **DTM** (Timestamp) — YYYY[MM[DD[HH[MM[SS[.S+]]]]]][+/-HHMM]
 /// - **SI** (Sequence ID) — non-negative integer
 ///
-/// Composite types (XPN, CX, CE, …) and free-text types (ST, TX, FT, ID, IS)
-/// are intentionally skipped — they are too format-ambiguous or table-dependent
-/// to validate without value sets.
+/// Composite and free-text types (XPN, CX, CE, ST, TX, FT, …) are
+/// intentionally skipped — they are too format-ambiguous to validate. Coded
+/// ID and IS values are checked against their tables by `validate_codes`.
 ///
 /// Only the first component of each field is checked. Multi-component values
 /// (e.g. `CODE^Text^System`) are typical for composite types and are skipped
 /// at the `check_datatype` level.
+///
+/// Datatypes come from the dictionary selected by MSH-12, so a 2.7 message is
+/// checked against DTM where a 2.5 message has TS.
 fn validate_data_types(msg: &Hl7Message, warnings: &mut Vec<ValidationWarning>) {
-    let dict = crate::dictionary::get_v251();
+    let dict = crate::dictionary::get(&msg.version);
     for seg in &msg.segments {
-        let Some(seg_def) = dict.segments.get(&seg.name) else {
-            continue;
-        };
         for field in &seg.fields {
             if field.value.is_empty() {
                 continue; // empty values are caught by MISSING_FIELD rules
             }
-            let Some(field_def) = seg_def.fields.iter().find(|f| f.seq == field.index) else {
+            let Some(field_def) = dict.field(&seg.name, field.index) else {
                 continue;
             };
             // Use only the first component — composite values include sub-component
@@ -405,9 +435,89 @@ fn check_datatype(datatype: &str, value: &str) -> Option<String> {
     match datatype {
         "NM" => validate_nm(value).map(str::to_string),
         "DT" => validate_dt(value),
-        "TS" => validate_ts(value),
+        "TS" | "DTM" => validate_ts(value),
         "SI" => validate_si(value).map(str::to_string),
-        _ => None, // composite or table-dependent types are not validated
+        _ => None, // composite, free-text and coded types are not validated here
+    }
+}
+
+// ─── Code table validation ───────────────────────────────────────────────────
+
+/// Validate coded values against their HL7 code tables (`INVALID_CODE`).
+///
+/// ID and IS fields are checked against the table the dictionary names for
+/// them, and so are the ID and IS components of composite fields (MSH-9.2
+/// against event types 0003, XTN.2 against telecommunication use codes 0201).
+/// Every repetition is checked.
+///
+/// Values are skipped when the table is not known (see `code_tables`), when
+/// they are the HL7 null `""`, or when they start with `Z`, which HL7
+/// reserves for locally defined codes.
+fn validate_codes(msg: &Hl7Message, warnings: &mut Vec<ValidationWarning>) {
+    let dict = crate::dictionary::get(&msg.version);
+    for seg in &msg.segments {
+        for field in &seg.fields {
+            let Some(def) = dict.field(&seg.name, field.index) else {
+                continue;
+            };
+            let location = format!("{}-{} ({})", seg.name, field.index, def.desc);
+            if is_coded(&def.datatype) {
+                for rep in &field.repetitions {
+                    check_code(warnings, seg, field.index, &location, def, rep.component(1));
+                }
+                continue;
+            }
+            for component in dict.components(&def.datatype) {
+                if !is_coded(&component.datatype) {
+                    continue;
+                }
+                let location = format!(
+                    "{}-{}.{} ({})",
+                    seg.name, field.index, component.seq, component.desc
+                );
+                for rep in &field.repetitions {
+                    let value = rep.component(component.seq);
+                    check_code(warnings, seg, field.index, &location, component, value);
+                }
+            }
+        }
+    }
+}
+
+fn is_coded(datatype: &str) -> bool {
+    matches!(datatype, "ID" | "IS")
+}
+
+fn check_code(
+    warnings: &mut Vec<ValidationWarning>,
+    seg: &Hl7Segment,
+    field: usize,
+    location: &str,
+    def: &FieldDef,
+    value: Option<&Hl7Component>,
+) {
+    let Some(table_id) = &def.table else {
+        return;
+    };
+    let Some(code) = value.and_then(|c| c.subcomponent(1)) else {
+        return;
+    };
+    if code.is_empty() || code == "\"\"" || code.starts_with('Z') {
+        return;
+    }
+    let Some(table) = crate::code_tables::get(table_id) else {
+        return;
+    };
+    if !table.contains(code) {
+        warnings.push(ValidationWarning {
+            code: "INVALID_CODE".into(),
+            message: format!(
+                "{}: '{}' is not in HL7 table {} ({})",
+                location, code, table_id, table.desc
+            ),
+            segment: seg.name.clone(),
+            field: Some(field),
+        });
     }
 }
 
@@ -682,4 +792,162 @@ mod tests {
             warnings
         );
     }
+
+    #[test]
+    fn datatypes_follow_msh12_version() {
+        // MSH-7 is DTM in 2.7 and still checked as a timestamp
+        let raw = "MSH|^~\\&|APP|FAC|R|R|notadate||ADT^A01|MSG001|P|2.7\r\
+             EVN||20240101\r\
+             PID|||12345^^^HOSP||Smith^John^^||19800515|M\r\
+             PV1||I";
+        let msg = parse_message(raw, "127.0.0.1:9999").unwrap();
+        let warnings = validate_message(&msg);
+        let warning = warnings
+            .iter()
+            .find(|w| w.code == "INVALID_DATATYPE" && w.field == Some(7))
+            .expect("MSH-7 should be validated as DTM");
+        assert!(
+            warning.message.contains("Date/Time Of Message"),
+            "{}",
+            warning.message
+        );
+    }
+
+    // ── Dictionary-driven field rules ───────────────────────────────────────
+
+    #[test]
+    fn required_fields_checked_in_any_segment() {
+        // No message-type rules for ZZZ, but AL1-3 is required wherever AL1 appears
+        let raw = "MSH|^~\\&|APP|FAC|R|R|20240101||ZZZ^Z01|MSG001|P|2.5\rAL1|1|DA";
+        let msg = parse_message(raw, "127.0.0.1:9999").unwrap();
+        let warnings = validate_message(&msg);
+        let missing: Vec<_> = warnings
+            .iter()
+            .filter(|w| w.code == "MISSING_FIELD")
+            .map(|w| (w.segment.as_str(), w.field))
+            .collect();
+        assert_eq!(missing, vec![("AL1", Some(3))], "{warnings:?}");
+        assert_eq!(
+            warnings[0].message,
+            "AL1-3 (Allergen Code/Mnemonic/Description) is required in HL7 v2.5"
+        );
+    }
+
+    #[test]
+    fn cardinality_and_length_checked() {
+        let raw = format!(
+            "MSH|^~\\&|APP|FAC|R|R|20240101||ADT^A01|{}|P|2.5\r\
+             EVN||20240101\r\
+             PID|||12345||Smith^John||19800515|M~F\r\
+             PV1||I",
+            "X".repeat(21)
+        );
+        let msg = parse_message(&raw, "127.0.0.1:9999").unwrap();
+        let warnings = validate_message(&msg);
+        let found = |code: &str, seg: &str, field: usize| {
+            warnings
+                .iter()
+                .any(|w| w.code == code && w.segment == seg && w.field == Some(field))
+        };
+        assert!(found("TOO_MANY_REPETITIONS", "PID", 8), "{warnings:?}");
+        assert!(found("FIELD_TOO_LONG", "MSH", 10), "{warnings:?}");
+        // PID-3 repeats freely
+        assert!(!found("TOO_MANY_REPETITIONS", "PID", 3));
+    }
+
+    #[test]
+    fn truncation_character_allowed_from_v27() {
+        let warnings_for = |version: &str| {
+            let raw = format!(
+                "MSH|^~\\&#|APP|FAC|R|R|20240101||ADT^A01^ADT_A01|MSG001|P|{version}\r\
+                 EVN||20240101\r\
+                 PID|||12345||Smith^John||19800515|M\r\
+                 PV1||I"
+            );
+            let msg = parse_message(&raw, "127.0.0.1:9999").unwrap();
+            validate_message(&msg)
+                .into_iter()
+                .filter(|w| w.code == "FIELD_TOO_LONG" && w.segment == "MSH")
+                .collect::<Vec<_>>()
+        };
+        assert!(warnings_for("2.7").is_empty(), "{:?}", warnings_for("2.7"));
+        assert!(warnings_for("2.8").is_empty(), "{:?}", warnings_for("2.8"));
+        // Four encoding characters before 2.7
+        assert!(!warnings_for("2.5.1").is_empty());
+    }
+
+    #[test]
+    fn length_counts_separators_per_repetition() {
+        let raw = "MSH|^~\\&|APP|FAC|R|R|20240101||ADT^A01|MSG001|P|2.5\r\
+                   EVN||20240101\r\
+                   PID|||1||Smith^John||19800515|M\r\
+                   PV1||I|W^R^B||||||||||||||||12345678901234567890^^^HOSP&1.2&ISO~V2";
+        let msg = parse_message(raw, "127.0.0.1:9999").unwrap();
+        let pv1 = msg.segments.iter().find(|s| s.name == "PV1").unwrap();
+        let pv1_19 = pv1.fields.iter().find(|f| f.index == 19).unwrap();
+        assert_eq!(repetition_length(&pv1_19.repetitions[0]), 20 + 3 + 12);
+        assert_eq!(repetition_length(&pv1_19.repetitions[1]), 2);
+        // ...and PV1-19 does not repeat
+        let warnings = validate_message(&msg);
+        assert!(warnings
+            .iter()
+            .any(|w| w.code == "TOO_MANY_REPETITIONS" && w.field == Some(19)));
+    }
+
+    fn invalid_codes(raw: &str) -> Vec<ValidationWarning> {
+        let msg = parse_message(raw, "127.0.0.1:9999").unwrap();
+        validate_message(&msg)
+            .into_iter()
+            .filter(|w| w.code == "INVALID_CODE")
+            .collect()
+    }
+
+    #[test]
+    fn out_of_table_values_flagged() {
+        let warnings = invalid_codes(
+            "MSH|^~\\&|APP|FAC|R|R|20240101||ADT^A01|MSG001|P|2.5\r\
+             EVN||20240101\r\
+             PID|||12345||Smith^John||19800515|X\r\
+             PV1||Q",
+        );
+        assert_eq!(warnings.len(), 2, "{warnings:?}");
+        assert_eq!(
+            warnings[0].message,
+            "PID-8 (Administrative Sex): 'X' is not in HL7 table 0001 (Administrative Sex)"
+        );
+        assert_eq!(
+            (warnings[1].segment.as_str(), warnings[1].field),
+            ("PV1", Some(2))
+        );
+    }
+
+    #[test]
+    fn component_codes_flagged_in_every_repetition() {
+        let warnings = invalid_codes(
+            "MSH|^~\\&|APP|FAC|R|R|20240101||ADT^A99|MSG001|P|2.3\r\
+             EVN||20240101\r\
+             PID|||12345||Smith^John||19800515|M|||||^PRN^PH~^BAD^PH\r\
+             PV1||I",
+        );
+        let messages: Vec<_> = warnings.iter().map(|w| w.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "MSH-9.2 (Trigger Event): 'A99' is not in HL7 table 0003 (Event Type)",
+                "PID-13.2 (Telecommunication Use Code): 'BAD' is not in HL7 table 0201 \
+                 (Telecommunication Use Code)",
+            ]
+        );
+        assert_eq!(warnings[0].field, Some(9));
+    }
+
+    #[test]
+    fn local_and_null_codes_skipped() {
+        let warnings = invalid_codes(
+            "MSH|^~\\&|APP|FAC|R|R|20240101||ZZZ^Z01|MSG001|P|2.5\r\
+             PID|||12345||Smith^John||19800515|Z\r\
+             PV1||\"\"",
+        );
+        assert!(warnings.is_empty(), "{warnings:?}");
+    }
 }
@@ -1,5 +1,12 @@
+use crate::config::OutboundConfig;
+use crate::hl7::charset;
+use crate::hl7::parser::extract_ack_code;
+use crate::hl7::path::FieldPath;
+use crate::hl7::types::{Hl7MessageSummary, ReplayRecord};
 use crate::mllp::MllpStats;
-use crate::store::{MessageStore, StoreEvent};
+use crate::mllp_client;
+use crate::store::{MessageStore, PatternSearch, SearchFilter, StoreEvent};
+use crate::timeline;
 use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
 use axum::extract::{Path, Query, State};
 use axum::http::StatusCode;
@@ -7,7 +14,7 @@ use axum::response::{Html, IntoResponse};
 use axum::routing::get;
 use axum::{Json, Router};
 use rust_embed::Embed;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::sync::atomic::Ordering;
 
 #[derive(Embed)]
@@ -18,7 +25,16 @@ struct StaticAssets;
 pub struct AppState {
     pub store: MessageStore,
     pub stats: MllpStats,
-    pub mllp_port: u16,
+    pub listeners: Vec<ListenerInfo>,
+    pub outbound: OutboundConfig,
+}
+
+/// Static description of a running MLLP listener, reported by `/api/stats`
+#[derive(Clone, Serialize)]
+pub struct ListenerInfo {
+    pub label: String,
+    pub bind_addr: String,
+    pub port: u16,
     pub max_connections: usize,
 }
 
@@ -27,7 +43,9 @@ pub fn create_router(state: AppState) -> Router {
         // API routes
         .route("/api/messages", get(list_messages))
         .route("/api/messages/:id", get(get_message))
+        .route("/api/messages/:id/field", get(get_message_field))
         .route("/api/search", get(search_messages))
+        .route("/api/patients/:id/timeline", get(get_patient_timeline))
         .route("/api/stats", get(get_stats))
         .route("/api/messages/:id/tags", axum::routing::post(add_tag))
         .route(
@@ -38,6 +56,10 @@ pub fn create_router(state: AppState) -> Router {
             "/api/messages/:id/bookmark",
             axum::routing::post(toggle_bookmark),
         )
+        .route(
+            "/api/messages/:id/replay",
+            axum::routing::post(replay_message),
+        )
         .route("/api/clear", axum::routing::post(clear_messages))
         // WebSocket
         .route("/ws", get(ws_handler))
@@ -52,6 +74,13 @@ pub fn create_router(state: AppState) -> Router {
 struct ListParams {
     offset: Option<usize>,
     limit: Option<usize>,
+    /// Exact MSH-10 lookup
+    control_id: Option<String>,
+    /// Exact PID-3 lookup
+    patient_id: Option<String>,
+    /// Only messages that never received an application ACK
+    #[serde(default)]
+    unacked: bool,
 }
 
 async fn list_messages(
@@ -60,21 +89,87 @@ async fn list_messages(
 ) -> impl IntoResponse {
     let offset = params.offset.unwrap_or(0);
     let limit = params.limit.unwrap_or(100).min(1000);
-    let summaries = state.store.list_summaries(offset, limit).await;
-    Json(summaries)
+    let summaries = if let Some(control_id) = &params.control_id {
+        state.store.find_by_control_id(control_id).await
+    } else if let Some(patient_id) = &params.patient_id {
+        state.store.find_by_patient_id(patient_id).await
+    } else if params.unacked {
+        return Json(state.store.list_unacked(offset, limit).await);
+    } else {
+        return Json(state.store.list_summaries(offset, limit).await);
+    };
+    Json(summaries.into_iter().skip(offset).take(limit).collect())
 }
 
+/// The full message, plus the summary of its linked ACK (or, for an ACK, the
+/// acknowledged message) as `ack_counterpart` when one is still stored.
 async fn get_message(State(state): State<AppState>, Path(id): Path<String>) -> impl IntoResponse {
+    let Some(msg) = state.store.get_by_id(&id).await else {
+        return (StatusCode::NOT_FOUND, "Message not found").into_response();
+    };
+    let counterpart = match &msg.ack_link {
+        Some(link) => state
+            .store
+            .get_by_id(&link.counterpart_id)
+            .await
+            .map(|m| Hl7MessageSummary::from(&m)),
+        None => None,
+    };
+    let mut value = serde_json::to_value(msg).unwrap();
+    value["ack_counterpart"] = serde_json::to_value(counterpart).unwrap();
+    Json(value).into_response()
+}
+
+#[derive(Deserialize)]
+struct FieldParams {
+    path: String,
+}
+
+/// Evaluate a terser-style path (e.g. `PID-3[2].4.1`) against a stored message.
+/// `value` is null when the message has no such segment, field or part.
+async fn get_message_field(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(params): Query<FieldParams>,
+) -> impl IntoResponse {
+    let path: FieldPath = match params.path.parse() {
+        Ok(path) => path,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
     match state.store.get_by_id(&id).await {
-        Some(msg) => Json(serde_json::to_value(msg).unwrap()).into_response(),
+        Some(msg) => Json(serde_json::json!({
+            "path": path.to_string(),
+            "value": msg.get_path(&path),
+        }))
+        .into_response(),
         None => (StatusCode::NOT_FOUND, "Message not found").into_response(),
     }
 }
 
+/// Every stored message for a PID-3 patient ID in arrival order, with
+/// impossible ADT transitions flagged.
+async fn get_patient_timeline(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    let summaries = state.store.find_by_patient_id(&id).await;
+    if summaries.is_empty() {
+        return (StatusCode::NOT_FOUND, "No messages for this patient").into_response();
+    }
+    let linked_ids = state.store.linked_patient_ids(&id);
+    Json(timeline::build(linked_ids, summaries)).into_response()
+}
+
 #[derive(Deserialize)]
 struct SearchParams {
     q: String,
+    /// Restrict results to messages received by this listener label
+    listener: Option<String>,
     limit: Option<usize>,
+    /// `text` (default) or `regex`
+    mode: Option<String>,
+    /// Regex mode: comma-separated fields to match (default: the raw message)
+    fields: Option<String>,
 }
 
 async fn search_messages(
@@ -82,12 +177,57 @@ async fn search_messages(
     Query(params): Query<SearchParams>,
 ) -> impl IntoResponse {
     let limit = params.limit.unwrap_or(100).min(1000);
-    let results = state.store.search(&params.q, limit).await;
-    Json(results)
+    let listener = params.listener.as_deref();
+    match params.mode.as_deref().unwrap_or("text") {
+        "text" => {}
+        "regex" => {
+            let search = match PatternSearch::new(&params.q, params.fields.as_deref()) {
+                Ok(search) => search,
+                Err(e) => return search_error(e.code(), &e.to_string()),
+            };
+            let (results, timed_out) = state.store.search_pattern(search, listener, limit).await;
+            return timed_out_response(results, timed_out);
+        }
+        other => return search_error("invalid_mode", &format!("Unknown search mode '{}'", other)),
+    }
+
+    let (results, timed_out) = match SearchFilter::parse(&params.q) {
+        Ok(Some(filter)) => state.store.search_filter(filter, listener, limit).await,
+        Ok(None) => (state.store.search(&params.q, listener, limit).await, false),
+        Err(e) => return search_error("invalid_filter", &e),
+    };
+    timed_out_response(results, timed_out)
+}
+
+/// Search results with the `X-Search-Timed-Out` header; partial results are
+/// still returned when a scan hit its time cap
+fn timed_out_response(
+    results: Vec<Hl7MessageSummary>,
+    timed_out: bool,
+) -> axum::response::Response {
+    (
+        [(
+            "x-search-timed-out",
+            if timed_out { "true" } else { "false" },
+        )],
+        Json(results),
+    )
+        .into_response()
+}
+
+/// 400 response with a JSON body: `{"error": code, "message": message}`
+fn search_error(code: &str, message: &str) -> axum::response::Response {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(serde_json::json!({ "error": code, "message": message })),
+    )
+        .into_response()
 }
 
 async fn get_stats(State(state): State<AppState>) -> impl IntoResponse {
     let count = state.store.count().await;
+    let (memory, budget) = state.store.memory_usage().await;
+    let (checked, duplicates) = state.store.duplicate_counts();
     Json(serde_json::json!({
         "total_messages": count,
         "received": state.stats.received.load(Ordering::Relaxed),
@@ -95,8 +235,20 @@ async fn get_stats(State(state): State<AppState>) -> impl IntoResponse {
         "parse_errors": state.stats.parse_errors.load(Ordering::Relaxed),
         "active_connections": state.stats.active_connections.load(Ordering::Relaxed),
         "rejected_connections": state.stats.rejected_connections.load(Ordering::Relaxed),
-        "max_connections": state.max_connections,
-        "mllp_port": state.mllp_port,
+        "max_connections": state.listeners.iter().map(|l| l.max_connections).sum::<usize>(),
+        "mllp_port": state.listeners.first().map(|l| l.port),
+        "listeners": state.listeners,
+        "memory": {
+            "raw_bytes": memory.raw_bytes,
+            "parsed_bytes": memory.parsed_bytes,
+            "index_bytes": memory.index_bytes,
+            "total_bytes": memory.total(),
+            "budget_bytes": budget,
+        },
+        "duplicates": {
+            "count": duplicates,
+            "rate": if checked > 0 { duplicates as f64 / checked as f64 } else { 0.0 },
+        },
     }))
 }
 
@@ -151,6 +303,81 @@ async fn toggle_bookmark(
     }
 }
 
+#[derive(Deserialize)]
+struct ReplayPayload {
+    /// Name of a target from `[[outbound.targets]]`
+    target: Option<String>,
+    host: Option<String>,
+    port: Option<u16>,
+}
+
+async fn replay_message(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(payload): Json<ReplayPayload>,
+) -> impl IntoResponse {
+    let (addr, target_name) = match (&payload.target, &payload.host, payload.port) {
+        (Some(name), _, _) => match state.outbound.target(name) {
+            Some(target) => (target.addr(), Some(name.clone())),
+            None => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    format!("Unknown replay target '{name}'"),
+                )
+                    .into_response()
+            }
+        },
+        (None, Some(host), Some(port)) => (format!("{host}:{port}"), None),
+        _ => {
+            return (
+                StatusCode::BAD_REQUEST,
+                "Either target or host and port are required",
+            )
+                .into_response()
+        }
+    };
+
+    let Some(msg) = state.store.get_by_id(&id).await else {
+        return (StatusCode::NOT_FOUND, "Message not found").into_response();
+    };
+
+    let sent_at = chrono::Utc::now();
+    let record = match mllp_client::send_message(
+        &addr,
+        &charset::encode(&msg.raw, msg.encoding.as_deref()),
+        &state.outbound,
+    )
+    .await
+    {
+        Ok(outcome) => ReplayRecord {
+            target: addr,
+            target_name,
+            sent_at,
+            latency_ms: Some(outcome.latency.as_millis() as u64),
+            ack_code: extract_ack_code(&outcome.ack),
+            ack_response: Some(outcome.ack),
+            error: None,
+        },
+        Err(e) => ReplayRecord {
+            target: addr,
+            target_name,
+            sent_at,
+            latency_ms: None,
+            ack_response: None,
+            ack_code: None,
+            error: Some(e.to_string()),
+        },
+    };
+
+    let status = if record.error.is_some() {
+        StatusCode::BAD_GATEWAY
+    } else {
+        StatusCode::OK
+    };
+    state.store.record_replay(&id, record.clone()).await;
+    (status, Json(record)).into_response()
+}
+
 // --- WebSocket ---
 
 async fn ws_handler(ws: WebSocketUpgrade, State(state): State<AppState>) -> impl IntoResponse {
@@ -200,6 +427,42 @@ async fn handle_ws(mut socket: WebSocket, state: AppState) {
                             break; // client disconnected
                         }
                     }
+                    Ok(StoreEvent::Replayed(summary)) => {
+                        let payload = serde_json::json!({
+                            "type": "replayed",
+                            "data": summary,
+                        });
+                        if socket.send(Message::Text(payload.to_string())).await.is_err() {
+                            break; // client disconnected
+                        }
+                    }
+                    Ok(StoreEvent::AckLinked(summary)) => {
+                        let payload = serde_json::json!({
+                            "type": "ack_linked",
+                            "data": summary,
+                        });
+                        if socket.send(Message::Text(payload.to_string())).await.is_err() {
+                            break; // client disconnected
+                        }
+                    }
+                    Ok(StoreEvent::AckSent(summary)) => {
+                        let payload = serde_json::json!({
+                            "type": "ack_sent",
+                            "data": summary,
+                        });
+                        if socket.send(Message::Text(payload.to_string())).await.is_err() {
+                            break; // client disconnected
+                        }
+                    }
+                    Ok(StoreEvent::Evicted(ids)) => {
+                        let payload = serde_json::json!({
+                            "type": "evicted",
+                            "data": ids,
+                        });
+                        if socket.send(Message::Text(payload.to_string())).await.is_err() {
+                            break; // client disconnected
+                        }
+                    }
                     Ok(StoreEvent::Cleared) => {
                         let payload = serde_json::json!({
                             "type": "cleared"
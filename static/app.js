@@ -19,6 +19,7 @@ let paused = false;
 let pendingMessages = [];
 let renderScheduled = false;
 let showBookmarkedOnly = false;
+let showUnackedOnly = false;
 let validationFilter = 0; // 0: All, 1: Warnings, 2: Errors Only
 
 // Segment diff state
@@ -26,6 +27,9 @@ let diffPinnedMessage = null; // the reference message pinned for comparison
 let diffIgnoreDynamic = false;
 const DYNAMIC_DIFF_FIELDS = new Set(['MSH-7', 'MSH-10']);
 
+// --- Replay ---
+let lastReplayTarget = '';
+
 // --- Source Color Mapping ---
 // 12 visually distinct colors optimised for dark backgrounds (HSL, high sat, medium lightness)
 const SOURCE_PALETTE = [
@@ -135,6 +139,7 @@ function saveSession() {
             colorByPort,
             highlightedSource,
             showBookmarkedOnly,
+            showUnackedOnly,
             validationFilter,
             diffIgnoreDynamic
         }));
@@ -154,6 +159,7 @@ function loadSession() {
         if (typeof saved.colorByPort === 'boolean') colorByPort = saved.colorByPort;
         if (saved.highlightedSource !== undefined) highlightedSource = saved.highlightedSource;
         if (typeof saved.showBookmarkedOnly === 'boolean') showBookmarkedOnly = saved.showBookmarkedOnly;
+        if (typeof saved.showUnackedOnly === 'boolean') showUnackedOnly = saved.showUnackedOnly;
         if (typeof saved.validationFilter === 'number') validationFilter = saved.validationFilter;
         if (typeof saved.diffIgnoreDynamic === 'boolean') diffIgnoreDynamic = saved.diffIgnoreDynamic;
         if (Array.isArray(saved.collapsedSegments)) {
@@ -199,6 +205,15 @@ function connectWs() {
             updateMessageTags(data.data);
         } else if (data.type === 'bookmark_toggled') {
             updateMessageBookmark(data.data);
+        } else if (data.type === 'replayed') {
+            updateMessageReplays(data.data);
+        } else if (data.type === 'ack_sent') {
+            // An application ACK was sent after the message arrived
+            if (selectedMessage && selectedMessage.id === data.data.id) selectMessage(data.data.id);
+        } else if (data.type === 'ack_linked') {
+            updateMessageAckLink(data.data);
+        } else if (data.type === 'evicted') {
+            removeMessages(data.data);
         } else if (data.type === 'lagged') {
             console.warn(`Missed ${data.missed} messages, reloading...`);
             loadMessages();
@@ -247,6 +262,83 @@ function updateMessageBookmark(summary) {
     renderMessageList();
 }
 
+function updateMessageReplays(summary) {
+    const listMsg = messages.find(m => m.id === summary.id);
+    if (listMsg) listMsg.replay_count = summary.replay_count;
+
+    // The summary carries only the count — refetch the full record for the open detail view
+    if (selectedMessage && selectedMessage.id === summary.id) {
+        selectMessage(summary.id);
+    }
+}
+
+function updateMessageAckLink(summary) {
+    for (const m of [...messages, ...pendingMessages]) {
+        if (m.id === summary.id) m.ack_link = summary.ack_link;
+    }
+    // Refetch so the detail view picks up the linked counterpart
+    if (selectedMessage && selectedMessage.id === summary.id) {
+        selectMessage(summary.id);
+    }
+    renderMessageList();
+}
+
+// Mirrors Hl7Message::awaiting_application_ack on the server
+function awaitingApplicationAck(m) {
+    if (m.parse_error || (m.message_type || '').startsWith('ACK')) return false;
+    return !(m.ack_link && ['AA', 'AE', 'AR'].includes(m.ack_link.ack_code));
+}
+
+// Components column: one line per repetition, components split by ^ and
+// subcomponents by &, each part labelled with its position (e.g. PID-3[2].4.1)
+// and, when the dictionary knows the data type, its name (e.g. "Given Name")
+function renderFieldStructure(segName, f) {
+    const reps = f.repetitions || [];
+    const nested = reps.length > 1 || reps.some(r => r.length > 1 || r.some(c => c.length > 1));
+    if (!nested) return '';
+    const names = f.component_descriptions || [];
+    const sep = (ch) => ` <span class="field-sep">${esc(ch)}</span> `;
+    const part = (label, desc, datatype, value) => desc
+        ? `<span class="has-comp-tooltip" data-desc="${escAttr(label + ': ' + desc + (datatype ? ' (' + datatype + ')' : ''))}">${esc(value)}</span>`
+        : `<span title="${escAttr(label)}">${esc(value)}</span>`;
+    return reps.map((rep, r) => {
+        const base = segName + '-' + f.index + (reps.length > 1 ? `[${r + 1}]` : '');
+        const comps = rep.map((comp, c) => {
+            const name = names[c];
+            const label = base + '.' + (c + 1);
+            if (comp.length <= 1) {
+                return part(label, name && name.description, name && name.datatype, comp[0] || '');
+            }
+            const subs = (name && name.subcomponents) || [];
+            return comp.map((sub, sIdx) =>
+                part(label + '.' + (sIdx + 1), subs[sIdx], null, sub)
+            ).join(sep('&'));
+        }).join(sep('^'));
+        return reps.length > 1
+            ? `<div class="field-repetition"><span class="field-rep-idx">${r + 1}</span>${comps}</div>`
+            : comps;
+    }).join('');
+}
+
+// Drop rows removed by a server-side retention sweep
+function removeMessages(ids) {
+    const removed = new Set(ids);
+    messages = messages.filter(m => !removed.has(m.id));
+    pendingMessages = pendingMessages.filter(m => !removed.has(m.id));
+
+    if (selectedId && removed.has(selectedId)) {
+        selectedId = null;
+        selectedMessage = null;
+        document.getElementById('detail-content').innerHTML = '<div class="empty-state"><p>Message removed by retention policy</p></div>';
+        document.getElementById('detail-title').textContent = 'Select a message';
+        document.getElementById('detail-meta').textContent = '';
+    }
+
+    document.getElementById('stat-total').textContent =
+        messages.length + pendingMessages.length;
+    renderMessageList();
+}
+
 // Task 2: buffer incoming messages, flush at most every 250 ms
 function addMessage(summary) {
     pendingMessages.unshift(summary);
@@ -303,6 +395,10 @@ async function pollStats() {
         if (!resp.ok) return;
         const stats = await resp.json();
         document.getElementById('stat-total').textContent = stats.total_messages;
+        if (stats.duplicates) {
+            document.getElementById('stat-total').parentElement.title =
+                `Duplicates: ${stats.duplicates.count} (${(stats.duplicates.rate * 100).toFixed(1)}%)`;
+        }
         document.getElementById('stat-connections').textContent =
             `${stats.active_connections} / ${stats.max_connections}`;
         document.getElementById('stat-errors').textContent = stats.parse_errors;
@@ -315,6 +411,20 @@ async function pollStats() {
                 rejectedEl.parentElement.style.display = 'none';
             }
         }
+        const memoryEl = document.getElementById('stat-memory');
+        if (memoryEl && stats.memory) {
+            const mb = (bytes) => (bytes / 1024 / 1024).toFixed(1);
+            const m = stats.memory;
+            if (m.budget_bytes == null) {
+                // SQLite backend: messages live on disk, bounded by max_messages only
+                memoryEl.textContent = 'on disk';
+                memoryEl.parentElement.title = 'SQLite store: no memory cap, max_messages applies';
+            } else {
+                memoryEl.textContent = `${mb(m.total_bytes)} / ${mb(m.budget_bytes)} MB`;
+                memoryEl.parentElement.title =
+                    `Raw: ${mb(m.raw_bytes)} MB, parsed: ${mb(m.parsed_bytes)} MB, indexes: ${mb(m.index_bytes)} MB`;
+            }
+        }
         if (stats.mllp_port) {
             document.getElementById('mllp-port').textContent = stats.mllp_port;
         }
@@ -331,6 +441,9 @@ function renderMessageList() {
     if (showBookmarkedOnly) {
         filtered = filtered.filter(m => m.bookmarked);
     }
+    if (showUnackedOnly) {
+        filtered = filtered.filter(awaitingApplicationAck);
+    }
     if (validationFilter === 1) { // Any warnings
         filtered = filtered.filter(m => (m.validation_warning_count || 0) > 0 || m.has_segment_errors);
     } else if (validationFilter === 2) { // Errors only
@@ -361,17 +474,10 @@ function renderMessageList() {
         row.dataset.id = msg.id;
         row.onclick = () => selectMessage(msg.id);
 
-        const time = new Date(msg.received_at);
-        const yyyy = time.getFullYear();
-        const mm = String(time.getMonth() + 1).padStart(2, '0');
-        const dd = String(time.getDate()).padStart(2, '0');
-        const hh = String(time.getHours()).padStart(2, '0');
-        const min = String(time.getMinutes()).padStart(2, '0');
-        const ss = String(time.getSeconds()).padStart(2, '0');
-        const timeStr = `${yyyy}-${mm}-${dd} ${hh}:${min}:${ss}`;
+        const timeStr = formatDateTime(msg.received_at);
         // Source color dot
         const srcColor = getSourceColor(msg.source_addr);
-        const dotHtml = `<span class="source-dot" style="background:${srcColor};box-shadow:0 0 4px ${srcColor}" title="${escAttr(msg.source_addr)}"></span>`;
+        const dotHtml = `<span class="source-dot" style="background:${srcColor};box-shadow:0 0 4px ${srcColor}" title="${escAttr(msg.listener ? `${msg.source_addr} → ${msg.listener}` : msg.source_addr)}"></span>`;
 
         // Validation badge: red if missing segments (errors), yellow for field warnings only
         const warnCount = msg.validation_warning_count || 0;
@@ -379,17 +485,20 @@ function renderMessageList() {
         const warnBadge = warnCount > 0
             ? ` <span class="${warnCls}" title="${warnCount} validation warning${warnCount > 1 ? 's' : ''}">⚠ ${warnCount}</span>`
             : '';
+        const dupBadge = msg.duplicate_of
+            ? ` <span class="duplicate-badge" title="Duplicate of ${escAttr(msg.duplicate_of)}">DUP</span>`
+            : '';
         const typeHtml = msg.parse_error
             ? `<span class="msg-type" style="color:var(--error)" title="${escAttr(msg.parse_error)}">⚠ PARSE ERROR</span>`
-            : `<span class="msg-type">${esc(msg.message_type)}${warnBadge}</span>`;
+            : `<span class="msg-type">${esc(msg.message_type)}${warnBadge}${dupBadge}</span>`;
 
         const tagsHtml = (msg.tags && msg.tags.length > 0)
             ? `<div class="msg-tags-list">` + msg.tags.map(t => `<span class="msg-tag-small">${esc(t)}</span>`).join('') + `</div>`
             : '';
 
         let ackColor = '';
-        if (msg.ack_code === 'AA') ackColor = 'color: var(--success);';
-        else if (msg.ack_code === 'AE' || msg.ack_code === 'AR') ackColor = 'color: var(--error);';
+        if (msg.ack_code === 'AA' || msg.ack_code === 'CA') ackColor = 'color: var(--success);';
+        else if (['AE', 'AR', 'CE', 'CR'].includes(msg.ack_code)) ackColor = 'color: var(--error);';
 
         const ackHtml = msg.ack_code
             ? `<span class="msg-ack" style="${ackColor}">${esc(msg.ack_code)}</span>`
@@ -428,6 +537,27 @@ function renderMessageList() {
     }
 }
 
+// Patient IDs the search query is linked to by ADT merges, from the server's
+// merge-aware `?patient_id=` lookup, so searching a prior ID also finds the
+// surviving ID's messages (and vice versa)
+let linkedPatientIds = new Set();
+
+async function refreshLinkedPatientIds(query) {
+    const q = query.trim();
+    if (!q || q.startsWith('has:')) {
+        linkedPatientIds = new Set();
+        return;
+    }
+    try {
+        const resp = await fetch(`/api/messages?patient_id=${encodeURIComponent(q)}&limit=1000`);
+        if (!resp.ok) return;
+        const results = await resp.json();
+        if (searchQuery !== query) return; // superseded by a newer query
+        linkedPatientIds = new Set(results.map(m => m.patient_id).filter(Boolean));
+        renderMessageList();
+    } catch (e) { }
+}
+
 function matchesSearch(msg, query) {
     let q = query.toLowerCase().trim();
     if (q.startsWith('has:warnings')) {
@@ -444,8 +574,10 @@ function matchesSearch(msg, query) {
         (msg.sending_facility || '').toLowerCase().includes(q) ||
         (msg.patient_name || '').toLowerCase().includes(q) ||
         (msg.patient_id || '').toLowerCase().includes(q) ||
+        linkedPatientIds.has(msg.patient_id) ||
         (msg.message_control_id || '').toLowerCase().includes(q) ||
         (msg.source_addr || '').toLowerCase().includes(q) ||
+        (msg.listener || '').toLowerCase().includes(q) ||
         (msg.tags || []).some(t => t.toLowerCase().includes(q))
     );
 }
@@ -483,7 +615,9 @@ function renderDetail() {
     }
 
     document.getElementById('detail-meta').textContent =
-        `${msg.source_addr} | ${msg.message_control_id} | v${msg.version}`;
+        `${msg.source_addr}${msg.listener ? ` → ${msg.listener}` : ''} | ${msg.message_control_id} | v${msg.version}`
+        + (msg.encoding ? ` | ${msg.encoding}` : '')
+        + (msg.tls_peer_subject ? ` | 🔒 ${msg.tls_peer_subject}` : '');
 
     const tagsContainer = document.getElementById('detail-tags');
 
@@ -497,7 +631,10 @@ function renderDetail() {
     tagsContainer.innerHTML = `
         <button class="${bookmarkBtnClass}" onclick="toggleBookmark('${msg.id}', event)" title="Toggle bookmark">${bookmarkBtnIcon} Bookmark</button>
         <button class="${pinBtnClass}" onclick="toggleDiffPin('${msg.id}')" title="Pin this message as the diff reference">${pinBtnLabel}</button>
-    ` + (msg.tags || []).map(t =>
+        <button class="detail-pin-btn" onclick="replayMessage('${msg.id}')" title="Send this message to another MLLP receiver">↻ Replay</button>
+    ` + (msg.ack_link ? renderAckLink(msg) : '') + (msg.duplicate_of
+        ? `<button class="detail-pin-btn duplicate" onclick="selectMessage('${escAttr(msg.duplicate_of)}')" title="This message repeats an earlier one">⧉ Duplicate of original</button>`
+        : '') + (msg.tags || []).map(t =>
         `<span class="msg-tag">${esc(t)} <span class="msg-tag-remove" onclick="removeTag('${msg.id}', '${escAttr(t)}')">×</span></span>`
     ).join('') + `
         <div class="msg-tag-add">
@@ -519,6 +656,15 @@ function renderDetail() {
     renderTab();
 }
 
+// Button jumping to the linked ACK (or acknowledged message) with its round-trip time
+function renderAckLink(msg) {
+    const link = msg.ack_link;
+    const isAck = (msg.message_type || '').startsWith('ACK');
+    const label = isAck ? '⇠ Acknowledges' : `⇢ ACK ${esc(link.ack_code || '?')}`;
+    const gone = msg.ack_counterpart ? '' : ' (no longer stored)';
+    return `<button class="detail-pin-btn" onclick="selectMessage('${escAttr(link.counterpart_id)}')" title="Round trip ${link.round_trip_ms} ms${gone}">${label} · ${link.round_trip_ms} ms</button>`;
+}
+
 async function toggleDiffPin(id) {
     if (diffPinnedMessage && diffPinnedMessage.id === id) {
         diffPinnedMessage = null;
@@ -606,7 +752,7 @@ function renderTab() {
                 <ul class="validation-warnings-list">
                 ${msg.validation_warnings.map(w => {
                 const badgeCls = w.code === 'MISSING_SEGMENT' ? 'validation-seg error'
-                    : w.code === 'INVALID_DATATYPE' ? 'validation-seg type'
+                    : ['INVALID_DATATYPE', 'INVALID_CODE', 'FIELD_TOO_LONG', 'TOO_MANY_REPETITIONS'].includes(w.code) ? 'validation-seg type'
                     : 'validation-seg';
                 const label = w.segment + (w.field != null ? '-' + w.field : '');
                 return `<li><span class="${badgeCls}">${esc(label)}</span> ${esc(w.message)}</li>`;
@@ -631,12 +777,9 @@ function renderTab() {
                     <tbody>
                     ${seg.fields.map(f => `
                         <tr>
-                            <td class="field-idx ${f.description ? 'has-tooltip' : ''}" ${f.description ? `data-desc="${escAttr(seg.name + '-' + f.index + ': ' + f.description)}"` : ''}>${esc(seg.name)}-${f.index}</td>
-                            <td class="field-val">${esc(f.value) || '<span class="field-empty">empty</span>'}</td>
-                            <td class="field-components">${f.components.length > 1
-                    ? f.components.map((c, i) => `<span title="${escAttr(seg.name + '-' + f.index + '.' + (i + 1))}">${esc(c)}</span>`).join(' <span style="color:var(--text-muted)">^</span> ')
-                    : ''
-                }</td>
+                            <td class="field-idx ${f.description ? 'has-tooltip' : ''}" ${f.description ? `data-desc="${escAttr(seg.name + '-' + f.index + ': ' + f.description + (f.datatype ? ' (' + f.datatype + (f.table ? ', table ' + f.table : '') + ')' : ''))}"` : ''}>${esc(seg.name)}-${f.index}</td>
+                            <td class="field-val${f.unescaped != null ? ' unescaped' : ''}"${f.unescaped != null ? ` title="${escAttr('Raw: ' + f.value)}"` : ''}>${esc(f.unescaped ?? f.value) || '<span class="field-empty">empty</span>'}</td>
+                            <td class="field-components">${renderFieldStructure(seg.name, f)}</td>
                         </tr>
                     `).join('')}
                     </tbody>
@@ -656,23 +799,85 @@ function renderTab() {
             }</div>`;
     } else if (activeTab === 'ack') {
         const ack = msg.ack_response;
-        if (!ack) {
-            content.innerHTML = `<div class="empty-state"><p>No ACK was generated for this message</p></div>`;
-        } else {
-            const lines = ack.split(/\r?\n|\r/).filter(l => l.trim());
-            content.innerHTML = `<div class="raw-view">${lines.map(line => {
-                const segName = line.substring(0, 3);
-                return `<div class="segment-line"><span style="color:var(--accent);font-weight:600">${esc(segName)}</span>${esc(line.substring(3))}</div>`;
-            }).join('')
-                }</div>`;
-        }
+        const sent = msg.acks_sent || [];
+        const ackKinds = { original: 'ACK', commit: 'Commit ACK', application: 'Application ACK' };
+        const ackHtml = sent.length
+            ? sent.map(a => `
+                <div class="replay-entry">
+                    <div class="replay-meta">${esc(formatDateTime(a.sent_at))} · ${esc(ackKinds[a.kind] || a.kind)}${a.target ? ` → ${esc(a.target)}` : ''}
+                        <span class="msg-ack">${esc(a.code)}</span>
+                        ${a.error ? `<span class="replay-error">${esc(a.error)}</span>` : ''}
+                    </div>
+                    ${renderSegmentLines(a.message)}
+                </div>`).join('')
+            : ack
+                ? renderSegmentLines(ack)
+                : `<div class="empty-state"><p>No ACK was generated for this message</p></div>`;
+        const replays = msg.replays || [];
+        const replayHtml = replays.length
+            ? `<div class="replay-list">
+                <div class="typical-segments-label">Replays (${replays.length})</div>
+                ${replays.slice().reverse().map(r => `
+                    <div class="replay-entry">
+                        <div class="replay-meta">${esc(formatDateTime(r.sent_at))} → ${esc(r.target_name ? `${r.target_name} (${r.target})` : r.target)}
+                            ${r.error
+                    ? `<span class="replay-error">${esc(r.error)}</span>`
+                    : `<span class="msg-ack">${esc(r.ack_code || '?')}</span> ${r.latency_ms} ms`}
+                        </div>
+                        ${r.ack_response ? renderSegmentLines(r.ack_response) : ''}
+                    </div>`).join('')}
+               </div>`
+            : '';
+        content.innerHTML = ackHtml + replayHtml;
     } else if (activeTab === 'json') {
         content.innerHTML = `<pre class="raw-view">${esc(JSON.stringify(msg, null, 2))}</pre>`;
     } else if (activeTab === 'diff') {
         renderDiffTab(content, msg);
+    } else if (activeTab === 'timeline') {
+        renderTimelineTab(content, msg);
     }
 }
 
+async function renderTimelineTab(container, msg) {
+    if (!msg.patient_id) {
+        container.innerHTML = '<div class="empty-state"><p>This message has no patient ID (PID-3)</p></div>';
+        return;
+    }
+    let timeline;
+    try {
+        const resp = await fetch(`/api/patients/${encodeURIComponent(msg.patient_id)}/timeline`);
+        if (!resp.ok) throw new Error(`HTTP ${resp.status}`);
+        timeline = await resp.json();
+    } catch (e) {
+        container.innerHTML = `<div class="empty-state"><p>Failed to load timeline: ${esc(e.message)}</p></div>`;
+        return;
+    }
+    // The user may have moved on while the request was in flight
+    if (activeTab !== 'timeline' || !selectedMessage || selectedMessage.id !== msg.id) return;
+
+    const header = `<div class="typical-segments-label">
+        ${esc(timeline.patient_name || timeline.patient_id)} — ${timeline.events.length} messages,
+        state: ${esc(timeline.state.replace('_', ' '))}${timeline.merged_ids.length ? `, merged with ${esc(timeline.merged_ids.join(', '))}` : ''}${timeline.flag_count ? `, <span style="color:var(--error)">${timeline.flag_count} flagged</span>` : ''}
+    </div>`;
+    const rows = timeline.events.map(e => `
+        <div class="timeline-event${e.id === msg.id ? ' current' : ''}${e.flag ? ' flagged' : ''}" onclick="selectMessage('${escAttr(e.id)}')">
+            <span class="msg-time">${esc(formatDateTime(e.received_at))}</span>
+            <span class="timeline-category ${esc(e.category)}">${esc(e.category.toUpperCase())}</span>
+            <span class="msg-type">${esc(e.message_type)}</span>
+            <span class="timeline-desc">${esc(e.message_type_description || '')}</span>
+            ${e.flag ? `<span class="validation-badge error" title="${escAttr(e.flag.code)}">⚠ ${esc(e.flag.message)}</span>` : ''}
+        </div>`).join('');
+    container.innerHTML = `<div class="timeline">${header}${rows}</div>`;
+}
+
+function renderSegmentLines(text) {
+    const lines = text.split(/\r?\n|\r/).filter(l => l.trim());
+    return `<div class="raw-view">${lines.map(line => {
+        const segName = line.substring(0, 3);
+        return `<div class="segment-line"><span style="color:var(--accent);font-weight:600">${esc(segName)}</span>${esc(line.substring(3))}</div>`;
+    }).join('')}</div>`;
+}
+
 function renderDiffTab(container, msgB) {
     const msgA = diffPinnedMessage;
     if (!msgA) {
@@ -900,6 +1105,30 @@ async function toggleBookmark(id, event) {
     }
 }
 
+async function replayMessage(id) {
+    const input = prompt('Replay to (configured target name or host:port):', lastReplayTarget);
+    if (!input || !input.trim()) return;
+    lastReplayTarget = input.trim();
+
+    const match = lastReplayTarget.match(/^(.+):(\d+)$/);
+    const body = match
+        ? { host: match[1], port: parseInt(match[2], 10) }
+        : { target: lastReplayTarget };
+    try {
+        const resp = await fetch(`/api/messages/${id}/replay`, {
+            method: 'POST',
+            headers: { 'Content-Type': 'application/json' },
+            body: JSON.stringify(body)
+        });
+        if (resp.status === 400) throw new Error(await resp.text());
+        const record = await resp.json();
+        if (record.error) throw new Error(`Replay failed: ${record.error}`);
+        showToast(`Replay ACK ${record.ack_code || '?'} in ${record.latency_ms} ms`, 'success');
+    } catch (e) {
+        showToast(e.message);
+    }
+}
+
 function toggleBookmarkFilter() {
     showBookmarkedOnly = !showBookmarkedOnly;
     const btn = document.getElementById('btn-bookmarks');
@@ -916,6 +1145,19 @@ function toggleBookmarkFilter() {
     saveSession();
 }
 
+function toggleUnackedFilter() {
+    showUnackedOnly = !showUnackedOnly;
+    syncUnackedFilterUI();
+    renderMessageList();
+    saveSession();
+}
+
+function syncUnackedFilterUI() {
+    const btn = document.getElementById('btn-unacked');
+    btn.style.borderColor = showUnackedOnly ? 'var(--warning)' : '';
+    btn.style.color = showUnackedOnly ? 'var(--warning)' : '';
+}
+
 function syncValidationFilterUI() {
     const btn = document.getElementById('btn-validation');
     if (!btn) return;
@@ -950,6 +1192,17 @@ function showToast(message, type = 'error') {
     setTimeout(() => toast.remove(), 4000);
 }
 
+function formatDateTime(value) {
+    const time = new Date(value);
+    const yyyy = time.getFullYear();
+    const mm = String(time.getMonth() + 1).padStart(2, '0');
+    const dd = String(time.getDate()).padStart(2, '0');
+    const hh = String(time.getHours()).padStart(2, '0');
+    const min = String(time.getMinutes()).padStart(2, '0');
+    const ss = String(time.getSeconds()).padStart(2, '0');
+    return `${yyyy}-${mm}-${dd} ${hh}:${min}:${ss}`;
+}
+
 function esc(str) {
     if (!str) return '';
     const div = document.createElement('div');
@@ -1067,17 +1320,18 @@ function initSplitter() {
 // Restore session state BEFORE first render so restored values take effect
 loadSession();
 
-// Search is purely client-side (filters the local `messages` array via matchesSearch).
-// The debounce is a forward-looking safeguard: if a future /api/search call is added,
-// rapid keystrokes would otherwise hammer the server and cause RwLock contention on the
-// Rust side. Local renderMessageList() remains immediately reactive inside the handler.
+// Search filters the local `messages` array via matchesSearch; the only server call is
+// the merge-aware patient ID lookup. The debounce keeps rapid keystrokes from hammering
+// the server and causing RwLock contention on the Rust side.
 let _searchDebounceTimer = null;
 document.getElementById('search-input').addEventListener('input', (e) => {
     clearTimeout(_searchDebounceTimer);
     _searchDebounceTimer = setTimeout(() => {
         searchQuery = e.target.value;
+        linkedPatientIds = new Set();
         renderMessageList();
         saveSession();
+        refreshLinkedPatientIds(searchQuery);
     }, 300);
 });
 
@@ -1093,7 +1347,10 @@ document.addEventListener('click', (e) => {
 
 // Apply restored session state to UI elements
 (function applyRestoredSession() {
-    if (searchQuery) document.getElementById('search-input').value = searchQuery;
+    if (searchQuery) {
+        document.getElementById('search-input').value = searchQuery;
+        refreshLinkedPatientIds(searchQuery);
+    }
 
     if (activeTab !== 'parsed') {
         document.querySelectorAll('.detail-tab').forEach(t => {
@@ -1115,6 +1372,7 @@ document.addEventListener('click', (e) => {
         btn.style.color = 'var(--warning)';
     }
 
+    syncUnackedFilterUI();
     syncValidationFilterUI();
 })();
 